@@ -1,6 +1,14 @@
-use crate::c::util::{cbytes_to_rust, rust_to_cbytes, ngenrs_free_ptr, box_into_raw_new};
-use crate::core::crypto::{Aes256EcbPkcs5, rsa_enc, rsa_dec, hash_md5, hash_sha1, hash_sha256, base64_encode, base64_decode};
-use std::os::raw::c_void;
+use crate::c::util::{cbytes_to_rust, rust_to_cbytes, rust_to_cstr, ngenrs_free_ptr, box_into_raw_new};
+use crate::c::error::{set_last_error, clear_last_error, set_invalid_argument};
+use crate::core::error::{ErrorCode, NGenError};
+use crate::core::crypto::{
+    Aes256EcbPkcs5, AesCbcPkcs5, AesGcm, RsaKeyFormat, RsaSignScheme,
+    rsa_enc, rsa_dec, rsa_generate, rsa_sign, rsa_verify,
+    rsa_import_private_key, rsa_import_public_key, rsa_export_private_key, rsa_export_public_key,
+    hash_md5, hash_sha1, hash_sha256, base64_encode, base64_decode
+};
+use std::error::Error;
+use std::os::raw::{c_char, c_void};
 
 unsafe fn common_crypto_process<F>(
     data: *const u8,
@@ -9,32 +17,62 @@ unsafe fn common_crypto_process<F>(
     op_fn: F
 ) -> *mut u8
 where
-    F: FnOnce(&[u8]) -> Vec<u8>
+    F: FnOnce(&[u8]) -> Result<Vec<u8>, Box<dyn Error>>
 {
     let data_bytes = match cbytes_to_rust(data, data_len) {
         Some(bytes) => bytes,
-        None => return std::ptr::null_mut(),
+        None => {
+            set_invalid_argument("data is null");
+            return std::ptr::null_mut();
+        }
     };
-    let out_bytes = op_fn(data_bytes);
-    let (ptr, len) = rust_to_cbytes(out_bytes);
-    unsafe { *out_len = len };
-    ptr
+    crypto_result_to_c(op_fn(data_bytes), out_len, std::ptr::null_mut())
+}
+
+fn crypto_result_to_c(
+    result: Result<Vec<u8>, Box<dyn Error>>,
+    out_len: *mut usize,
+    err_out: *mut *mut c_char,
+) -> *mut u8 {
+    match result {
+        Ok(bytes) => {
+            let (ptr, len) = rust_to_cbytes(bytes);
+            unsafe { *out_len = len };
+            ptr
+        }
+        Err(e) => {
+            if !err_out.is_null() {
+                unsafe { *err_out = rust_to_cstr(e.to_string()) };
+            }
+            set_last_error(NGenError::from_boxed(e, ErrorCode::Crypto));
+            std::ptr::null_mut()
+        }
+    }
+}
+
+fn cipher_init_to_c<T>(cipher: Result<T, Box<dyn Error>>) -> *mut c_void {
+    match cipher {
+        Ok(cipher) => box_into_raw_new(cipher) as *mut c_void,
+        Err(e) => {
+            set_last_error(NGenError::from_boxed(e, ErrorCode::Crypto));
+            std::ptr::null_mut()
+        }
+    }
 }
 
 #[unsafe(no_mangle)]
 pub extern "C"
 fn ngenrs_crypto_aes256_ecb_pkcs5_init(key: *const u8, key_len: usize) -> *mut c_void {
+    clear_last_error();
     if key.is_null() || key_len != 32 {
+        set_invalid_argument("key must be 32 bytes");
         return std::ptr::null_mut();
     }
     let key_bytes = match { cbytes_to_rust(key, key_len) } {
         Some(bytes) => bytes,
         None => return std::ptr::null_mut(),
     };
-    match Aes256EcbPkcs5::new(key_bytes) {
-        Ok(cipher) => box_into_raw_new(cipher) as *mut c_void,
-        Err(_) => std::ptr::null_mut(),
-    }
+    cipher_init_to_c(Aes256EcbPkcs5::new(key_bytes))
 }
 
 #[unsafe(no_mangle)]
@@ -45,13 +83,15 @@ fn ngenrs_crypto_aes256_ecb_pkcs5_encrypt(
     data_len: usize,
     out_len: *mut usize,
 ) -> *mut u8 {
+    clear_last_error();
     if cipher.is_null() {
+        set_invalid_argument("cipher is null");
         return std::ptr::null_mut();
     }
     let cipher_ref = unsafe { &*(cipher as *mut Aes256EcbPkcs5) };
     unsafe { 
         common_crypto_process(data, data_len, out_len, |bytes| {
-            cipher_ref.enc(bytes)
+            Ok(cipher_ref.enc(bytes))
         })
     }
 }
@@ -64,13 +104,15 @@ fn ngenrs_crypto_aes256_ecb_pkcs5_decrypt(
     data_len: usize,
     out_len: *mut usize,
 ) -> *mut u8 {
+    clear_last_error();
     if cipher.is_null() {
+        set_invalid_argument("cipher is null");
         return std::ptr::null_mut();
     }
     let cipher_ref = unsafe { &*cipher };
     unsafe {
         common_crypto_process(data, data_len, out_len, |bytes| {
-            cipher_ref.dec(bytes).unwrap_or_default()
+            cipher_ref.dec(bytes)
         })
     }
 }
@@ -81,6 +123,164 @@ fn ngenrs_crypto_aes256_ecb_pkcs5_release(cipher: *mut c_void) {
     ngenrs_free_ptr(cipher);
 }
 
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_crypto_aes_cbc_pkcs5_init(key: *const u8, key_len: usize) -> *mut c_void {
+    clear_last_error();
+    if key.is_null() || !matches!(key_len, 16 | 32) {
+        set_invalid_argument("key must be 16 or 32 bytes");
+        return std::ptr::null_mut();
+    }
+    let key_bytes = match cbytes_to_rust(key, key_len) {
+        Some(bytes) => bytes,
+        None => {
+            set_invalid_argument("key is null");
+            return std::ptr::null_mut();
+        }
+    };
+    cipher_init_to_c(AesCbcPkcs5::new(key_bytes))
+}
+
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_crypto_aes_cbc_pkcs5_encrypt(
+    cipher: *mut c_void,
+    iv: *const u8,
+    iv_len: usize,
+    data: *const u8,
+    data_len: usize,
+    out_len: *mut usize,
+) -> *mut u8 {
+    clear_last_error();
+    if cipher.is_null() {
+        set_invalid_argument("cipher is null");
+        return std::ptr::null_mut();
+    }
+    let cipher_ref = unsafe { &*(cipher as *mut AesCbcPkcs5) };
+    let iv_bytes = match cbytes_to_rust(iv, iv_len) {
+        Some(bytes) => bytes,
+        None => {
+            set_invalid_argument("iv is null");
+            return std::ptr::null_mut();
+        }
+    };
+    unsafe {
+        common_crypto_process(data, data_len, out_len, |bytes| {
+            cipher_ref.enc(iv_bytes, bytes)
+        })
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_crypto_aes_cbc_pkcs5_decrypt(
+    cipher: *mut c_void,
+    iv: *const u8,
+    iv_len: usize,
+    data: *const u8,
+    data_len: usize,
+    out_len: *mut usize,
+) -> *mut u8 {
+    clear_last_error();
+    if cipher.is_null() {
+        set_invalid_argument("cipher is null");
+        return std::ptr::null_mut();
+    }
+    let cipher_ref = unsafe { &*(cipher as *mut AesCbcPkcs5) };
+    let iv_bytes = match cbytes_to_rust(iv, iv_len) {
+        Some(bytes) => bytes,
+        None => {
+            set_invalid_argument("iv is null");
+            return std::ptr::null_mut();
+        }
+    };
+    unsafe {
+        common_crypto_process(data, data_len, out_len, |bytes| {
+            cipher_ref.dec(iv_bytes, bytes)
+        })
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_crypto_aes_cbc_pkcs5_release(cipher: *mut c_void) {
+    ngenrs_free_ptr(cipher as *mut AesCbcPkcs5);
+}
+
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_crypto_aes_gcm_init(key: *const u8, key_len: usize) -> *mut c_void {
+    clear_last_error();
+    if key.is_null() || !matches!(key_len, 16 | 32) {
+        set_invalid_argument("key must be 16 or 32 bytes");
+        return std::ptr::null_mut();
+    }
+    let key_bytes = match cbytes_to_rust(key, key_len) {
+        Some(bytes) => bytes,
+        None => {
+            set_invalid_argument("key is null");
+            return std::ptr::null_mut();
+        }
+    };
+    cipher_init_to_c(AesGcm::new(key_bytes))
+}
+
+/// Output is `nonce(12) || ciphertext || tag(16)`; `aad` may be null.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_crypto_aes_gcm_encrypt(
+    cipher: *mut c_void,
+    data: *const u8,
+    data_len: usize,
+    aad: *const u8,
+    aad_len: usize,
+    out_len: *mut usize,
+) -> *mut u8 {
+    clear_last_error();
+    if cipher.is_null() {
+        set_invalid_argument("cipher is null");
+        return std::ptr::null_mut();
+    }
+    let cipher_ref = unsafe { &*(cipher as *mut AesGcm) };
+    let aad_bytes = cbytes_to_rust(aad, aad_len).unwrap_or_default();
+    unsafe {
+        common_crypto_process(data, data_len, out_len, |bytes| {
+            cipher_ref.enc(bytes, aad_bytes)
+        })
+    }
+}
+
+/// Expects the layout produced by `ngenrs_crypto_aes_gcm_encrypt`; `aad` may be null.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_crypto_aes_gcm_decrypt(
+    cipher: *mut c_void,
+    data: *const u8,
+    data_len: usize,
+    aad: *const u8,
+    aad_len: usize,
+    out_len: *mut usize,
+) -> *mut u8 {
+    clear_last_error();
+    if cipher.is_null() {
+        set_invalid_argument("cipher is null");
+        return std::ptr::null_mut();
+    }
+    let cipher_ref = unsafe { &*(cipher as *mut AesGcm) };
+    let aad_bytes = cbytes_to_rust(aad, aad_len).unwrap_or_default();
+    unsafe {
+        common_crypto_process(data, data_len, out_len, |bytes| {
+            cipher_ref.dec(bytes, aad_bytes)
+        })
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_crypto_aes_gcm_release(cipher: *mut c_void) {
+    ngenrs_free_ptr(cipher as *mut AesGcm);
+}
+
 #[unsafe(no_mangle)]
 pub extern "C" 
 fn ngenrs_crypto_rsa_encrypt(
@@ -91,7 +291,9 @@ fn ngenrs_crypto_rsa_encrypt(
     padding: i32,
     out_len: *mut usize,
 ) -> *mut u8 {
+    clear_last_error();
     if input.is_null() || pub_key.is_null() {
+        set_invalid_argument("input or key is null");
         return std::ptr::null_mut();
     }
 
@@ -105,10 +307,7 @@ fn ngenrs_crypto_rsa_encrypt(
         None => return std::ptr::null_mut(),
     };
 
-    let result = rsa_enc(input_bytes, pub_key_bytes, padding);
-    let (ptr, len) = rust_to_cbytes(result);
-    unsafe { *out_len = len };
-    ptr
+    crypto_result_to_c(rsa_enc(input_bytes, pub_key_bytes, padding), out_len, std::ptr::null_mut())
 }
 
 #[unsafe(no_mangle)]
@@ -121,7 +320,9 @@ fn ngenrs_crypto_rsa_decrypt(
     padding: i32,
     out_len: *mut usize,
 ) -> *mut u8 {
+    clear_last_error();
     if input.is_null() || priv_key.is_null() {
+        set_invalid_argument("input or key is null");
         return std::ptr::null_mut();
     }
 
@@ -135,39 +336,221 @@ fn ngenrs_crypto_rsa_decrypt(
         None => return std::ptr::null_mut(),
     };
 
-    let result = rsa_dec(input_bytes, priv_key_bytes, padding);
-    let (ptr, len) = rust_to_cbytes(result);
-    unsafe { *out_len = len };
-    ptr
+    crypto_result_to_c(rsa_dec(input_bytes, priv_key_bytes, padding), out_len, std::ptr::null_mut())
+}
+
+/// Generates an RSA keypair; both keys are returned encoded in `format`
+/// and must be released with `ngenrs_free_bytes`.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_crypto_rsa_generate(
+    bits: usize,
+    format: i32,
+    priv_out: *mut *mut u8,
+    priv_len_out: *mut usize,
+    pub_out: *mut *mut u8,
+    pub_len_out: *mut usize,
+    err_out: *mut *mut c_char,
+) -> bool {
+    clear_last_error();
+    if priv_out.is_null() || priv_len_out.is_null() || pub_out.is_null() || pub_len_out.is_null() {
+        set_invalid_argument("output pointer is null");
+        return false;
+    }
+
+    let generated = RsaKeyFormat::try_from(format)
+        .map_err(Into::into)
+        .and_then(|format| rsa_generate(bits, format));
+    match generated {
+        Ok((priv_key, pub_key)) => {
+            let (priv_ptr, priv_len) = rust_to_cbytes(priv_key);
+            let (pub_ptr, pub_len) = rust_to_cbytes(pub_key);
+            unsafe {
+                *priv_out = priv_ptr;
+                *priv_len_out = priv_len;
+                *pub_out = pub_ptr;
+                *pub_len_out = pub_len;
+            }
+            true
+        }
+        Err(e) => {
+            if !err_out.is_null() {
+                unsafe { *err_out = rust_to_cstr(e.to_string()) };
+            }
+            set_last_error(NGenError::from_boxed(e, ErrorCode::Crypto));
+            false
+        }
+    }
+}
+
+/// Re-encodes a private key (any supported format) into `format`.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_crypto_rsa_convert_private_key(
+    key: *const u8,
+    key_len: usize,
+    format: i32,
+    out_len: *mut usize,
+    err_out: *mut *mut c_char,
+) -> *mut u8 {
+    clear_last_error();
+    let key_bytes = match cbytes_to_rust(key, key_len) {
+        Some(bytes) => bytes,
+        None => {
+            set_invalid_argument("key is null");
+            return std::ptr::null_mut();
+        }
+    };
+    let result = RsaKeyFormat::try_from(format)
+        .map_err(Into::into)
+        .and_then(|format| rsa_export_private_key(&rsa_import_private_key(key_bytes)?, format));
+    crypto_result_to_c(result, out_len, err_out)
+}
+
+/// Re-encodes a public key (any supported format) into `format`.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_crypto_rsa_convert_public_key(
+    key: *const u8,
+    key_len: usize,
+    format: i32,
+    out_len: *mut usize,
+    err_out: *mut *mut c_char,
+) -> *mut u8 {
+    clear_last_error();
+    let key_bytes = match cbytes_to_rust(key, key_len) {
+        Some(bytes) => bytes,
+        None => {
+            set_invalid_argument("key is null");
+            return std::ptr::null_mut();
+        }
+    };
+    let result = RsaKeyFormat::try_from(format)
+        .map_err(Into::into)
+        .and_then(|format| rsa_export_public_key(&rsa_import_public_key(key_bytes)?, format));
+    crypto_result_to_c(result, out_len, err_out)
+}
+
+/// Extracts the public half of a private key, encoded in `format`.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_crypto_rsa_public_key_from_private(
+    priv_key: *const u8,
+    priv_key_len: usize,
+    format: i32,
+    out_len: *mut usize,
+    err_out: *mut *mut c_char,
+) -> *mut u8 {
+    clear_last_error();
+    let key_bytes = match cbytes_to_rust(priv_key, priv_key_len) {
+        Some(bytes) => bytes,
+        None => {
+            set_invalid_argument("key is null");
+            return std::ptr::null_mut();
+        }
+    };
+    let result = RsaKeyFormat::try_from(format)
+        .map_err(Into::into)
+        .and_then(|format| rsa_export_public_key(&rsa_import_private_key(key_bytes)?.to_public_key(), format));
+    crypto_result_to_c(result, out_len, err_out)
+}
+
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_crypto_rsa_sign(
+    data: *const u8,
+    data_len: usize,
+    priv_key: *const u8,
+    priv_key_len: usize,
+    scheme: i32,
+    out_len: *mut usize,
+    err_out: *mut *mut c_char,
+) -> *mut u8 {
+    clear_last_error();
+    let (data_bytes, key_bytes) = match (cbytes_to_rust(data, data_len), cbytes_to_rust(priv_key, priv_key_len)) {
+        (Some(d), Some(k)) => (d, k),
+        _ => {
+            set_invalid_argument("data or key is null");
+            return std::ptr::null_mut();
+        }
+    };
+    let result = RsaSignScheme::try_from(scheme)
+        .map_err(Into::into)
+        .and_then(|scheme| rsa_sign(data_bytes, key_bytes, scheme));
+    crypto_result_to_c(result, out_len, err_out)
+}
+
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_crypto_rsa_verify(
+    data: *const u8,
+    data_len: usize,
+    sig: *const u8,
+    sig_len: usize,
+    pub_key: *const u8,
+    pub_key_len: usize,
+    scheme: i32,
+    err_out: *mut *mut c_char,
+) -> bool {
+    clear_last_error();
+    let (data_bytes, sig_bytes, key_bytes) = match (
+        cbytes_to_rust(data, data_len),
+        cbytes_to_rust(sig, sig_len),
+        cbytes_to_rust(pub_key, pub_key_len),
+    ) {
+        (Some(d), Some(s), Some(k)) => (d, s, k),
+        _ => {
+            set_invalid_argument("data, signature or key is null");
+            return false;
+        }
+    };
+    let verified = RsaSignScheme::try_from(scheme)
+        .map_err(Into::into)
+        .and_then(|scheme| rsa_verify(data_bytes, sig_bytes, key_bytes, scheme));
+    match verified {
+        Ok(_) => true,
+        Err(e) => {
+            if !err_out.is_null() {
+                unsafe { *err_out = rust_to_cstr(e.to_string()) };
+            }
+            set_last_error(NGenError::from_boxed(e, ErrorCode::Crypto));
+            false
+        }
+    }
 }
 
 #[unsafe(no_mangle)]
 pub extern "C" 
 fn ngenrs_crypto_hash_md5(data: *const u8, data_len: usize, out_len: *mut usize) -> *mut u8 {
-    unsafe { common_crypto_process(data, data_len, out_len, hash_md5) }
+    clear_last_error();
+    unsafe { common_crypto_process(data, data_len, out_len, |b| Ok(hash_md5(b))) }
 }
 
 #[unsafe(no_mangle)]
 pub extern "C"
 fn ngenrs_crypto_hash_sha1(data: *const u8, data_len: usize, out_len: *mut usize) -> *mut u8 {
-    unsafe { common_crypto_process(data, data_len, out_len, hash_sha1) }
+    clear_last_error();
+    unsafe { common_crypto_process(data, data_len, out_len, |b| Ok(hash_sha1(b))) }
 }
 
 #[unsafe(no_mangle)]
 pub extern "C"
 fn ngenrs_crypto_hash_sha256(data: *const u8, data_len: usize, out_len: *mut usize) -> *mut u8 {
-    unsafe { common_crypto_process(data, data_len, out_len, hash_sha256) }
+    clear_last_error();
+    unsafe { common_crypto_process(data, data_len, out_len, |b| Ok(hash_sha256(b))) }
 }
 
 #[unsafe(no_mangle)]
 pub extern "C"
 fn ngenrs_crypto_base64_encode(data: *const u8, data_len: usize, out_len: *mut usize) -> *mut u8 {
-    unsafe { common_crypto_process(data, data_len, out_len, base64_encode) }
+    clear_last_error();
+    unsafe { common_crypto_process(data, data_len, out_len, |b| Ok(base64_encode(b))) }
 }
 
 #[unsafe(no_mangle)]
 pub extern "C"
 fn ngenrs_crypto_base64_decode(data: *const u8, data_len: usize, out_len: *mut usize) -> *mut u8 {
-    unsafe { common_crypto_process(data, data_len, out_len, base64_decode) }
+    clear_last_error();
+    unsafe { common_crypto_process(data, data_len, out_len, |b| Ok(base64_decode(b))) }
 }
 
@@ -1,65 +1,96 @@
 use crate::core::db::{DB, QueryResult};
 use crate::c::util::{cstr_to_rust, rust_to_cstr, ngenrs_free_ptr, box_into_raw_new};
+use crate::c::error::{set_last_error, clear_last_error, set_invalid_argument};
+use crate::core::error::NGenError;
 use std::ffi::{c_void, c_char};
 use std::ptr;
 
 #[unsafe(no_mangle)]
 pub extern "C" 
 fn ngenrs_db_open(path: *const c_char) -> *mut c_void {
+    clear_last_error();
     if path.is_null() {
+        set_invalid_argument("null argument");
         return ptr::null_mut();
     }
 
     let path_str = match { cstr_to_rust(path) } {
         Some(s) => s,
-        None => return ptr::null_mut(),
+        None => {
+            set_invalid_argument("invalid UTF-8 string");
+            return ptr::null_mut();
+        }
     };
 
     match DB::open(&path_str) {
         Ok(db) => { box_into_raw_new(db) as *mut c_void },
-        Err(_) => ptr::null_mut(),
+        Err(e) => {
+            set_last_error(e);
+            ptr::null_mut()
+        }
     }
 }
 
 #[unsafe(no_mangle)]
 pub extern "C" 
 fn ngenrs_db_exec(db: *mut c_void, sql: *const c_char) -> bool {
+    clear_last_error();
     if db.is_null() || sql.is_null() {
+        set_invalid_argument("null argument");
         return false;
     }
 
     let db = unsafe { &*(db as *mut DB) };
     let sql_str = match { cstr_to_rust(sql) } {
         Some(s) => s,
-        None => return false,
+        None => {
+            set_invalid_argument("invalid UTF-8 string");
+            return false;
+        }
     };
 
-    db.exec(&sql_str).is_ok()
+    match db.exec(&sql_str) {
+        Ok(_) => true,
+        Err(e) => {
+            set_last_error(e);
+            false
+        }
+    }
 }
 
 #[unsafe(no_mangle)]
 pub extern "C" 
 fn ngenrs_db_query(db: *mut c_void, sql: *const c_char) -> *mut c_void {
+    clear_last_error();
     if db.is_null() || sql.is_null() {
+        set_invalid_argument("null argument");
         return ptr::null_mut();
     }
 
     let db = unsafe { &mut *(db as *mut DB) };
     let sql_str = match { cstr_to_rust(sql) } {
         Some(s) => s,
-        None => return ptr::null_mut(),
+        None => {
+            set_invalid_argument("invalid UTF-8 string");
+            return ptr::null_mut();
+        }
     };
 
     match db.query(&sql_str) {
         Ok(result) => { box_into_raw_new(result) as *mut c_void },
-        Err(_) => ptr::null_mut(),
+        Err(e) => {
+            set_last_error(e);
+            ptr::null_mut()
+        }
     }
 }
 
 #[unsafe(no_mangle)]
 pub extern "C" 
 fn ngenrs_db_next_row(result: *mut c_void) -> bool {
+    clear_last_error();
     if result.is_null() {
+        set_invalid_argument("null argument");
         return false;
     }
 
@@ -70,56 +101,82 @@ fn ngenrs_db_next_row(result: *mut c_void) -> bool {
 #[unsafe(no_mangle)]
 pub extern "C" 
 fn ngenrs_db_get_string(result: *mut c_void, column: *const c_char) -> *mut c_char {
+    clear_last_error();
     if result.is_null() || column.is_null() {
+        set_invalid_argument("null argument");
         return ptr::null_mut();
     }
 
     let result = unsafe { &mut *(result as *mut QueryResult) };
     let column_str = match { cstr_to_rust(column) } {
         Some(s) => s,
-        None => return ptr::null_mut(),
+        None => {
+            set_invalid_argument("invalid UTF-8 string");
+            return ptr::null_mut();
+        }
     };
 
     match result.next_row().and_then(|row| row.get_string(&column_str)) {
-        Some(s) => { rust_to_cstr(s) },
-        None => ptr::null_mut(),
+        Some(s) => rust_to_cstr(s),
+        None => {
+            set_last_error(NGenError::not_found(format!("no string value for column {}", column_str)));
+            ptr::null_mut()
+        }
     }
 }
 
 #[unsafe(no_mangle)]
 pub extern "C" 
 fn ngenrs_db_get_i64(result: *mut c_void, column: *const c_char) -> i64 {
+    clear_last_error();
     if result.is_null() || column.is_null() {
+        set_invalid_argument("null argument");
         return 0;
     }
 
     let result = unsafe { &mut *(result as *mut QueryResult) };
     let column_str = match { cstr_to_rust(column) } {
         Some(s) => s,
-        None => return 0,
+        None => {
+            set_invalid_argument("invalid UTF-8 string");
+            return 0;
+        }
     };
 
-    result.next_row()
-        .and_then(|row| row.get_i64(&column_str))
-        .unwrap_or(0)
+    match result.next_row().and_then(|row| row.get_i64(&column_str)) {
+        Some(v) => v,
+        None => {
+            set_last_error(NGenError::not_found(format!("no i64 value for column {}", column_str)));
+            0
+        }
+    }
 }
 
 #[unsafe(no_mangle)]
 pub extern "C" 
 fn ngenrs_db_get_f64(result: *mut c_void, column: *const c_char) -> f64 {
+    clear_last_error();
     if result.is_null() || column.is_null() {
+        set_invalid_argument("null argument");
         return 0.0;
     }
 
     let result = unsafe { &mut *(result as *mut QueryResult) };
     let column_str = match { cstr_to_rust(column) } {
         Some(s) => s,
-        None => return 0.0,
+        None => {
+            set_invalid_argument("invalid UTF-8 string");
+            return 0.0;
+        }
     };
 
-    result.next_row()
-        .and_then(|row| row.get_f64(&column_str))
-        .unwrap_or(0.0)
+    match result.next_row().and_then(|row| row.get_f64(&column_str)) {
+        Some(v) => v,
+        None => {
+            set_last_error(NGenError::not_found(format!("no f64 value for column {}", column_str)));
+            0.0
+        }
+    }
 }
 
 #[unsafe(no_mangle)]
@@ -0,0 +1,52 @@
+use std::cell::RefCell;
+use std::os::raw::c_char;
+use crate::c::util::rust_to_cstr;
+use crate::core::error::{ErrorCode, NGenError};
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<NGenError>> = const { RefCell::new(None) };
+}
+
+/// Records the error for the calling thread, replacing any previous one
+pub fn set_last_error(err: impl Into<NGenError>) {
+    let err = err.into();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(err));
+}
+
+/// Resets the calling thread's error state to `ErrorCode::Ok`
+pub fn clear_last_error() {
+    LAST_ERROR.with(|last| *last.borrow_mut() = None);
+}
+
+/// Records an `InvalidArgument` error; used for null or malformed C inputs
+pub fn set_invalid_argument(message: &str) {
+    set_last_error(NGenError::invalid_argument(message));
+}
+
+/// Code of the last error raised on this thread, `0` if none.
+/// Every fallible `ngenrs_*` function resets it on entry, so it always
+/// describes the most recent call; release functions leave it untouched.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_last_error_code() -> i32 {
+    LAST_ERROR.with(|last| {
+        last.borrow().as_ref().map_or(ErrorCode::Ok, |e| e.code) as i32
+    })
+}
+
+/// Message of the last error raised on this thread, or null if none.
+/// The returned string must be released with `ngenrs_free_cstr`.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_last_error_message() -> *mut c_char {
+    LAST_ERROR.with(|last| match last.borrow().as_ref() {
+        Some(e) => rust_to_cstr(e.message.clone()),
+        None => std::ptr::null_mut(),
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_clear_last_error() {
+    clear_last_error();
+}
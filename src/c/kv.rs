@@ -1,34 +1,53 @@
 use std::os::raw::{c_char, c_void};
 use crate::c::util::{cstr_to_rust, rust_to_cstr, ngenrs_free_ptr, box_into_raw_new};
+use crate::c::error::{set_last_error, clear_last_error, set_invalid_argument};
+use crate::core::error::NGenError;
 use crate::core::kv::KV;
 
 #[unsafe(no_mangle)]
 pub extern "C" 
 fn ngenrs_kv_open(path: *const c_char) -> *mut c_void {
+    clear_last_error();
     let path_str = match cstr_to_rust(path) {
         Some(s) => s,
-        None => return std::ptr::null_mut(),
+        None => {
+            set_invalid_argument("path is null");
+            return std::ptr::null_mut();
+        }
     };
     
     match KV::open(path_str) {
         Ok(store) => box_into_raw_new(store) as *mut c_void,
-        Err(_) => std::ptr::null_mut(),
+        Err(e) => {
+            set_last_error(e);
+            std::ptr::null_mut()
+        }
     }
 }
 
 #[unsafe(no_mangle)]
 pub extern "C" 
 fn ngenrs_kv_write_int(store: *mut c_void, key: *const c_char, value: i64) -> bool {
-    if store.is_null() { return false; }
+    clear_last_error();
+    if store.is_null() {
+        set_invalid_argument("store is null");
+        return false;
+    }
     let key_str = match cstr_to_rust(key) {
         Some(s) => s,
-        None => return false,
+        None => {
+            set_invalid_argument("key is null");
+            return false;
+        }
     };
     unsafe {
         let kv_ref = &mut *(store as *mut KV);
         match kv_ref.write_int(key_str, value) {
             Ok(_) => true,
-            Err(_) => false,
+            Err(e) => {
+                set_last_error(e);
+                false
+            }
         }
     }
 }
@@ -36,16 +55,30 @@ fn ngenrs_kv_write_int(store: *mut c_void, key: *const c_char, value: i64) -> bo
 #[unsafe(no_mangle)]
 pub extern "C" 
 fn ngenrs_kv_read_int(store: *mut KV, key: *const c_char) -> i64 {
-    if store.is_null() { return 0; }
+    clear_last_error();
+    if store.is_null() {
+        set_invalid_argument("store is null");
+        return 0;
+    }
     let key_str = match cstr_to_rust(key) {
         Some(s) => s,
-        None => return 0,
+        None => {
+            set_invalid_argument("key is null");
+            return 0;
+        }
     };
     unsafe {
         let kv_ref = &mut *store;
         match kv_ref.read_int(key_str) {
-            Ok(value) => value.unwrap_or(0),
-            Err(_) => 0,
+            Ok(Some(value)) => value,
+            Ok(None) => {
+                set_last_error(NGenError::not_found(format!("key {} not found", key_str)));
+                0
+            }
+            Err(e) => {
+                set_last_error(e);
+                0
+            }
         }
     }
 }
@@ -53,16 +86,26 @@ fn ngenrs_kv_read_int(store: *mut KV, key: *const c_char) -> i64 {
 #[unsafe(no_mangle)]
 pub extern "C" 
 fn ngenrs_kv_write_float(store: *mut KV, key: *const c_char, value: f64) -> bool {
-    if store.is_null() { return false; }
+    clear_last_error();
+    if store.is_null() {
+        set_invalid_argument("store is null");
+        return false;
+    }
     let key_str = match cstr_to_rust(key) {
         Some(s) => s,
-        None => return false,
+        None => {
+            set_invalid_argument("key is null");
+            return false;
+        }
     };
     unsafe {
         let kv_ref = &mut *store;
         match kv_ref.write_float(key_str, value) {
             Ok(_) => true,
-            Err(_) => false,
+            Err(e) => {
+                set_last_error(e);
+                false
+            }
         }
     }
 }
@@ -70,16 +113,30 @@ fn ngenrs_kv_write_float(store: *mut KV, key: *const c_char, value: f64) -> bool
 #[unsafe(no_mangle)]
 pub extern "C" 
 fn ngenrs_kv_read_float(store: *mut KV, key: *const c_char) -> f64 {
-    if store.is_null() { return 0.0; }
+    clear_last_error();
+    if store.is_null() {
+        set_invalid_argument("store is null");
+        return 0.0;
+    }
     let key_str = match cstr_to_rust(key) {
         Some(s) => s,
-        None => return 0.0,
+        None => {
+            set_invalid_argument("key is null");
+            return 0.0;
+        }
     };
     unsafe {
         let kv_ref = &mut *store;
         match kv_ref.read_float(key_str) {
-            Ok(value) => value.unwrap_or(0.0),
-            Err(_) => 0.0,
+            Ok(Some(value)) => value,
+            Ok(None) => {
+                set_last_error(NGenError::not_found(format!("key {} not found", key_str)));
+                0.0
+            }
+            Err(e) => {
+                set_last_error(e);
+                0.0
+            }
         }
     }
 }
@@ -87,10 +144,17 @@ fn ngenrs_kv_read_float(store: *mut KV, key: *const c_char) -> f64 {
 #[unsafe(no_mangle)]
 pub extern "C" 
 fn ngenrs_kv_write_string(store: *mut KV, key: *const c_char, value: *const c_char) -> bool {
-    if store.is_null() { return false; }
+    clear_last_error();
+    if store.is_null() {
+        set_invalid_argument("store is null");
+        return false;
+    }
     let key_str = match cstr_to_rust(key) {
         Some(s) => s,
-        None => return false,
+        None => {
+            set_invalid_argument("key is null");
+            return false;
+        }
     };
     let value_str = match cstr_to_rust(value) {
         Some(s) => s,
@@ -100,7 +164,10 @@ fn ngenrs_kv_write_string(store: *mut KV, key: *const c_char, value: *const c_ch
         let kv_ref = &mut *store;
         match kv_ref.write_string(key_str, value_str) {
             Ok(_) => true,
-            Err(_) => false,
+            Err(e) => {
+                set_last_error(e);
+                false
+            }
         }
     }
 }
@@ -108,19 +175,30 @@ fn ngenrs_kv_write_string(store: *mut KV, key: *const c_char, value: *const c_ch
 #[unsafe(no_mangle)]
 pub extern "C" 
 fn ngenrs_kv_read_string(store: *mut c_void, key: *const c_char) -> *mut c_char {
-    if store.is_null() { return std::ptr::null_mut(); }
+    clear_last_error();
+    if store.is_null() {
+        set_invalid_argument("store is null");
+        return std::ptr::null_mut();
+    }
     let key_str = match cstr_to_rust(key) {
         Some(s) => s,
-        None => return std::ptr::null_mut(),
+        None => {
+            set_invalid_argument("key is null");
+            return std::ptr::null_mut();
+        }
     };
     unsafe {
         let kv_ref = &mut *(store as *mut KV);
         match kv_ref.read_string(key_str) {
-            Ok(value) => match value {
-                Some(s) => rust_to_cstr(s),
-                None => std::ptr::null_mut(),
-            },
-            Err(_) => std::ptr::null_mut(),
+            Ok(Some(s)) => rust_to_cstr(s),
+            Ok(None) => {
+                set_last_error(NGenError::not_found(format!("key {} not found", key_str)));
+                std::ptr::null_mut()
+            }
+            Err(e) => {
+                set_last_error(e);
+                std::ptr::null_mut()
+            }
         }
     }
 }
@@ -128,5 +206,5 @@ fn ngenrs_kv_read_string(store: *mut c_void, key: *const c_char) -> *mut c_char
 #[unsafe(no_mangle)]
 pub extern "C" 
 fn ngenrs_kv_close(store: *mut c_void) {
-    ngenrs_free_ptr(store)
+    ngenrs_free_ptr(store as *mut KV)
 }
\ No newline at end of file
@@ -1,20 +1,106 @@
 use std::ffi::{c_char, c_void};
+use std::path::PathBuf;
+use std::time::Duration;
+use mlua::StdLib;
 use crate::c::util::{cstr_to_rust, rust_to_cstr, ngenrs_free_ptr, box_into_raw_new};
-use crate::core::lua::LuaBridge;
+use crate::c::error::{set_last_error, clear_last_error, set_invalid_argument};
+use crate::core::lua::{LuaBridge, LuaSandbox};
+use crate::core::net::HttpClient;
 
+/// Standard library flags for `CLuaSandbox::libs`
+pub const NGENRS_LUA_LIB_COROUTINE: u32 = 1;
+pub const NGENRS_LUA_LIB_TABLE: u32 = 1 << 1;
+pub const NGENRS_LUA_LIB_IO: u32 = 1 << 2;
+pub const NGENRS_LUA_LIB_OS: u32 = 1 << 3;
+pub const NGENRS_LUA_LIB_STRING: u32 = 1 << 4;
+pub const NGENRS_LUA_LIB_UTF8: u32 = 1 << 5;
+pub const NGENRS_LUA_LIB_MATH: u32 = 1 << 6;
+
+/// Sandbox profile for `ngenrs_lua_bridge_init`. Obtain defaults from
+/// `ngenrs_lua_sandbox_default` and override the fields you need; 0 means no limit.
+#[repr(C)]
+pub struct CLuaSandbox {
+    /// `NGENRS_LUA_LIB_*` flags of the standard libraries to open
+    pub libs: u32,
+    pub memory_limit: usize,
+    /// VM instructions allowed per call into the bridge
+    pub instruction_limit: u64,
+    pub execution_timeout_ms: u64,
+    /// Directory `require` loads modules from; null disables `require`
+    pub module_dir: *const c_char,
+}
+
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_lua_sandbox_default() -> CLuaSandbox {
+    clear_last_error();
+    CLuaSandbox {
+        libs: NGENRS_LUA_LIB_COROUTINE | NGENRS_LUA_LIB_TABLE | NGENRS_LUA_LIB_STRING
+            | NGENRS_LUA_LIB_UTF8 | NGENRS_LUA_LIB_MATH,
+        memory_limit: 0,
+        instruction_limit: 0,
+        execution_timeout_ms: 0,
+        module_dir: std::ptr::null(),
+    }
+}
+
+fn sandbox_from_c(sandbox: &CLuaSandbox) -> Option<LuaSandbox> {
+    let libs = [
+        (NGENRS_LUA_LIB_COROUTINE, StdLib::COROUTINE),
+        (NGENRS_LUA_LIB_TABLE, StdLib::TABLE),
+        (NGENRS_LUA_LIB_IO, StdLib::IO),
+        (NGENRS_LUA_LIB_OS, StdLib::OS),
+        (NGENRS_LUA_LIB_STRING, StdLib::STRING),
+        (NGENRS_LUA_LIB_UTF8, StdLib::UTF8),
+        (NGENRS_LUA_LIB_MATH, StdLib::MATH),
+    ]
+    .into_iter()
+    .filter(|(flag, _)| sandbox.libs & flag != 0)
+    .fold(StdLib::NONE, |libs, (_, lib)| libs | lib);
+    let module_dir = if sandbox.module_dir.is_null() {
+        None
+    } else {
+        Some(PathBuf::from(cstr_to_rust(sandbox.module_dir)?))
+    };
+    Some(LuaSandbox {
+        libs,
+        memory_limit: (sandbox.memory_limit != 0).then_some(sandbox.memory_limit),
+        instruction_limit: (sandbox.instruction_limit != 0).then_some(sandbox.instruction_limit),
+        execution_timeout: (sandbox.execution_timeout_ms != 0)
+            .then(|| Duration::from_millis(sandbox.execution_timeout_ms)),
+        module_dir,
+    })
+}
+
+/// Creates a bridge. A null `sandbox` trusts the scripts: every standard library
+/// but `debug` is opened, including `io` and `os`, so they can read and write any
+/// file and run commands. Otherwise scripts run restricted by the sandbox profile.
 #[unsafe(no_mangle)]
 pub extern "C" 
-fn ngenrs_lua_bridge_init() -> *mut c_void {
-    match LuaBridge::new() {
+fn ngenrs_lua_bridge_init(sandbox: *const CLuaSandbox) -> *mut c_void {
+    clear_last_error();
+    let bridge = if sandbox.is_null() {
+        LuaBridge::new()
+    } else {
+        let Some(sandbox) = sandbox_from_c(unsafe { &*sandbox }) else {
+            set_invalid_argument("module_dir is not valid UTF-8");
+            return std::ptr::null_mut();
+        };
+        LuaBridge::with_sandbox(sandbox)
+    };
+    match bridge {
         Ok(bridge) => box_into_raw_new(bridge) as *mut c_void,
-        Err(_) => std::ptr::null_mut(),
+        Err(e) => {
+            set_last_error(e);
+            std::ptr::null_mut()
+        }
     }
 }
 
 #[unsafe(no_mangle)]
 pub extern "C" 
 fn ngenrs_lua_bridge_release(bridge: *mut c_void) {
-    ngenrs_free_ptr(bridge)
+    ngenrs_free_ptr(bridge as *mut LuaBridge)
 }
 
 #[unsafe(no_mangle)]
@@ -23,15 +109,26 @@ fn ngenrs_lua_load_file(
     bridge: *mut c_void,
     path: *const c_char,
 ) -> bool {
+    clear_last_error();
     if bridge.is_null() || path.is_null() {
+        set_invalid_argument("null argument");
         return false;
     }
     let bridge = unsafe { &*(bridge as *mut LuaBridge) };
     let path_str = match cstr_to_rust(path) {
         Some(s) => s,
-        None => return false,
+        None => {
+            set_invalid_argument("invalid UTF-8 string");
+            return false;
+        }
     };
-    bridge.load_file(&path_str).is_ok()
+    match bridge.load_file(&path_str) {
+        Ok(_) => true,
+        Err(e) => {
+            set_last_error(e);
+            false
+        }
+    }
 }
 
 #[unsafe(no_mangle)]
@@ -40,15 +137,26 @@ fn ngenrs_lua_load_string(
     bridge: *mut c_void,
     script: *const c_char,
 ) -> bool {
+    clear_last_error();
     if bridge.is_null() || script.is_null() {
+        set_invalid_argument("null argument");
         return false;
     }
     let bridge = unsafe { &*(bridge as *mut LuaBridge) };
     let script_str = match cstr_to_rust(script) {
         Some(s) => s,
-        None => return false,
+        None => {
+            set_invalid_argument("invalid UTF-8 string");
+            return false;
+        }
     };
-    bridge.load_string(&script_str).is_ok()
+    match bridge.load_string(&script_str) {
+        Ok(_) => true,
+        Err(e) => {
+            set_last_error(e);
+            false
+        }
+    }
 }
 
 #[unsafe(no_mangle)]
@@ -60,19 +168,27 @@ fn ngenrs_lua_call_function(
     result_out: *mut *mut c_char,
     err_out: *mut *mut c_char,
 ) -> bool {
+    clear_last_error();
     if bridge.is_null() || func_name.is_null() {
+        set_invalid_argument("null argument");
         return false;
     }
 
     let bridge = unsafe { &*(bridge as *mut LuaBridge) };
     let func_name_str = match cstr_to_rust(func_name) {
         Some(s) => s,
-        None => return false,
+        None => {
+            set_invalid_argument("invalid UTF-8 string");
+            return false;
+        }
     };
 
     let arg_str = match cstr_to_rust(arg) {
         Some(s) => s,
-        None => return false,
+        None => {
+            set_invalid_argument("invalid UTF-8 string");
+            return false;
+        }
     };
 
     match bridge.call_function(func_name_str, arg_str) {
@@ -86,7 +202,152 @@ fn ngenrs_lua_call_function(
             if !err_out.is_null() {
                 unsafe { *err_out = rust_to_cstr(e.to_string()) };
             }
+            set_last_error(e);
             false
         }
     }
-}
\ No newline at end of file
+}
+
+/// Calls a Lua function with arguments given as a JSON array (null for none)
+/// and returns its first result as JSON; see `LuaBridge::call_json`.
+/// Free `result_out` and `err_out` with `ngenrs_free_cstr`.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_lua_call_json(
+    bridge: *mut c_void,
+    func_name: *const c_char,
+    args_json: *const c_char,
+    result_out: *mut *mut c_char,
+    err_out: *mut *mut c_char,
+) -> bool {
+    clear_last_error();
+    if bridge.is_null() || func_name.is_null() {
+        set_invalid_argument("null argument");
+        return false;
+    }
+    let bridge = unsafe { &*(bridge as *mut LuaBridge) };
+    let Some(func_name) = cstr_to_rust(func_name) else {
+        set_invalid_argument("invalid UTF-8 string");
+        return false;
+    };
+    let args: Vec<serde_json::Value> = if args_json.is_null() {
+        Vec::new()
+    } else {
+        match cstr_to_rust(args_json).map(serde_json::from_str) {
+            Some(Ok(args)) => args,
+            _ => {
+                set_invalid_argument("args_json must be a JSON array");
+                return false;
+            }
+        }
+    };
+
+    match bridge.call_json(func_name, &args) {
+        Ok(result) => {
+            if !result_out.is_null() {
+                unsafe { *result_out = rust_to_cstr(result.to_string()) };
+            }
+            true
+        }
+        Err(e) => {
+            if !err_out.is_null() {
+                unsafe { *err_out = rust_to_cstr(e.to_string()) };
+            }
+            set_last_error(e);
+            false
+        }
+    }
+}
+
+/// Runs the global function `func_name` with `arg` as a coroutine (see
+/// `LuaBridge::spawn`); drive it with `ngenrs_lua_poll`
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_lua_spawn(bridge: *mut c_void, func_name: *const c_char, arg: *const c_char) -> bool {
+    clear_last_error();
+    if bridge.is_null() || func_name.is_null() || arg.is_null() {
+        set_invalid_argument("null argument");
+        return false;
+    }
+    let bridge = unsafe { &*(bridge as *mut LuaBridge) };
+    let (Some(func_name), Some(arg)) = (cstr_to_rust(func_name), cstr_to_rust(arg)) else {
+        set_invalid_argument("invalid UTF-8 string");
+        return false;
+    };
+    match bridge.spawn(func_name, arg) {
+        Ok(()) => true,
+        Err(e) => {
+            set_last_error(e);
+            false
+        }
+    }
+}
+
+/// Registers global Lua functions as an HTTP interceptor on `client` (see
+/// `LuaBridge::add_http_interceptor`); either name may be null. Returns an id
+/// for `ngenrs_http_client_remove_interceptor`, or 0 on error.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_lua_add_http_interceptor(
+    bridge: *mut c_void,
+    client: *const c_void,
+    on_request: *const c_char,
+    on_response: *const c_char,
+) -> u64 {
+    clear_last_error();
+    if bridge.is_null() || client.is_null() || (on_request.is_null() && on_response.is_null()) {
+        set_invalid_argument("null argument");
+        return 0;
+    }
+    if [on_request, on_response].iter().any(|&name| !name.is_null() && cstr_to_rust(name).is_none()) {
+        set_invalid_argument("hook name is not valid UTF-8");
+        return 0;
+    }
+    let bridge = unsafe { &*(bridge as *mut LuaBridge) };
+    let client = unsafe { &*(client as *const HttpClient) };
+    bridge.add_http_interceptor(client, cstr_to_rust(on_request), cstr_to_rust(on_response))
+}
+
+/// Uses `client` for the script `http` module instead of a default client.
+/// The client is shared, the caller keeps ownership of its handle.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_lua_set_http_client(bridge: *mut c_void, client: *const c_void) -> bool {
+    clear_last_error();
+    if bridge.is_null() || client.is_null() {
+        set_invalid_argument("null argument");
+        return false;
+    }
+    let bridge = unsafe { &*(bridge as *mut LuaBridge) };
+    let client = unsafe { &*(client as *const HttpClient) };
+    bridge.set_http_client(client.clone());
+    true
+}
+
+/// Runs the Lua callbacks of finished `http` requests and due timers; call it
+/// from the thread driving the script. Returns the milliseconds until the next
+/// timer is due (0 if overdue), -1 without timers, or -2 if a callback raised
+/// an error. `pending_out` may be null; it receives the number of requests in
+/// flight and timers scheduled, the bridge is idle when it is 0.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_lua_poll(bridge: *mut c_void, pending_out: *mut i32) -> i64 {
+    clear_last_error();
+    if bridge.is_null() {
+        set_invalid_argument("null argument");
+        return -2;
+    }
+    let bridge = unsafe { &*(bridge as *mut LuaBridge) };
+    match bridge.poll() {
+        Ok(pending) => {
+            if !pending_out.is_null() {
+                unsafe { *pending_out = pending as i32 };
+            }
+            bridge.next_timer_delay().map_or(-1, |delay| delay.as_millis() as i64)
+        }
+        Err(e) => {
+            set_last_error(e);
+            -2
+        }
+    }
+}
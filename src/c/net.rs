@@ -1,35 +1,346 @@
 use std::collections::HashMap;
+use std::ffi::CString;
+use std::future::Future;
 use std::os::raw::{c_char, c_void};
-use std::path::Path;
-use crate::c::util::{cstr_to_rust, rust_to_cstr, rust_map_from_c_arrays, rust_map_to_c_arrays, ngenrs_free_ptr, box_into_raw_new};
-use crate::core::net::{HttpClient, HttpResponse};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use crate::c::util::{cstr_to_rust, cbytes_to_rust, rust_to_cstr, rust_to_cbytes, rust_map_from_c_arrays, rust_map_to_c_arrays, ngenrs_free_ptr, box_into_raw_new, UserData};
+use crate::c::error::{set_last_error, clear_last_error, set_invalid_argument};
+use crate::core::error::{ErrorCode, NGenError};
+use crate::core::cookie_jar::CookieJar;
+use crate::core::http_cache::HttpCacheConfig;
+use crate::core::net::{ClientIdentity, DownloadOptions, HttpClient, HttpClientConfig, HttpInterceptor, HttpMethod, HttpRequest, HttpResponse, MultipartField, ProxyConfig, RetryPolicy, RUNTIME, WebSocket, WsConfig, WsEvent, WsMessage};
 use once_cell::sync::Lazy;
-use tokio::runtime::Runtime;
+use tokio::sync::Notify;
 
-static RUNTIME: Lazy<Runtime> = Lazy::new(|| {
-    Runtime::new().expect("Failed to create Tokio runtime")
+/// Completion callback for the `_async` variants. Runs on a runtime worker thread.
+/// On success `rsp` is an owned response (release with `ngenrs_http_rsp_release`)
+/// and `err_code` is 0; otherwise `rsp` is null and `err_msg` is only valid during the call.
+pub type HttpCallback = extern "C" fn(
+    request_id: u64,
+    rsp: *mut c_void,
+    err_code: i32,
+    err_msg: *const c_char,
+    user_data: *mut c_void,
+);
+
+/// Receives body chunks in streaming mode; `data` is only valid during the call.
+/// Return false to abort the transfer. `request_id` is 0 for blocking sends.
+pub type HttpChunkCallback = extern "C" fn(
+    request_id: u64,
+    data: *const u8,
+    len: usize,
+    user_data: *mut c_void,
+) -> bool;
+
+/// Reports download progress; `total` is -1 when the size is unknown.
+/// `request_id` is 0 for blocking downloads.
+pub type HttpProgressCallback = extern "C" fn(
+    request_id: u64,
+    received: u64,
+    total: i64,
+    user_data: *mut c_void,
+);
+
+static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(1);
+static PENDING_REQUESTS: Lazy<Mutex<HashMap<u64, Arc<Notify>>>> = Lazy::new(|| {
+    Mutex::new(HashMap::new())
 });
 
+fn next_request_id() -> u64 {
+    NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+fn spawn_request<F>(callback: HttpCallback, user_data: *mut c_void, request: F) -> u64
+where
+    F: Future<Output = Result<HttpResponse, NGenError>> + Send + 'static,
+{
+    spawn_request_with_id(next_request_id(), callback, user_data, request)
+}
+
+fn spawn_request_with_id<F>(
+    request_id: u64,
+    callback: HttpCallback,
+    user_data: *mut c_void,
+    request: F,
+) -> u64
+where
+    F: Future<Output = Result<HttpResponse, NGenError>> + Send + 'static,
+{
+    let cancel = Arc::new(Notify::new());
+    PENDING_REQUESTS.lock().unwrap().insert(request_id, cancel.clone());
+    let user_data = UserData(user_data);
+
+    RUNTIME.spawn(async move {
+        let user_data = user_data;
+        let result = tokio::select! {
+            result = request => result,
+            _ = cancel.notified() => Err(NGenError::new(ErrorCode::Cancelled, "Request cancelled")),
+        };
+        // Whichever of completion and `ngenrs_http_cancel` removes the entry first wins
+        let result = match PENDING_REQUESTS.lock().unwrap().remove(&request_id) {
+            Some(_) => result,
+            None => Err(NGenError::new(ErrorCode::Cancelled, "Request cancelled")),
+        };
+
+        match result {
+            Ok(rsp) => {
+                callback(request_id, box_into_raw_new(rsp) as *mut c_void, 0, std::ptr::null(), user_data.0);
+            }
+            Err(e) => {
+                let msg = CString::new(e.message).unwrap_or_default();
+                callback(request_id, std::ptr::null_mut(), e.code as i32, msg.as_ptr(), user_data.0);
+            }
+        }
+    });
+
+    request_id
+}
+
 // Client management
 #[unsafe(no_mangle)]
 pub extern "C"
 fn ngenrs_http_client_init(ca_cert_path: *const c_char) -> *mut c_void {
+    clear_last_error();
     let ca_path = if !ca_cert_path.is_null() {
-        let path_str = cstr_to_rust(ca_cert_path).unwrap();
-        Some(std::path::Path::new(path_str))
+        match cstr_to_rust(ca_cert_path) {
+            Some(path_str) => Some(std::path::Path::new(path_str)),
+            None => {
+                set_invalid_argument("ca_cert_path is not valid UTF-8");
+                return std::ptr::null_mut();
+            }
+        }
     } else {
         None
     };
 
-    box_into_raw_new(
-        HttpClient::new(ca_path).expect("Failed to create HTTP client")
-    ) as *mut c_void
+    match HttpClient::new(ca_path) {
+        Ok(client) => box_into_raw_new(client) as *mut c_void,
+        Err(e) => {
+            set_last_error(NGenError::from_boxed(e, ErrorCode::Network));
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Client settings for `ngenrs_http_client_init_with_config`. Obtain defaults from
+/// `ngenrs_http_client_config_default` and override the fields you need.
+/// Durations are in milliseconds with 0 meaning no limit; string fields may be null.
+#[repr(C)]
+pub struct CHttpClientConfig {
+    pub ca_cert_path: *const c_char,
+    pub connect_timeout_ms: u64,
+    pub read_timeout_ms: u64,
+    pub total_timeout_ms: u64,
+    /// Total attempts including the first; 1 disables retries
+    pub retry_max_attempts: u32,
+    pub retry_initial_backoff_ms: u64,
+    pub retry_max_backoff_ms: u64,
+    pub retry_jitter: bool,
+    /// Statuses to retry; null keeps the defaults (429, 502, 503, 504)
+    pub retry_statuses: *const u16,
+    pub retry_statuses_len: usize,
+    /// Also retry POST and PATCH, which may repeat their side effects
+    pub retry_non_idempotent: bool,
+    /// -1 keeps the default limit, 0 disables redirects
+    pub max_redirects: i32,
+    pub proxy_url: *const c_char,
+    pub proxy_username: *const c_char,
+    pub proxy_password: *const c_char,
+    pub no_proxy: *const c_char,
+    /// Null uses the library default
+    pub user_agent: *const c_char,
+    /// PEM client certificate and key for mutual TLS; ignored when null
+    pub client_cert_path: *const c_char,
+    pub client_key_path: *const c_char,
+    /// PKCS#12 client identity, used instead of the PEM pair when set
+    pub pkcs12_path: *const c_char,
+    pub pkcs12_password: *const c_char,
+    /// Parallel arrays of `pins_len` host / SPKI SHA-256 (base64) pairs;
+    /// repeat a host to accept several keys
+    pub pin_hosts: *const *const c_char,
+    pub pin_hashes: *const *const c_char,
+    pub pins_len: usize,
+    /// redb file for the response cache; null disables caching
+    pub cache_path: *const c_char,
+    pub cache_max_bytes: u64,
+    /// Keeps cookies in memory; implied by `cookie_path`
+    pub enable_cookies: bool,
+    /// JSON file the cookie jar is loaded from and saved to
+    pub cookie_path: *const c_char,
+    /// Also persist cookies without Expires/Max-Age
+    pub persist_session_cookies: bool,
+}
+
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_http_client_config_default() -> CHttpClientConfig {
+    clear_last_error();
+    let retry = RetryPolicy::default();
+    CHttpClientConfig {
+        ca_cert_path: std::ptr::null(),
+        connect_timeout_ms: 0,
+        read_timeout_ms: 0,
+        total_timeout_ms: 0,
+        retry_max_attempts: retry.max_attempts,
+        retry_initial_backoff_ms: retry.initial_backoff.as_millis() as u64,
+        retry_max_backoff_ms: retry.max_backoff.as_millis() as u64,
+        retry_jitter: retry.jitter,
+        retry_statuses: std::ptr::null(),
+        retry_statuses_len: 0,
+        retry_non_idempotent: retry.retry_non_idempotent,
+        max_redirects: -1,
+        proxy_url: std::ptr::null(),
+        proxy_username: std::ptr::null(),
+        proxy_password: std::ptr::null(),
+        no_proxy: std::ptr::null(),
+        user_agent: std::ptr::null(),
+        client_cert_path: std::ptr::null(),
+        client_key_path: std::ptr::null(),
+        pkcs12_path: std::ptr::null(),
+        pkcs12_password: std::ptr::null(),
+        pin_hosts: std::ptr::null(),
+        pin_hashes: std::ptr::null(),
+        pins_len: 0,
+        cache_path: std::ptr::null(),
+        cache_max_bytes: 64 * 1024 * 1024,
+        enable_cookies: false,
+        cookie_path: std::ptr::null(),
+        persist_session_cookies: false,
+    }
+}
+
+fn optional_cstr(ptr: *const c_char, name: &str) -> Result<Option<String>, NGenError> {
+    if ptr.is_null() {
+        return Ok(None);
+    }
+    cstr_to_rust(ptr)
+        .map(|s| Some(s.to_string()))
+        .ok_or_else(|| NGenError::invalid_argument(format!("{} is not valid UTF-8", name)))
+}
+
+fn optional_millis(ms: u64) -> Option<Duration> {
+    (ms > 0).then(|| Duration::from_millis(ms))
+}
+
+fn client_config_from_c(config: &CHttpClientConfig) -> Result<HttpClientConfig, NGenError> {
+    let mut retry = RetryPolicy {
+        max_attempts: config.retry_max_attempts.max(1),
+        initial_backoff: Duration::from_millis(config.retry_initial_backoff_ms),
+        max_backoff: Duration::from_millis(config.retry_max_backoff_ms),
+        jitter: config.retry_jitter,
+        retry_non_idempotent: config.retry_non_idempotent,
+        ..Default::default()
+    };
+    if !config.retry_statuses.is_null() {
+        retry.retry_statuses = unsafe {
+            std::slice::from_raw_parts(config.retry_statuses, config.retry_statuses_len)
+        }.to_vec();
+    }
+
+    let proxy = match optional_cstr(config.proxy_url, "proxy_url")? {
+        Some(url) => Some(ProxyConfig {
+            url,
+            username: optional_cstr(config.proxy_username, "proxy_username")?,
+            password: optional_cstr(config.proxy_password, "proxy_password")?,
+            no_proxy: optional_cstr(config.no_proxy, "no_proxy")?,
+        }),
+        None => None,
+    };
+
+    let identity = if let Some(path) = optional_cstr(config.pkcs12_path, "pkcs12_path")? {
+        Some(ClientIdentity::Pkcs12 {
+            der: std::fs::read(path)?,
+            password: optional_cstr(config.pkcs12_password, "pkcs12_password")?.unwrap_or_default(),
+        })
+    } else {
+        match (
+            optional_cstr(config.client_cert_path, "client_cert_path")?,
+            optional_cstr(config.client_key_path, "client_key_path")?,
+        ) {
+            (Some(cert), Some(key)) => Some(ClientIdentity::Pem {
+                cert: std::fs::read(cert)?,
+                key: std::fs::read(key)?,
+            }),
+            (None, None) => None,
+            _ => return Err(NGenError::invalid_argument("client_cert_path and client_key_path must be set together")),
+        }
+    };
+
+    let mut pins: HashMap<String, Vec<String>> = HashMap::new();
+    if config.pins_len > 0 {
+        if config.pin_hosts.is_null() || config.pin_hashes.is_null() {
+            return Err(NGenError::invalid_argument("pin_hosts or pin_hashes is null"));
+        }
+        let hosts = unsafe { std::slice::from_raw_parts(config.pin_hosts, config.pins_len) };
+        let hashes = unsafe { std::slice::from_raw_parts(config.pin_hashes, config.pins_len) };
+        for (&host, &hash) in hosts.iter().zip(hashes) {
+            let (Some(host), Some(hash)) = (cstr_to_rust(host), cstr_to_rust(hash)) else {
+                return Err(NGenError::invalid_argument("pin host or hash is null or not valid UTF-8"));
+            };
+            pins.entry(host.to_string()).or_default().push(hash.to_string());
+        }
+    }
+
+    let cookie_jar = match optional_cstr(config.cookie_path, "cookie_path")? {
+        Some(path) => Some(Arc::new(CookieJar::open(path, config.persist_session_cookies)?)),
+        None if config.enable_cookies => Some(Arc::new(CookieJar::new())),
+        None => None,
+    };
+
+    Ok(HttpClientConfig {
+        ca_cert_path: optional_cstr(config.ca_cert_path, "ca_cert_path")?.map(PathBuf::from),
+        connect_timeout: optional_millis(config.connect_timeout_ms),
+        read_timeout: optional_millis(config.read_timeout_ms),
+        total_timeout: optional_millis(config.total_timeout_ms),
+        retry,
+        max_redirects: usize::try_from(config.max_redirects).ok(),
+        proxy,
+        user_agent: optional_cstr(config.user_agent, "user_agent")?,
+        identity,
+        pins,
+        cache: optional_cstr(config.cache_path, "cache_path")?.map(|path| HttpCacheConfig {
+            path: PathBuf::from(path),
+            max_bytes: config.cache_max_bytes,
+        }),
+        cookie_jar,
+    })
+}
+
+/// Creates a client from `config`; a null `config` behaves like the defaults.
+/// Returns null and sets the last error on failure.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_http_client_init_with_config(config: *const CHttpClientConfig) -> *mut c_void {
+    clear_last_error();
+    let config = if config.is_null() {
+        Ok(HttpClientConfig::default())
+    } else {
+        client_config_from_c(unsafe { &*config })
+    };
+    let config = match config {
+        Ok(config) => config,
+        Err(e) => {
+            set_last_error(e);
+            return std::ptr::null_mut();
+        }
+    };
+
+    match HttpClient::with_config(config) {
+        Ok(client) => box_into_raw_new(client) as *mut c_void,
+        Err(e) => {
+            set_last_error(NGenError::from_boxed(e, ErrorCode::Network));
+            std::ptr::null_mut()
+        }
+    }
 }
 
 #[unsafe(no_mangle)]
 pub extern "C" 
 fn ngenrs_http_client_release(client: *mut c_void) {
-    ngenrs_free_ptr(client)
+    ngenrs_free_ptr(client as *mut HttpClient)
 }
 
 // HTTP GET
@@ -43,8 +354,16 @@ fn ngenrs_http_get(
     headers_len: usize,
     body: *const c_char,
 ) -> *mut c_void {
+    clear_last_error();
+    if client.is_null() || url.is_null() {
+        set_invalid_argument("client or url is null");
+        return std::ptr::null_mut();
+    }
+    let Some(url) = cstr_to_rust(url) else {
+        set_invalid_argument("url is not valid UTF-8");
+        return std::ptr::null_mut();
+    };
     let client = unsafe { &*(client as *const HttpClient) };
-    let url = cstr_to_rust(url).unwrap_or_default();
     let headers = unsafe { rust_map_from_c_arrays(header_keys, header_values, headers_len) };
     let body = if !body.is_null() {
         Some(cstr_to_rust(body).unwrap_or_default())
@@ -53,12 +372,15 @@ fn ngenrs_http_get(
     };
 
     let result = RUNTIME.block_on(async {
-        client.get(&url, headers, body).await
+        client.get(url, headers, body).await
     });
 
     match result {
         Ok(resp) => box_into_raw_new(resp) as *mut c_void,
-        Err(_) => std::ptr::null_mut(),
+        Err(e) => {
+            set_last_error(NGenError::from_boxed(e, ErrorCode::Network));
+            std::ptr::null_mut()
+        }
     }
 }
 
@@ -76,8 +398,16 @@ fn ngenrs_http_post(
     json_values: *const *const c_char,
     json_len: usize,
 ) -> *mut c_void {
+    clear_last_error();
+    if client.is_null() || url.is_null() {
+        set_invalid_argument("client or url is null");
+        return std::ptr::null_mut();
+    }
+    let Some(url) = cstr_to_rust(url) else {
+        set_invalid_argument("url is not valid UTF-8");
+        return std::ptr::null_mut();
+    };
     let client = unsafe { &*(client as *const HttpClient) };
-    let url = cstr_to_rust(url).unwrap_or_default();
     let headers = unsafe { rust_map_from_c_arrays(header_keys, header_values, headers_len) };
     let body = if !body.is_null() {
         Some(cstr_to_rust(body).unwrap_or_default())
@@ -87,12 +417,15 @@ fn ngenrs_http_post(
     let json_map = unsafe { rust_map_from_c_arrays(json_keys, json_values, json_len) };
 
     let result = RUNTIME.block_on(async {
-        client.post(&url, headers, body, json_map).await
+        client.post(url, headers, body, json_map).await
     });
 
     match result {
         Ok(resp) => box_into_raw_new(resp) as *mut c_void,
-        Err(_) => std::ptr::null_mut(),
+        Err(e) => {
+            set_last_error(NGenError::from_boxed(e, ErrorCode::Network));
+            std::ptr::null_mut()
+        }
     }
 }
 
@@ -107,26 +440,603 @@ fn ngenrs_http_download(
     headers_len: usize,
     output_path: *const c_char,
 ) -> *mut c_void {
+    clear_last_error();
+    if client.is_null() || url.is_null() || output_path.is_null() {
+        set_invalid_argument("client, url or output_path is null");
+        return std::ptr::null_mut();
+    }
+    let Some(url) = cstr_to_rust(url) else {
+        set_invalid_argument("url is not valid UTF-8");
+        return std::ptr::null_mut();
+    };
     let client = unsafe { &*(client as *const HttpClient) };
-    let url = cstr_to_rust(url).unwrap_or_default();
+    let Some(output_path) = cstr_to_rust(output_path).map(Path::new) else {
+        set_invalid_argument("output_path is not valid UTF-8");
+        return std::ptr::null_mut();
+    };
     let headers = unsafe { rust_map_from_c_arrays(header_keys, header_values, headers_len) };
-    let output_path = Path::new(cstr_to_rust(output_path).unwrap_or_default());
 
     let result = RUNTIME.block_on(async {
-        client.download(&url, headers, output_path).await
+        client.download(url, headers, output_path).await
     });
 
     match result {
         Ok(resp) => box_into_raw_new(resp) as *mut c_void,
-        Err(_) => std::ptr::null_mut(),
+        Err(e) => {
+            set_last_error(NGenError::from_boxed(e, ErrorCode::Network));
+            std::ptr::null_mut()
+        }
+    }
+}
+
+// Asynchronous variants: return a request id (0 on invalid arguments) immediately
+// and report completion through `callback`, which is invoked exactly once.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_http_get_async(
+    client: *const c_void,
+    url: *const c_char,
+    header_keys: *const *const c_char,
+    header_values: *const *const c_char,
+    headers_len: usize,
+    body: *const c_char,
+    callback: HttpCallback,
+    user_data: *mut c_void,
+) -> u64 {
+    clear_last_error();
+    if client.is_null() || url.is_null() {
+        set_invalid_argument("client or url is null");
+        return 0;
+    }
+    let Some(url) = cstr_to_rust(url).map(str::to_string) else {
+        set_invalid_argument("url is not valid UTF-8");
+        return 0;
+    };
+    let client = unsafe { &*(client as *const HttpClient) }.clone();
+    let headers = unsafe { rust_map_from_c_arrays(header_keys, header_values, headers_len) };
+    let body = cstr_to_rust(body).map(|s| s.to_string());
+
+    spawn_request(callback, user_data, async move {
+        client.get(&url, headers, body.as_deref()).await
+            .map_err(|e| NGenError::from_boxed(e, ErrorCode::Network))
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_http_post_async(
+    client: *const c_void,
+    url: *const c_char,
+    header_keys: *const *const c_char,
+    header_values: *const *const c_char,
+    headers_len: usize,
+    body: *const c_char,
+    json_keys: *const *const c_char,
+    json_values: *const *const c_char,
+    json_len: usize,
+    callback: HttpCallback,
+    user_data: *mut c_void,
+) -> u64 {
+    clear_last_error();
+    if client.is_null() || url.is_null() {
+        set_invalid_argument("client or url is null");
+        return 0;
     }
+    let Some(url) = cstr_to_rust(url).map(str::to_string) else {
+        set_invalid_argument("url is not valid UTF-8");
+        return 0;
+    };
+    let client = unsafe { &*(client as *const HttpClient) }.clone();
+    let headers = unsafe { rust_map_from_c_arrays(header_keys, header_values, headers_len) };
+    let body = cstr_to_rust(body).map(|s| s.to_string());
+    let json_map = unsafe { rust_map_from_c_arrays(json_keys, json_values, json_len) };
+
+    spawn_request(callback, user_data, async move {
+        client.post(&url, headers, body.as_deref(), json_map).await
+            .map_err(|e| NGenError::from_boxed(e, ErrorCode::Network))
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_http_download_async(
+    client: *const c_void,
+    url: *const c_char,
+    header_keys: *const *const c_char,
+    header_values: *const *const c_char,
+    headers_len: usize,
+    output_path: *const c_char,
+    callback: HttpCallback,
+    user_data: *mut c_void,
+) -> u64 {
+    clear_last_error();
+    if client.is_null() || url.is_null() || output_path.is_null() {
+        set_invalid_argument("client, url or output_path is null");
+        return 0;
+    }
+    let Some(url) = cstr_to_rust(url).map(str::to_string) else {
+        set_invalid_argument("url is not valid UTF-8");
+        return 0;
+    };
+    let client = unsafe { &*(client as *const HttpClient) }.clone();
+    let Some(output_path) = cstr_to_rust(output_path).map(PathBuf::from) else {
+        set_invalid_argument("output_path is not valid UTF-8");
+        return 0;
+    };
+    let headers = unsafe { rust_map_from_c_arrays(header_keys, header_values, headers_len) };
+
+    spawn_request(callback, user_data, async move {
+        client.download(&url, headers, &output_path).await
+            .map_err(|e| NGenError::from_boxed(e, ErrorCode::Network))
+    })
+}
+
+/// Cancels a pending async request. Its callback still fires, with `ErrorCode::Cancelled`.
+/// Returns false if the request already completed or the id is unknown.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_http_cancel(request_id: u64) -> bool {
+    clear_last_error();
+    match PENDING_REQUESTS.lock().unwrap().remove(&request_id) {
+        Some(cancel) => {
+            cancel.notify_one();
+            true
+        }
+        None => false,
+    }
+}
+
+// Generic request builder: `ngenrs_http_request_new` returns a request handle that is
+// filled in with the setters below, sent any number of times and freed with
+// `ngenrs_http_request_release`. Method values follow `HttpMethod`.
+fn update_request<F>(req: *mut c_void, op: F) -> bool
+where
+    F: FnOnce(HttpRequest) -> HttpRequest,
+{
+    if req.is_null() {
+        set_invalid_argument("request is null");
+        return false;
+    }
+    let req = unsafe { &mut *(req as *mut HttpRequest) };
+    let current = std::mem::replace(req, HttpRequest::new(HttpMethod::Get, ""));
+    *req = op(current);
+    true
+}
+
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_http_request_new(method: i32, url: *const c_char) -> *mut c_void {
+    clear_last_error();
+    match cstr_to_rust(url) {
+        Some(url) => box_into_raw_new(HttpRequest::new(HttpMethod::from(method), url)) as *mut c_void,
+        None => {
+            set_invalid_argument("url is null");
+            std::ptr::null_mut()
+        }
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_http_request_set_header(req: *mut c_void, key: *const c_char, value: *const c_char) -> bool {
+    clear_last_error();
+    let (Some(key), Some(value)) = (cstr_to_rust(key), cstr_to_rust(value)) else {
+        set_invalid_argument("header key or value is null");
+        return false;
+    };
+    update_request(req, |r| r.header(key, value))
+}
+
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_http_request_add_query(req: *mut c_void, key: *const c_char, value: *const c_char) -> bool {
+    clear_last_error();
+    let (Some(key), Some(value)) = (cstr_to_rust(key), cstr_to_rust(value)) else {
+        set_invalid_argument("query key or value is null");
+        return false;
+    };
+    update_request(req, |r| r.query(key, value))
+}
+
+/// Sets a raw body; `data` may be binary.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_http_request_set_body(req: *mut c_void, data: *const u8, data_len: usize) -> bool {
+    clear_last_error();
+    let Some(data) = cbytes_to_rust(data, data_len) else {
+        set_invalid_argument("body is null");
+        return false;
+    };
+    update_request(req, |r| r.body(data))
+}
+
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_http_request_set_json(req: *mut c_void, json: *const c_char) -> bool {
+    clear_last_error();
+    let Some(json) = cstr_to_rust(json) else {
+        set_invalid_argument("json is null");
+        return false;
+    };
+    match serde_json::from_str(json) {
+        Ok(value) => update_request(req, |r| r.json(value)),
+        Err(e) => {
+            set_invalid_argument(&e.to_string());
+            false
+        }
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_http_request_add_form_field(req: *mut c_void, key: *const c_char, value: *const c_char) -> bool {
+    clear_last_error();
+    let (Some(key), Some(value)) = (cstr_to_rust(key), cstr_to_rust(value)) else {
+        set_invalid_argument("form key or value is null");
+        return false;
+    };
+    update_request(req, |r| r.form_field(key, value))
+}
+
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_http_request_add_multipart_text(req: *mut c_void, name: *const c_char, value: *const c_char) -> bool {
+    clear_last_error();
+    let (Some(name), Some(value)) = (cstr_to_rust(name), cstr_to_rust(value)) else {
+        set_invalid_argument("part name or value is null");
+        return false;
+    };
+    update_request(req, |r| r.multipart_field(name, MultipartField::Text(value.to_string())))
+}
+
+/// `filename` and `mime` may be null.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_http_request_add_multipart_bytes(
+    req: *mut c_void,
+    name: *const c_char,
+    data: *const u8,
+    data_len: usize,
+    filename: *const c_char,
+    mime: *const c_char,
+) -> bool {
+    clear_last_error();
+    let (Some(name), Some(data)) = (cstr_to_rust(name), cbytes_to_rust(data, data_len)) else {
+        set_invalid_argument("part name or data is null");
+        return false;
+    };
+    let field = MultipartField::Bytes {
+        data: data.to_vec(),
+        filename: cstr_to_rust(filename).map(|s| s.to_string()),
+        mime: cstr_to_rust(mime).map(|s| s.to_string()),
+    };
+    update_request(req, |r| r.multipart_field(name, field))
+}
+
+/// The file is read when the request is sent; `mime` may be null.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_http_request_add_multipart_file(
+    req: *mut c_void,
+    name: *const c_char,
+    path: *const c_char,
+    mime: *const c_char,
+) -> bool {
+    clear_last_error();
+    let (Some(name), Some(path)) = (cstr_to_rust(name), cstr_to_rust(path)) else {
+        set_invalid_argument("part name or path is null");
+        return false;
+    };
+    let field = MultipartField::File {
+        path: PathBuf::from(path),
+        mime: cstr_to_rust(mime).map(|s| s.to_string()),
+    };
+    update_request(req, |r| r.multipart_field(name, field))
+}
+
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_http_request_remove_header(req: *mut c_void, key: *const c_char) -> bool {
+    clear_last_error();
+    let Some(key) = cstr_to_rust(key) else {
+        set_invalid_argument("header key is null");
+        return false;
+    };
+    update_request(req, |r| r.remove_header(key))
+}
+
+/// Replaces the URL; query parameters added with `ngenrs_http_request_add_query` are kept.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_http_request_set_url(req: *mut c_void, url: *const c_char) -> bool {
+    clear_last_error();
+    let Some(url) = cstr_to_rust(url) else {
+        set_invalid_argument("url is null");
+        return false;
+    };
+    update_request(req, |r| HttpRequest { url: url.to_string(), ..r })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_http_request_get_method(req: *const c_void) -> i32 {
+    clear_last_error();
+    if req.is_null() {
+        set_invalid_argument("request is null");
+        return -1;
+    }
+    let req = unsafe { &*(req as *const HttpRequest) };
+    req.method as i32
+}
+
+/// Returns the URL including query parameters (release with `ngenrs_free_cstr`).
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_http_request_get_url(req: *const c_void) -> *mut c_char {
+    clear_last_error();
+    if req.is_null() {
+        set_invalid_argument("request is null");
+        return std::ptr::null_mut();
+    }
+    let req = unsafe { &*(req as *const HttpRequest) };
+    rust_to_cstr(req.full_url().unwrap_or_else(|| req.url.clone()))
+}
+
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_http_request_get_headers(
+    req: *const c_void,
+    keys: *mut *mut c_char,
+    values: *mut *mut c_char,
+    count: *mut usize,
+) {
+    clear_last_error();
+    if req.is_null() {
+        set_invalid_argument("request is null");
+        return;
+    }
+    let req = unsafe { &*(req as *const HttpRequest) };
+    let headers_map: HashMap<String, String> = req.headers.iter().cloned().collect();
+
+    unsafe { rust_map_to_c_arrays(&headers_map, keys, values, count) };
+}
+
+/// Returns a copy of the encoded body (release with `ngenrs_free_bytes`), e.g. for
+/// request signing. Null for multipart bodies, which are encoded at send time.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_http_request_get_body(req: *const c_void, out_len: *mut usize) -> *mut u8 {
+    clear_last_error();
+    if req.is_null() || out_len.is_null() {
+        set_invalid_argument("request or out_len is null");
+        return std::ptr::null_mut();
+    }
+    let req = unsafe { &*(req as *const HttpRequest) };
+    match req.body_bytes() {
+        Some(body) => {
+            let (ptr, len) = rust_to_cbytes(body);
+            unsafe { *out_len = len };
+            ptr
+        }
+        None => {
+            unsafe { *out_len = 0 };
+            std::ptr::null_mut()
+        }
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_http_request_send(client: *const c_void, req: *const c_void) -> *mut c_void {
+    clear_last_error();
+    if client.is_null() || req.is_null() {
+        set_invalid_argument("client or request is null");
+        return std::ptr::null_mut();
+    }
+    let client = unsafe { &*(client as *const HttpClient) };
+    let request = unsafe { &*(req as *const HttpRequest) }.clone();
+
+    let result = RUNTIME.block_on(async {
+        client.send(request).await
+    });
+
+    match result {
+        Ok(resp) => box_into_raw_new(resp) as *mut c_void,
+        Err(e) => {
+            set_last_error(NGenError::from_boxed(e, ErrorCode::Network));
+            std::ptr::null_mut()
+        }
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_http_request_send_async(
+    client: *const c_void,
+    req: *const c_void,
+    callback: HttpCallback,
+    user_data: *mut c_void,
+) -> u64 {
+    clear_last_error();
+    if client.is_null() || req.is_null() {
+        set_invalid_argument("client or request is null");
+        return 0;
+    }
+    let client = unsafe { &*(client as *const HttpClient) }.clone();
+    let request = unsafe { &*(req as *const HttpRequest) }.clone();
+
+    spawn_request(callback, user_data, async move {
+        client.send(request).await
+            .map_err(|e| NGenError::from_boxed(e, ErrorCode::Network))
+    })
+}
+
+/// Sends the request and streams the body through `on_chunk` on the calling thread.
+/// The returned response has status and headers but no body.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_http_request_send_streaming(
+    client: *const c_void,
+    req: *const c_void,
+    on_chunk: HttpChunkCallback,
+    user_data: *mut c_void,
+) -> *mut c_void {
+    clear_last_error();
+    if client.is_null() || req.is_null() {
+        set_invalid_argument("client or request is null");
+        return std::ptr::null_mut();
+    }
+    let client = unsafe { &*(client as *const HttpClient) };
+    let request = unsafe { &*(req as *const HttpRequest) }.clone();
+
+    let result = RUNTIME.block_on(async {
+        client.send_streaming(request, |chunk| on_chunk(0, chunk.as_ptr(), chunk.len(), user_data)).await
+    });
+
+    match result {
+        Ok(resp) => box_into_raw_new(resp) as *mut c_void,
+        Err(e) => {
+            set_last_error(NGenError::from_boxed(e, ErrorCode::Network));
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Streaming counterpart of `ngenrs_http_request_send_async`: chunks are delivered to
+/// `on_chunk` on a runtime worker thread, then `callback` fires once with a bodiless response.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_http_request_send_streaming_async(
+    client: *const c_void,
+    req: *const c_void,
+    on_chunk: HttpChunkCallback,
+    callback: HttpCallback,
+    user_data: *mut c_void,
+) -> u64 {
+    clear_last_error();
+    if client.is_null() || req.is_null() {
+        set_invalid_argument("client or request is null");
+        return 0;
+    }
+    let client = unsafe { &*(client as *const HttpClient) }.clone();
+    let request = unsafe { &*(req as *const HttpRequest) }.clone();
+    let request_id = next_request_id();
+    let chunk_user_data = UserData(user_data);
+
+    spawn_request_with_id(request_id, callback, user_data, async move {
+        client.send_streaming(request, move |chunk| {
+            on_chunk(request_id, chunk.as_ptr(), chunk.len(), chunk_user_data.get())
+        }).await
+            .map_err(|e| NGenError::from_boxed(e, ErrorCode::Network))
+    })
+}
+
+fn download_options(resume: bool, expected_sha256: *const c_char) -> Option<DownloadOptions> {
+    let expected_sha256 = if expected_sha256.is_null() {
+        None
+    } else {
+        Some(cstr_to_rust(expected_sha256)?.to_string())
+    };
+    Some(DownloadOptions { resume, expected_sha256 })
+}
+
+/// Downloads the request's response body into `output_path` on the calling thread.
+/// With `resume`, a partial file left by an interrupted download is continued via
+/// `Range`/`If-Range`. `expected_sha256` (hex, may be null) is checked once the file
+/// is complete; on mismatch the file is removed and `ErrorCode::ChecksumMismatch` is set.
+/// `on_progress` may be null.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_http_request_download(
+    client: *const c_void,
+    req: *const c_void,
+    output_path: *const c_char,
+    resume: bool,
+    expected_sha256: *const c_char,
+    on_progress: Option<HttpProgressCallback>,
+    user_data: *mut c_void,
+) -> *mut c_void {
+    clear_last_error();
+    if client.is_null() || req.is_null() || output_path.is_null() {
+        set_invalid_argument("client, request or output_path is null");
+        return std::ptr::null_mut();
+    }
+    let client = unsafe { &*(client as *const HttpClient) };
+    let request = unsafe { &*(req as *const HttpRequest) }.clone();
+    let (Some(output_path), Some(options)) = (cstr_to_rust(output_path), download_options(resume, expected_sha256)) else {
+        set_invalid_argument("output_path or expected_sha256 is not valid UTF-8");
+        return std::ptr::null_mut();
+    };
+
+    let result = RUNTIME.block_on(async {
+        client.download_request(request, Path::new(output_path), &options, |received, total| {
+            if let Some(on_progress) = on_progress {
+                on_progress(0, received, total.map_or(-1, |t| t as i64), user_data);
+            }
+        }).await
+    });
+
+    match result {
+        Ok(resp) => box_into_raw_new(resp) as *mut c_void,
+        Err(e) => {
+            set_last_error(NGenError::from_boxed(e, ErrorCode::Network));
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Async counterpart of `ngenrs_http_request_download`; progress is reported on a
+/// runtime worker thread and `callback` fires once with a bodiless response.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_http_request_download_async(
+    client: *const c_void,
+    req: *const c_void,
+    output_path: *const c_char,
+    resume: bool,
+    expected_sha256: *const c_char,
+    on_progress: Option<HttpProgressCallback>,
+    callback: HttpCallback,
+    user_data: *mut c_void,
+) -> u64 {
+    clear_last_error();
+    if client.is_null() || req.is_null() || output_path.is_null() {
+        set_invalid_argument("client, request or output_path is null");
+        return 0;
+    }
+    let client = unsafe { &*(client as *const HttpClient) }.clone();
+    let request = unsafe { &*(req as *const HttpRequest) }.clone();
+    let (Some(output_path), Some(options)) = (cstr_to_rust(output_path), download_options(resume, expected_sha256)) else {
+        set_invalid_argument("output_path or expected_sha256 is not valid UTF-8");
+        return 0;
+    };
+    let output_path = PathBuf::from(output_path);
+    let request_id = next_request_id();
+    let progress_user_data = UserData(user_data);
+
+    spawn_request_with_id(request_id, callback, user_data, async move {
+        client.download_request(request, &output_path, &options, move |received, total| {
+            if let Some(on_progress) = on_progress {
+                on_progress(request_id, received, total.map_or(-1, |t| t as i64), progress_user_data.get());
+            }
+        }).await
+            .map_err(|e| NGenError::from_boxed(e, ErrorCode::Network))
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_http_request_release(req: *mut c_void) {
+    ngenrs_free_ptr(req as *mut HttpRequest)
 }
 
 // Response parsing functions
 #[unsafe(no_mangle)]
 pub extern "C"
 fn ngenrs_http_parse_rsp_status(rsp_ptr: *mut c_void) -> i32 {
+    clear_last_error();
     if rsp_ptr.is_null() {
+        set_invalid_argument("response is null");
         return -1;
     }
     let rsp = unsafe { &*(rsp_ptr as *const HttpResponse) };
@@ -141,7 +1051,9 @@ fn ngenrs_http_parse_rsp_headers(
     values: *mut *mut c_char,
     count: *mut usize
 ) {
+    clear_last_error();
     if rsp_ptr.is_null() {
+        set_invalid_argument("response is null");
         return;
     }
     let rsp = unsafe { &*(rsp_ptr as *const HttpResponse) };
@@ -155,12 +1067,483 @@ fn ngenrs_http_parse_rsp_headers(
 #[unsafe(no_mangle)]
 pub extern "C" 
 fn ngenrs_http_parse_rsp_body(rsp_ptr: *mut c_void) -> *mut c_char {
+    clear_last_error();
     if rsp_ptr.is_null() {
+        set_invalid_argument("response is null");
         return std::ptr::null_mut();
     }
     let rsp = unsafe { &*(rsp_ptr as *const HttpResponse) };
-    match &rsp.body {
-        Some(body) => rust_to_cstr(body.to_string()),  // Convert &str to String if needed
+    match rsp.text() {
+        Some(body) => rust_to_cstr(body),
         None => std::ptr::null_mut(),
     }
 }
+
+/// Returns a copy of the raw body bytes (release with `ngenrs_free_bytes`),
+/// or null if the response has no buffered body.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_http_parse_rsp_body_bytes(rsp_ptr: *mut c_void, out_len: *mut usize) -> *mut u8 {
+    clear_last_error();
+    if rsp_ptr.is_null() || out_len.is_null() {
+        set_invalid_argument("response or out_len is null");
+        return std::ptr::null_mut();
+    }
+    let rsp = unsafe { &*(rsp_ptr as *const HttpResponse) };
+    match &rsp.body {
+        Some(body) => {
+            let (ptr, len) = rust_to_cbytes(body.clone());
+            unsafe { *out_len = len };
+            ptr
+        }
+        None => {
+            unsafe { *out_len = 0 };
+            std::ptr::null_mut()
+        }
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_http_rsp_release(rsp_ptr: *mut c_void) {
+    ngenrs_free_ptr(rsp_ptr as *mut HttpResponse)
+}
+
+// Interceptors
+
+/// Runs before every attempt of every request sent by the client, on the sending
+/// thread. `req` may be changed with the `ngenrs_http_request_*` setters but must
+/// not be released. Return false to fail the request with `ErrorCode::Cancelled`.
+pub type HttpRequestInterceptor = extern "C" fn(
+    req: *mut c_void,
+    user_data: *mut c_void,
+) -> bool;
+
+/// Inspects the request as sent and its response (readable with
+/// `ngenrs_http_parse_rsp_*`, not to be released). The response has no body for
+/// streamed and downloaded requests. Return false to fail the request.
+pub type HttpResponseInterceptor = extern "C" fn(
+    req: *mut c_void,
+    rsp: *mut c_void,
+    user_data: *mut c_void,
+) -> bool;
+
+struct CInterceptor {
+    on_request: Option<HttpRequestInterceptor>,
+    on_response: Option<HttpResponseInterceptor>,
+    user_data: UserData,
+}
+
+// Callbacks may run on any thread; the caller is responsible for making `user_data` safe to share
+unsafe impl Sync for CInterceptor {}
+
+impl HttpInterceptor for CInterceptor {
+    fn on_request(&self, request: &mut HttpRequest) -> Result<(), NGenError> {
+        match self.on_request {
+            Some(callback) if !callback(request as *mut HttpRequest as *mut c_void, self.user_data.get()) => {
+                Err(NGenError::new(ErrorCode::Cancelled, "Request rejected by interceptor"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn on_response(&self, request: &HttpRequest, response: &HttpResponse) -> Result<(), NGenError> {
+        let Some(callback) = self.on_response else {
+            return Ok(());
+        };
+        let req = request as *const HttpRequest as *mut c_void;
+        let rsp = response as *const HttpResponse as *mut c_void;
+        if callback(req, rsp, self.user_data.get()) {
+            Ok(())
+        } else {
+            Err(NGenError::new(ErrorCode::Cancelled, "Response rejected by interceptor"))
+        }
+    }
+}
+
+/// Adds an interceptor to the client; either callback may be null. `user_data`
+/// must stay valid until the interceptor is removed or the client is released.
+/// Returns an id for `ngenrs_http_client_remove_interceptor`, or 0 on error.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_http_client_add_interceptor(
+    client: *const c_void,
+    on_request: Option<HttpRequestInterceptor>,
+    on_response: Option<HttpResponseInterceptor>,
+    user_data: *mut c_void,
+) -> u64 {
+    clear_last_error();
+    if client.is_null() || (on_request.is_none() && on_response.is_none()) {
+        set_invalid_argument("client is null or no callback given");
+        return 0;
+    }
+    let client = unsafe { &*(client as *const HttpClient) };
+    client.add_interceptor(Arc::new(CInterceptor {
+        on_request,
+        on_response,
+        user_data: UserData(user_data),
+    }))
+}
+
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_http_client_remove_interceptor(client: *const c_void, id: u64) -> bool {
+    clear_last_error();
+    if client.is_null() {
+        set_invalid_argument("client is null");
+        return false;
+    }
+    let client = unsafe { &*(client as *const HttpClient) };
+    if client.remove_interceptor(id) {
+        true
+    } else {
+        set_last_error(NGenError::not_found(format!("no interceptor with id {}", id)));
+        false
+    }
+}
+
+// Response cache of clients created with `cache_path`
+
+fn client_cache(client: &HttpClient) -> Option<&crate::core::http_cache::HttpCache> {
+    let cache = client.cache();
+    if cache.is_none() {
+        set_last_error(NGenError::not_found("client has no response cache"));
+    }
+    cache
+}
+
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_http_cache_clear(client: *const c_void) -> bool {
+    clear_last_error();
+    if client.is_null() {
+        set_invalid_argument("client is null");
+        return false;
+    }
+    let client = unsafe { &*(client as *const HttpClient) };
+    let Some(cache) = client_cache(client) else { return false };
+    match cache.clear() {
+        Ok(()) => true,
+        Err(e) => {
+            set_last_error(e);
+            false
+        }
+    }
+}
+
+/// Removes the entry for `url` (including its query string); false if none was stored
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_http_cache_remove(client: *const c_void, url: *const c_char) -> bool {
+    clear_last_error();
+    if client.is_null() {
+        set_invalid_argument("client is null");
+        return false;
+    }
+    let client = unsafe { &*(client as *const HttpClient) };
+    let Some(cache) = client_cache(client) else { return false };
+    let Some(url) = cstr_to_rust(url) else {
+        set_invalid_argument("url is null");
+        return false;
+    };
+    match cache.invalidate(url) {
+        Ok(removed) => removed,
+        Err(e) => {
+            set_last_error(e);
+            false
+        }
+    }
+}
+
+/// Number of entries and total body size in bytes
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_http_cache_stats(client: *const c_void, entries_out: *mut u64, bytes_out: *mut u64) -> bool {
+    clear_last_error();
+    if client.is_null() {
+        set_invalid_argument("client is null");
+        return false;
+    }
+    let client = unsafe { &*(client as *const HttpClient) };
+    let Some(cache) = client_cache(client) else { return false };
+    match cache.entries() {
+        Ok(entries) => {
+            if !entries_out.is_null() {
+                unsafe { *entries_out = entries.len() as u64 };
+            }
+            if !bytes_out.is_null() {
+                unsafe { *bytes_out = entries.iter().map(|e| e.size).sum() };
+            }
+            true
+        }
+        Err(e) => {
+            set_last_error(e);
+            false
+        }
+    }
+}
+
+/// JSON array describing each entry (`url`, `status`, `size`, `stored_at`,
+/// `expires_at`, `last_access`; times in Unix seconds). Free with `ngenrs_free_cstr`.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_http_cache_entries(client: *const c_void) -> *mut c_char {
+    clear_last_error();
+    if client.is_null() {
+        set_invalid_argument("client is null");
+        return std::ptr::null_mut();
+    }
+    let client = unsafe { &*(client as *const HttpClient) };
+    let Some(cache) = client_cache(client) else { return std::ptr::null_mut() };
+    match cache.entries() {
+        Ok(entries) => {
+            let entries: Vec<serde_json::Value> = entries.into_iter()
+                .map(|e| serde_json::json!({
+                    "url": e.url,
+                    "status": e.status,
+                    "size": e.size,
+                    "stored_at": e.stored_at,
+                    "expires_at": e.expires_at,
+                    "last_access": e.last_access,
+                }))
+                .collect();
+            rust_to_cstr(serde_json::Value::Array(entries).to_string())
+        }
+        Err(e) => {
+            set_last_error(e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+// Cookie jar of clients created with `enable_cookies` or `cookie_path`
+
+fn client_cookie_jar(client: &HttpClient) -> Option<&CookieJar> {
+    let jar = client.cookie_jar();
+    if jar.is_none() {
+        set_last_error(NGenError::not_found("client has no cookie jar"));
+    }
+    jar
+}
+
+/// JSON array of the cookies that would be sent to `url`, or of all cookies
+/// when `url` is null. Each has `name`, `value`, `domain`, `path`, `expires`
+/// (Unix seconds or null), `secure` and `http_only`. Free with `ngenrs_free_cstr`.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_http_cookies_list(client: *const c_void, url: *const c_char) -> *mut c_char {
+    clear_last_error();
+    if client.is_null() {
+        set_invalid_argument("client is null");
+        return std::ptr::null_mut();
+    }
+    let client = unsafe { &*(client as *const HttpClient) };
+    let Some(jar) = client_cookie_jar(client) else { return std::ptr::null_mut() };
+    match jar.list(cstr_to_rust(url)) {
+        Ok(cookies) => {
+            let cookies: Vec<serde_json::Value> = cookies.into_iter()
+                .map(|c| serde_json::json!({
+                    "name": c.name,
+                    "value": c.value,
+                    "domain": c.domain,
+                    "path": c.path,
+                    "expires": c.expires,
+                    "secure": c.secure,
+                    "http_only": c.http_only,
+                }))
+                .collect();
+            rust_to_cstr(serde_json::Value::Array(cookies).to_string())
+        }
+        Err(e) => {
+            set_last_error(e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Stores `set_cookie` (a Set-Cookie header value) as if received from `url`
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_http_cookies_set(client: *const c_void, url: *const c_char, set_cookie: *const c_char) -> bool {
+    clear_last_error();
+    if client.is_null() {
+        set_invalid_argument("client is null");
+        return false;
+    }
+    let client = unsafe { &*(client as *const HttpClient) };
+    let Some(jar) = client_cookie_jar(client) else { return false };
+    let (Some(url), Some(set_cookie)) = (cstr_to_rust(url), cstr_to_rust(set_cookie)) else {
+        set_invalid_argument("url or set_cookie is null");
+        return false;
+    };
+    match jar.set(url, set_cookie) {
+        Ok(()) => true,
+        Err(e) => {
+            set_last_error(e);
+            false
+        }
+    }
+}
+
+/// Removes the cookies that would be sent to `url`, or every cookie when `url` is null
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_http_cookies_clear(client: *const c_void, url: *const c_char) -> bool {
+    clear_last_error();
+    if client.is_null() {
+        set_invalid_argument("client is null");
+        return false;
+    }
+    let client = unsafe { &*(client as *const HttpClient) };
+    let Some(jar) = client_cookie_jar(client) else { return false };
+    match jar.clear(cstr_to_rust(url)) {
+        Ok(()) => true,
+        Err(e) => {
+            set_last_error(e);
+            false
+        }
+    }
+}
+
+// WebSocket
+
+/// Receives text (`is_binary == false`, UTF-8 without terminator) and binary
+/// messages on a runtime worker thread; `data` is only valid during the call.
+pub type WsMessageCallback = extern "C" fn(
+    is_binary: bool,
+    data: *const u8,
+    len: usize,
+    user_data: *mut c_void,
+);
+
+/// Reports state changes (0 connecting, 1 open, 2 reconnecting, 3 closed).
+/// When the change follows a server close frame `close_code` is set; when it
+/// follows a failure `err_code` is set. `message` carries the close reason or
+/// error text and may be null. `user_data` must stay valid until state 3.
+pub type WsStateCallback = extern "C" fn(
+    state: i32,
+    close_code: i32,
+    err_code: i32,
+    message: *const c_char,
+    user_data: *mut c_void,
+);
+
+/// Opens a WebSocket with the client's TLS settings and returns a handle for
+/// `ngenrs_ws_send`/`ngenrs_ws_close`. `ping_interval_ms` of 0 disables keepalive.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_ws_connect(
+    client: *const c_void,
+    url: *const c_char,
+    header_keys: *const *const c_char,
+    header_values: *const *const c_char,
+    headers_len: usize,
+    ping_interval_ms: u64,
+    auto_reconnect: bool,
+    on_message: Option<WsMessageCallback>,
+    on_state: Option<WsStateCallback>,
+    user_data: *mut c_void,
+) -> *mut c_void {
+    clear_last_error();
+    if client.is_null() || url.is_null() {
+        set_invalid_argument("client or url is null");
+        return std::ptr::null_mut();
+    }
+    let Some(on_message) = on_message else {
+        set_invalid_argument("on_message is null");
+        return std::ptr::null_mut();
+    };
+    let client = unsafe { &*(client as *const HttpClient) };
+    let Some(url) = cstr_to_rust(url) else {
+        set_invalid_argument("url is not valid UTF-8");
+        return std::ptr::null_mut();
+    };
+    let headers = unsafe { rust_map_from_c_arrays(header_keys, header_values, headers_len) };
+
+    let defaults = WsConfig::default();
+    let config = WsConfig {
+        headers: headers.into_iter().flatten()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect(),
+        ping_interval: optional_millis(ping_interval_ms),
+        reconnect: if auto_reconnect { defaults.reconnect } else { None },
+    };
+
+    let user_data = UserData(user_data);
+    // Close frames and errors are reported with the state change that follows them
+    let mut close_code = 0;
+    let mut failure: Option<NGenError> = None;
+    let mut reason = String::new();
+    let (ws, driver) = client.websocket(url, config, move |event| match event {
+        WsEvent::Message(WsMessage::Text(text)) => on_message(false, text.as_ptr(), text.len(), user_data.get()),
+        WsEvent::Message(WsMessage::Binary(data)) => on_message(true, data.as_ptr(), data.len(), user_data.get()),
+        WsEvent::PeerClosed(code, close_reason) => {
+            close_code = code as i32;
+            reason = close_reason;
+        }
+        WsEvent::Error(e) => failure = Some(e),
+        WsEvent::State(state) => {
+            let Some(on_state) = on_state else { return };
+            let err_code = failure.as_ref().map_or(0, |e| e.code as i32);
+            if let Some(e) = failure.take() {
+                reason = e.message;
+            }
+            let message = (!reason.is_empty()).then(|| CString::new(std::mem::take(&mut reason)).unwrap_or_default());
+            on_state(
+                state as i32,
+                std::mem::take(&mut close_code),
+                err_code,
+                message.as_ref().map_or(std::ptr::null(), |m| m.as_ptr()),
+                user_data.get(),
+            );
+        }
+    });
+    RUNTIME.spawn(driver);
+
+    box_into_raw_new(ws) as *mut c_void
+}
+
+/// Queues a message; sent immediately when open, otherwise after reconnecting
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_ws_send(ws: *const c_void, data: *const u8, len: usize, is_binary: bool) -> bool {
+    clear_last_error();
+    if ws.is_null() || (data.is_null() && len > 0) {
+        set_invalid_argument("ws or data is null");
+        return false;
+    }
+    let ws = unsafe { &*(ws as *const WebSocket) };
+    let data = cbytes_to_rust(data, len).map(<[u8]>::to_vec).unwrap_or_default();
+    let message = if is_binary {
+        WsMessage::Binary(data)
+    } else {
+        match String::from_utf8(data) {
+            Ok(text) => WsMessage::Text(text),
+            Err(_) => {
+                set_invalid_argument("text message is not valid UTF-8");
+                return false;
+            }
+        }
+    };
+
+    match ws.send(message) {
+        Ok(()) => true,
+        Err(e) => {
+            set_last_error(e);
+            false
+        }
+    }
+}
+
+/// Starts the closing handshake with `code`/`reason` (reason may be null) and
+/// releases the handle. The state callback still fires with the final state.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_ws_close(ws: *mut c_void, code: u16, reason: *const c_char) {
+    clear_last_error();
+    if ws.is_null() {
+        return;
+    }
+    let ws = unsafe { Box::from_raw(ws as *mut WebSocket) };
+    let _ = ws.close(code, cstr_to_rust(reason).unwrap_or_default());
+}
@@ -1,14 +1,79 @@
-use crate::c::util::{box_into_raw_new, cstr_to_rust, cbytes_to_rust, ngenrs_free_ptr, rust_to_cstr};
-use crate::core::qjs::JSBridge;
+use crate::c::util::{box_into_raw_new, cstr_to_rust, cbytes_to_rust, ngenrs_free_ptr, rust_to_cstr, UserData};
+use crate::c::error::{set_last_error, clear_last_error, set_invalid_argument};
+use crate::core::error::{ErrorCode, NGenError};
+use crate::core::net::HttpClient;
+use crate::core::qjs::{JSBridge, JsRuntimeConfig, JsValue, ModuleSource};
 use libc::{c_char, c_void};
+use std::ffi::CString;
+use std::time::Duration;
 
 /// Creates a new JSBridge instance
 #[unsafe(no_mangle)]
 pub extern "C" 
 fn ngenrs_qjs_init() -> *mut c_void {
+    clear_last_error();
     box_into_raw_new(JSBridge::new()) as *mut c_void
 }
 
+/// Runtime limits for `ngenrs_qjs_init_with_config`. Obtain defaults from
+/// `ngenrs_qjs_config_default`; 0 keeps the QuickJS default (no limit for memory
+/// and execution time).
+#[repr(C)]
+pub struct CJsRuntimeConfig {
+    pub memory_limit: usize,
+    pub max_stack_size: usize,
+    pub gc_threshold: usize,
+    /// Longest a script may run per call into the bridge, in milliseconds
+    pub execution_timeout_ms: u64,
+}
+
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_qjs_config_default() -> CJsRuntimeConfig {
+    clear_last_error();
+    CJsRuntimeConfig {
+        memory_limit: 0,
+        max_stack_size: 0,
+        gc_threshold: 0,
+        execution_timeout_ms: 0,
+    }
+}
+
+/// Creates a JSBridge with the limits in `config`; a null `config` behaves like `ngenrs_qjs_init`
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_qjs_init_with_config(config: *const CJsRuntimeConfig) -> *mut c_void {
+    clear_last_error();
+    if config.is_null() {
+        return ngenrs_qjs_init();
+    }
+    let config = unsafe { &*config };
+    let nonzero = |value: usize| (value != 0).then_some(value);
+    box_into_raw_new(JSBridge::with_config(JsRuntimeConfig {
+        memory_limit: nonzero(config.memory_limit),
+        max_stack_size: nonzero(config.max_stack_size),
+        gc_threshold: nonzero(config.gc_threshold),
+        execution_timeout: (config.execution_timeout_ms != 0)
+            .then(|| Duration::from_millis(config.execution_timeout_ms)),
+    })) as *mut c_void
+}
+
+/// Aborts the script running on `handle`. Unlike the other functions it may be
+/// called from any thread while the bridge is in use; if no script is running,
+/// the next one is aborted.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_qjs_interrupt(handle: *mut c_void) -> bool {
+    clear_last_error();
+    if handle.is_null() {
+        set_invalid_argument("null argument");
+        return false;
+    }
+    let bridge = unsafe { &*(handle as *mut JSBridge) };
+    bridge.interrupt();
+    true
+}
+
 /// Common handler for loading operations
 fn _ngenrs_qjs_load<T, F>(
     handle: *mut c_void,
@@ -21,6 +86,7 @@ where
     F: FnOnce(&JSBridge, T) -> Result<(), String>,
 {
     if handle.is_null() {
+        set_invalid_argument("null argument");
         return false;
     }
 
@@ -29,8 +95,9 @@ where
         Ok(_) => true,
         Err(e) => {
             if !err_out.is_null() {
-                unsafe { *err_out = rust_to_cstr(e) };
+                unsafe { *err_out = rust_to_cstr(e.clone()) };
             }
+            set_last_error(NGenError::new(ErrorCode::Js, e));
             false
         }
     }
@@ -44,12 +111,17 @@ fn ngenrs_qjs_load_script_file(
     is_module: bool,
     err_out: *mut *mut c_char,
 ) -> bool {
+    clear_last_error();
     if path.is_null() {
+        set_invalid_argument("null argument");
         return false;
     }
     let path_str = match cstr_to_rust(path) {
         Some(s) => s,
-        None => return false,
+        None => {
+            set_invalid_argument("invalid UTF-8 string");
+            return false;
+        }
     };
     _ngenrs_qjs_load(handle, (path_str, is_module), err_out, |bridge, (path, is_module)| {
         bridge.load_script_file(path, is_module)
@@ -64,12 +136,17 @@ fn ngenrs_qjs_load_script_content(
     is_module: bool,
     err_out: *mut *mut c_char,
 ) -> bool {
+    clear_last_error();
     if script.is_null() {
+        set_invalid_argument("null argument");
         return false;
     }
     let script_str = match cstr_to_rust(script) {
         Some(s) => s,
-        None => return false,
+        None => {
+            set_invalid_argument("invalid UTF-8 string");
+            return false;
+        }
     };
     _ngenrs_qjs_load(handle, (script_str, is_module), err_out, |bridge, (script, is_module)| {
         bridge.load_script_content(script, is_module)
@@ -83,12 +160,17 @@ fn ngenrs_qjs_load_bytecode_file(
     path: *const c_char,
     err_out: *mut *mut c_char,
 ) -> bool {
+    clear_last_error();
     if path.is_null() {
+        set_invalid_argument("null argument");
         return false;
     }
     let path_str = match cstr_to_rust(path) {
         Some(s) => s,
-        None => return false,
+        None => {
+            set_invalid_argument("invalid UTF-8 string");
+            return false;
+        }
     };
     _ngenrs_qjs_load(handle, path_str, err_out, |bridge, path| {
         bridge.load_bytecode_file(path)
@@ -103,18 +185,181 @@ fn ngenrs_qjs_load_bytecode_content(
     length: usize,
     err_out: *mut *mut c_char,
 ) -> bool {
+    clear_last_error();
     if bytecode.is_null() {
+        set_invalid_argument("null argument");
         return false;
     }
     let bytecode_slice = match cbytes_to_rust(bytecode, length) {
         Some(slice) => slice,
-        None => return false,
+        None => {
+            set_invalid_argument("invalid bytecode buffer");
+            return false;
+        }
     };
     _ngenrs_qjs_load(handle, bytecode_slice, err_out, |bridge, bytecode| {
         bridge.load_bytecode_content(bytecode)
     })
 }
 
+/// Imports and evaluates the module `name` through the module loader
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_qjs_load_module(
+    handle: *mut c_void,
+    name: *const c_char,
+    err_out: *mut *mut c_char,
+) -> bool {
+    clear_last_error();
+    if name.is_null() {
+        set_invalid_argument("null argument");
+        return false;
+    }
+    let Some(name) = cstr_to_rust(name) else {
+        set_invalid_argument("invalid UTF-8 string");
+        return false;
+    };
+    _ngenrs_qjs_load(handle, name, err_out, |bridge, name| bridge.load_module(name))
+}
+
+/// Resolves module imports against `dir`; files outside it can't be imported
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_qjs_set_module_base_dir(handle: *mut c_void, dir: *const c_char) -> bool {
+    clear_last_error();
+    if handle.is_null() || dir.is_null() {
+        set_invalid_argument("null argument");
+        return false;
+    }
+    let bridge = unsafe { &*(handle as *mut JSBridge) };
+    let Some(dir) = cstr_to_rust(dir) else {
+        set_invalid_argument("invalid UTF-8 string");
+        return false;
+    };
+    bridge.set_module_base_dir(dir);
+    true
+}
+
+/// Registers module source code importable as `name`
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_qjs_add_module(handle: *mut c_void, name: *const c_char, source: *const c_char) -> bool {
+    clear_last_error();
+    if handle.is_null() || name.is_null() || source.is_null() {
+        set_invalid_argument("null argument");
+        return false;
+    }
+    let bridge = unsafe { &*(handle as *mut JSBridge) };
+    let (Some(name), Some(source)) = (cstr_to_rust(name), cstr_to_rust(source)) else {
+        set_invalid_argument("invalid UTF-8 string");
+        return false;
+    };
+    bridge.add_module(name, ModuleSource::Source(source.to_string()));
+    true
+}
+
+/// Registers module bytecode importable as `name`; it must have been compiled under that name
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_qjs_add_module_bytecode(
+    handle: *mut c_void,
+    name: *const c_char,
+    bytecode: *const u8,
+    length: usize,
+) -> bool {
+    clear_last_error();
+    if handle.is_null() || name.is_null() || bytecode.is_null() {
+        set_invalid_argument("null argument");
+        return false;
+    }
+    let bridge = unsafe { &*(handle as *mut JSBridge) };
+    let Some(name) = cstr_to_rust(name) else {
+        set_invalid_argument("invalid UTF-8 string");
+        return false;
+    };
+    let Some(bytecode) = cbytes_to_rust(bytecode, length) else {
+        set_invalid_argument("invalid bytecode buffer");
+        return false;
+    };
+    bridge.add_module(name, ModuleSource::Bytecode(bytecode.to_vec()));
+    true
+}
+
+/// Module resolver set with `ngenrs_qjs_set_module_resolver`, called with the
+/// resolved module name. Supply the module through `module` with
+/// `ngenrs_qjs_module_set_source` or `ngenrs_qjs_module_set_bytecode`; leaving
+/// it unset falls back to the module directory. `module` is only valid during the call.
+pub type QjsModuleResolver = extern "C" fn(
+    name: *const c_char,
+    module: *mut c_void,
+    user_data: *mut c_void,
+);
+
+/// Consults `callback` for modules not registered with `ngenrs_qjs_add_module*`.
+/// It is called on the thread running the script and replaces any previous resolver.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_qjs_set_module_resolver(
+    handle: *mut c_void,
+    callback: Option<QjsModuleResolver>,
+    user_data: *mut c_void,
+) -> bool {
+    clear_last_error();
+    if handle.is_null() {
+        set_invalid_argument("null argument");
+        return false;
+    }
+    let Some(callback) = callback else {
+        set_invalid_argument("callback is null");
+        return false;
+    };
+    let bridge = unsafe { &*(handle as *mut JSBridge) };
+    let user_data = UserData(user_data);
+    bridge.set_module_resolver(move |name| {
+        let name = CString::new(name).ok()?;
+        let mut module: Option<ModuleSource> = None;
+        callback(name.as_ptr(), &mut module as *mut Option<ModuleSource> as *mut c_void, user_data.get());
+        module
+    });
+    true
+}
+
+/// Supplies module source code from a module resolver
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_qjs_module_set_source(module: *mut c_void, source: *const c_char) -> bool {
+    clear_last_error();
+    if module.is_null() || source.is_null() {
+        set_invalid_argument("null argument");
+        return false;
+    }
+    let module = unsafe { &mut *(module as *mut Option<ModuleSource>) };
+    let Some(source) = cstr_to_rust(source) else {
+        set_invalid_argument("invalid UTF-8 string");
+        return false;
+    };
+    *module = Some(ModuleSource::Source(source.to_string()));
+    true
+}
+
+/// Supplies module bytecode from a module resolver
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_qjs_module_set_bytecode(module: *mut c_void, bytecode: *const u8, length: usize) -> bool {
+    clear_last_error();
+    if module.is_null() || bytecode.is_null() {
+        set_invalid_argument("null argument");
+        return false;
+    }
+    let module = unsafe { &mut *(module as *mut Option<ModuleSource>) };
+    let Some(bytecode) = cbytes_to_rust(bytecode, length) else {
+        set_invalid_argument("invalid bytecode buffer");
+        return false;
+    };
+    *module = Some(ModuleSource::Bytecode(bytecode.to_vec()));
+    true
+}
+
 /// Calls a JavaScript function with single string argument
 #[unsafe(no_mangle)]
 pub extern "C" 
@@ -125,19 +370,27 @@ fn ngenrs_qjs_call_function(
     result_out: *mut *mut c_char,
     err_out: *mut *mut c_char,
 ) -> bool {
+    clear_last_error();
     if handle.is_null() || func_name.is_null() {
+        set_invalid_argument("null argument");
         return false;
     }
 
     let bridge = unsafe { &*(handle as *mut JSBridge) };
     let func_name_str = match cstr_to_rust(func_name) {
         Some(s) => s,
-        None => return false,
+        None => {
+            set_invalid_argument("invalid UTF-8 string");
+            return false;
+        }
     };
 
     let arg_str = match cstr_to_rust(arg) {
         Some(s) => s,
-        None => return false,
+        None => {
+            set_invalid_argument("invalid UTF-8 string");
+            return false;
+        }
     };
 
     match bridge.call_function(func_name_str, arg_str) {
@@ -149,8 +402,268 @@ fn ngenrs_qjs_call_function(
         }
         Err(e) => {
             if !err_out.is_null() {
-                unsafe { *err_out = rust_to_cstr(e) };
+                unsafe { *err_out = rust_to_cstr(e.clone()) };
+            }
+            set_last_error(NGenError::new(ErrorCode::Js, e));
+            false
+        }
+    }
+}
+
+/// Calls a JavaScript function with arguments given as a JSON array (null for
+/// none) and returns the result as JSON; see `JSBridge::call`.
+/// Free `result_out` and `err_out` with `ngenrs_free_cstr`.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_qjs_call_json(
+    handle: *mut c_void,
+    func_name: *const c_char,
+    args_json: *const c_char,
+    result_out: *mut *mut c_char,
+    err_out: *mut *mut c_char,
+) -> bool {
+    clear_last_error();
+    if handle.is_null() || func_name.is_null() {
+        set_invalid_argument("null argument");
+        return false;
+    }
+    let bridge = unsafe { &*(handle as *mut JSBridge) };
+    let Some(func_name) = cstr_to_rust(func_name) else {
+        set_invalid_argument("invalid UTF-8 string");
+        return false;
+    };
+    let args: Vec<serde_json::Value> = if args_json.is_null() {
+        Vec::new()
+    } else {
+        match cstr_to_rust(args_json).map(serde_json::from_str) {
+            Some(Ok(args)) => args,
+            _ => {
+                set_invalid_argument("args_json must be a JSON array");
+                return false;
+            }
+        }
+    };
+
+    match bridge.call_json(func_name, &args) {
+        Ok(result) => {
+            if !result_out.is_null() {
+                unsafe { *result_out = rust_to_cstr(result.to_string()) };
+            }
+            true
+        }
+        Err(e) => {
+            if !err_out.is_null() {
+                unsafe { *err_out = rust_to_cstr(e.clone()) };
             }
+            set_last_error(NGenError::new(ErrorCode::Js, e));
+            false
+        }
+    }
+}
+
+/// Host function registered with `ngenrs_qjs_register_function`. `args_json` holds
+/// the call arguments as a JSON array. Report the outcome through `result` with
+/// `ngenrs_qjs_result_set_json` or `ngenrs_qjs_result_set_error`; leaving it unset
+/// returns undefined. `result` is only valid during the call.
+pub type QjsHostFunction = extern "C" fn(
+    args_json: *const c_char,
+    result: *mut c_void,
+    user_data: *mut c_void,
+);
+
+/// Exposes `callback` to scripts as the global function `name`. It is called on
+/// the thread running the script and kept until the bridge is released.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_qjs_register_function(
+    handle: *mut c_void,
+    name: *const c_char,
+    callback: Option<QjsHostFunction>,
+    user_data: *mut c_void,
+) -> bool {
+    clear_last_error();
+    if handle.is_null() || name.is_null() {
+        set_invalid_argument("null argument");
+        return false;
+    }
+    let Some(callback) = callback else {
+        set_invalid_argument("callback is null");
+        return false;
+    };
+    let bridge = unsafe { &*(handle as *mut JSBridge) };
+    let Some(name) = cstr_to_rust(name) else {
+        set_invalid_argument("invalid UTF-8 string");
+        return false;
+    };
+    let user_data = UserData(user_data);
+
+    let registered = bridge.register_function(name, move |args| {
+        let args_json = serde_json::Value::Array(args.iter().cloned().map(serde_json::Value::from).collect());
+        let args_json = CString::new(args_json.to_string()).unwrap_or_default();
+        let mut result: Result<JsValue, String> = Ok(JsValue::Undefined);
+        callback(args_json.as_ptr(), &mut result as *mut Result<JsValue, String> as *mut c_void, user_data.get());
+        result
+    });
+    match registered {
+        Ok(()) => true,
+        Err(e) => {
+            set_invalid_argument(&e);
+            false
+        }
+    }
+}
+
+/// Sets the value returned by a host function call to the parsed `json`
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_qjs_result_set_json(result: *mut c_void, json: *const c_char) -> bool {
+    clear_last_error();
+    if result.is_null() || json.is_null() {
+        set_invalid_argument("null argument");
+        return false;
+    }
+    let result = unsafe { &mut *(result as *mut Result<JsValue, String>) };
+    match cstr_to_rust(json).map(serde_json::from_str::<serde_json::Value>) {
+        Some(Ok(value)) => {
+            *result = Ok(value.into());
+            true
+        }
+        _ => {
+            set_invalid_argument("json is not valid JSON");
+            false
+        }
+    }
+}
+
+/// Makes a host function call throw an `Error` with `message`
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_qjs_result_set_error(result: *mut c_void, message: *const c_char) -> bool {
+    clear_last_error();
+    if result.is_null() || message.is_null() {
+        set_invalid_argument("null argument");
+        return false;
+    }
+    let result = unsafe { &mut *(result as *mut Result<JsValue, String>) };
+    *result = Err(cstr_to_rust(message).unwrap_or("Host function failed").to_string());
+    true
+}
+
+/// Registers global JS functions as an HTTP interceptor on `client` (see
+/// `JSBridge::add_http_interceptor`); either name may be null. Returns an id
+/// for `ngenrs_http_client_remove_interceptor`, or 0 on error.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_qjs_add_http_interceptor(
+    handle: *mut c_void,
+    client: *const c_void,
+    on_request: *const c_char,
+    on_response: *const c_char,
+) -> u64 {
+    clear_last_error();
+    if handle.is_null() || client.is_null() || (on_request.is_null() && on_response.is_null()) {
+        set_invalid_argument("null argument");
+        return 0;
+    }
+    if [on_request, on_response].iter().any(|&name| !name.is_null() && cstr_to_rust(name).is_none()) {
+        set_invalid_argument("hook name is not valid UTF-8");
+        return 0;
+    }
+    let bridge = unsafe { &*(handle as *mut JSBridge) };
+    let client = unsafe { &*(client as *const HttpClient) };
+    match bridge.add_http_interceptor(client, cstr_to_rust(on_request), cstr_to_rust(on_response)) {
+        Ok(id) => id,
+        Err(e) => {
+            set_invalid_argument(&e);
+            0
+        }
+    }
+}
+
+/// Uses `client` for the script `fetch` instead of a default client.
+/// The client is shared, the caller keeps ownership of its handle.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_qjs_set_http_client(handle: *mut c_void, client: *const c_void) -> bool {
+    clear_last_error();
+    if handle.is_null() || client.is_null() {
+        set_invalid_argument("null argument");
+        return false;
+    }
+    let bridge = unsafe { &*(handle as *mut JSBridge) };
+    let client = unsafe { &*(client as *const HttpClient) };
+    bridge.set_http_client(client.clone());
+    true
+}
+
+/// Runs one event loop turn (see `JSBridge::poll`); call it from the host's main
+/// loop. Returns the number of host operations still pending, or -1 on error.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_qjs_poll(handle: *mut c_void) -> i32 {
+    clear_last_error();
+    if handle.is_null() {
+        set_invalid_argument("null argument");
+        return -1;
+    }
+    let bridge = unsafe { &*(handle as *mut JSBridge) };
+    match bridge.poll() {
+        Ok(pending) => pending as i32,
+        Err(e) => {
+            set_last_error(NGenError::new(ErrorCode::Js, e));
+            -1
+        }
+    }
+}
+
+/// Runs queued Promise jobs. Returns how many ran, or -1 on error.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_qjs_run_pending_jobs(handle: *mut c_void) -> i32 {
+    clear_last_error();
+    if handle.is_null() {
+        set_invalid_argument("null argument");
+        return -1;
+    }
+    let bridge = unsafe { &*(handle as *mut JSBridge) };
+    match bridge.run_pending_jobs() {
+        Ok(ran) => ran as i32,
+        Err(e) => {
+            set_last_error(NGenError::new(ErrorCode::Js, e));
+            -1
+        }
+    }
+}
+
+/// Milliseconds until the next JS timer is due (0 if overdue), or -1 without timers
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_qjs_next_timer_delay(handle: *mut c_void) -> i64 {
+    clear_last_error();
+    if handle.is_null() {
+        set_invalid_argument("null argument");
+        return -1;
+    }
+    let bridge = unsafe { &*(handle as *mut JSBridge) };
+    bridge.next_timer_delay().map_or(-1, |delay| delay.as_millis() as i64)
+}
+
+/// Drives the event loop until nothing is pending. A negative `timeout_ms` waits
+/// without limit; on timeout the call fails with pending operations left.
+#[unsafe(no_mangle)]
+pub extern "C"
+fn ngenrs_qjs_run_until_idle(handle: *mut c_void, timeout_ms: i64) -> bool {
+    clear_last_error();
+    if handle.is_null() {
+        set_invalid_argument("null argument");
+        return false;
+    }
+    let bridge = unsafe { &*(handle as *mut JSBridge) };
+    let timeout = u64::try_from(timeout_ms).ok().map(std::time::Duration::from_millis);
+    match bridge.run_until_idle(timeout) {
+        Ok(()) => true,
+        Err(e) => {
+            set_last_error(NGenError::new(ErrorCode::Js, e));
             false
         }
     }
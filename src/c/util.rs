@@ -1,9 +1,21 @@
 use std::collections::HashMap;
 use std::ffi::{CStr, CString};
-use std::os::raw::c_char;
+use std::os::raw::{c_char, c_void};
 use libc;
 use std::slice;
 
+/// Caller-supplied `user_data` pointer carried to callbacks on other threads
+pub(crate) struct UserData(pub(crate) *mut c_void);
+
+// The pointer is opaque to us and only handed back to the caller's callback
+unsafe impl Send for UserData {}
+
+impl UserData {
+    pub(crate) fn get(&self) -> *mut c_void {
+        self.0
+    }
+}
+
 /// Utility function to convert C string to Rust string (safe wrapper)
 pub fn cstr_to_rust(cstr: *const c_char) -> Option<&'static str> {
     if cstr.is_null() {
@@ -3,6 +3,8 @@ use std::os::raw::c_int;
 use std::io;
 use crate::core::zip::{CompressionFormat, compress, decompress};
 use crate::c::util::{cbytes_to_rust, rust_to_cbytes};
+use crate::c::error::{set_last_error, clear_last_error, set_invalid_argument};
+use crate::core::error::{ErrorCode, NGenError};
 
 fn _ngenrs_z_process(
     format: c_int,
@@ -16,16 +18,23 @@ fn _ngenrs_z_process(
         0 => CompressionFormat::Gzip,
         1 => CompressionFormat::Zlib,
         2 => CompressionFormat::Raw,
-        _ => return std::ptr::null_mut(),
+        _ => {
+            set_invalid_argument("unknown compression format");
+            return std::ptr::null_mut();
+        }
     };
 
     if input.is_null() || output.is_null() || output_len.is_null() {
+        set_invalid_argument("null argument");
         return std::ptr::null_mut();
     }
 
     let input_slice = match cbytes_to_rust(input, input_len) {
         Some(slice) => slice,
-        None => return CString::new("Invalid input buffer").unwrap().into_raw() as *mut u8,
+        None => {
+            set_invalid_argument("Invalid input buffer");
+            return CString::new("Invalid input buffer").unwrap().into_raw() as *mut u8;
+        }
     };
 
     // Then update the error handling in the match statement:
@@ -38,7 +47,10 @@ fn _ngenrs_z_process(
             }
             std::ptr::null_mut()
         }
-        Err(e) => CString::new(e.to_string()).unwrap().into_raw() as *mut u8,
+        Err(e) => {
+            set_last_error(NGenError::new(ErrorCode::Compression, e.to_string()));
+            CString::new(e.to_string()).unwrap().into_raw() as *mut u8
+        }
     }
 }
 
@@ -51,6 +63,7 @@ fn ngenrs_z_compress(
     output_len: *mut usize,
     format: c_int,
 ) -> *mut u8 {
+    clear_last_error();
     _ngenrs_z_process(format, input, input_len, output, output_len, compress)
 }
 
@@ -63,5 +76,6 @@ fn ngenrs_z_decompress(
     output_len: *mut usize,
     format: c_int,
 ) -> *mut u8 {
+    clear_last_error();
     _ngenrs_z_process(format, input, input_len, output, output_len, decompress)
 }
\ No newline at end of file
@@ -0,0 +1,238 @@
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+use cookie_store::{CookieExpiration, CookieStore, RawCookie};
+use reqwest::Url;
+use reqwest::header::HeaderValue;
+use crate::core::error::{ErrorCode, NGenError};
+
+#[derive(Clone, Debug)]
+pub struct CookieInfo {
+    pub name: String,
+    pub value: String,
+    pub domain: String,
+    pub path: String,
+    /// Unix timestamp in seconds; `None` for session cookies
+    pub expires: Option<i64>,
+    pub secure: bool,
+    pub http_only: bool,
+}
+
+/// RFC 6265 cookie store for `HttpClient`, optionally persisted as JSON.
+/// Persistent jars are saved on a blocking thread whenever a response changes them.
+#[derive(Debug)]
+pub struct CookieJar {
+    store: RwLock<CookieStore>,
+    path: Option<PathBuf>,
+    persist_session_cookies: bool,
+    writer: Arc<JarWriter>,
+}
+
+/// Serializes writes of the jar file and holds the newest snapshot not yet written
+#[derive(Debug, Default)]
+struct JarWriter {
+    pending: Mutex<Option<Vec<u8>>>,
+    file: Mutex<()>,
+}
+
+impl JarWriter {
+    /// Writes queued snapshots until none is left; bursts collapse into the latest one
+    fn flush(&self, path: &Path) {
+        let _file = self.file.lock().unwrap();
+        loop {
+            let Some(data) = self.pending.lock().unwrap().take() else {
+                return;
+            };
+            // Failing to persist must not fail the response
+            let _ = write_file(path, &data);
+        }
+    }
+}
+
+impl Default for CookieJar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CookieJar {
+    /// In-memory jar, lost when the process exits
+    pub fn new() -> Self {
+        Self {
+            store: RwLock::new(CookieStore::default()),
+            path: None,
+            persist_session_cookies: false,
+            writer: Arc::default(),
+        }
+    }
+
+    /// Loads the jar saved at `path`, starting empty if it does not exist yet.
+    /// Session cookies (no Expires/Max-Age) are only kept across restarts
+    /// when `persist_session_cookies` is set.
+    pub fn open(path: impl AsRef<Path>, persist_session_cookies: bool) -> Result<Self, NGenError> {
+        let path = path.as_ref().to_path_buf();
+        let store = match std::fs::File::open(&path) {
+            Ok(file) => {
+                let reader = BufReader::new(file);
+                let loaded = if persist_session_cookies {
+                    CookieStore::load_json_all(reader)
+                } else {
+                    CookieStore::load_json(reader)
+                };
+                loaded.map_err(|e| NGenError::new(ErrorCode::Io, e.to_string()))?
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => CookieStore::default(),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(Self {
+            store: RwLock::new(store),
+            path: Some(path),
+            persist_session_cookies,
+            writer: Arc::default(),
+        })
+    }
+
+    /// Writes the jar to its file; a no-op for in-memory jars
+    pub fn save(&self) -> Result<(), NGenError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let _file = self.writer.file.lock().unwrap();
+        // The snapshot taken below supersedes anything still queued
+        self.writer.pending.lock().unwrap().take();
+        write_file(path, &self.snapshot()?)?;
+        Ok(())
+    }
+
+    /// Queues the current contents for the background writer
+    fn save_in_background(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let Ok(data) = self.snapshot() else {
+            return;
+        };
+        if self.writer.pending.lock().unwrap().replace(data).is_some() {
+            // A writer is already scheduled and will pick up this snapshot
+            return;
+        }
+        let writer = self.writer.clone();
+        let path = path.clone();
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                handle.spawn_blocking(move || writer.flush(&path));
+            }
+            Err(_) => writer.flush(&path),
+        }
+    }
+
+    fn snapshot(&self) -> Result<Vec<u8>, NGenError> {
+        let mut data = Vec::new();
+        let store = self.store.read().unwrap();
+        let saved = if self.persist_session_cookies {
+            store.save_incl_expired_and_nonpersistent_json(&mut data)
+        } else {
+            store.save_json(&mut data)
+        };
+        saved.map_err(|e| NGenError::new(ErrorCode::Io, e.to_string()))?;
+        Ok(data)
+    }
+
+    /// Unexpired cookies that would be sent to `url`, or every stored cookie when `url` is `None`
+    pub fn list(&self, url: Option<&str>) -> Result<Vec<CookieInfo>, NGenError> {
+        let store = self.store.read().unwrap();
+        let cookies = match url {
+            Some(url) => store.matches(&parse_url(url)?),
+            None => store.iter_unexpired().collect(),
+        };
+        Ok(cookies.into_iter()
+            .map(|cookie| CookieInfo {
+                name: cookie.name().to_string(),
+                value: cookie.value().to_string(),
+                domain: String::from(&cookie.domain),
+                path: String::from(&cookie.path),
+                expires: match &cookie.expires {
+                    CookieExpiration::AtUtc(at) => Some(at.unix_timestamp()),
+                    CookieExpiration::SessionEnd => None,
+                },
+                secure: cookie.secure().unwrap_or(false),
+                http_only: cookie.http_only().unwrap_or(false),
+            })
+            .collect())
+    }
+
+    /// Stores a cookie as if `set_cookie` had arrived in a Set-Cookie header from `url`
+    pub fn set(&self, url: &str, set_cookie: &str) -> Result<(), NGenError> {
+        let url = parse_url(url)?;
+        self.store.write().unwrap()
+            .parse(set_cookie, &url)
+            .map_err(|e| NGenError::invalid_argument(e.to_string()))?;
+        self.save()
+    }
+
+    /// Removes the cookies that would be sent to `url`, or all cookies when `url` is `None`
+    pub fn clear(&self, url: Option<&str>) -> Result<(), NGenError> {
+        {
+            let mut store = self.store.write().unwrap();
+            match url {
+                Some(url) => {
+                    let url = parse_url(url)?;
+                    let matched: Vec<(String, String, String)> = store.matches(&url)
+                        .into_iter()
+                        .map(|c| (String::from(&c.domain), String::from(&c.path), c.name().to_string()))
+                        .collect();
+                    for (domain, path, name) in matched {
+                        store.remove(&domain, &path, &name);
+                    }
+                }
+                None => store.clear(),
+            }
+        }
+        self.save()
+    }
+}
+
+impl reqwest::cookie::CookieStore for CookieJar {
+    fn set_cookies(&self, cookie_headers: &mut dyn Iterator<Item = &HeaderValue>, url: &Url) {
+        let cookies = cookie_headers
+            .filter_map(|value| value.to_str().ok())
+            .filter_map(|value| RawCookie::parse(value.to_string()).ok())
+            .collect::<Vec<_>>();
+        if cookies.is_empty() {
+            return;
+        }
+        self.store.write().unwrap().store_response_cookies(cookies.into_iter(), url);
+        self.save_in_background();
+    }
+
+    fn cookies(&self, url: &Url) -> Option<HeaderValue> {
+        let header = self.store.read().unwrap()
+            .get_request_values(url)
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>()
+            .join("; ");
+        if header.is_empty() {
+            return None;
+        }
+        HeaderValue::from_str(&header).ok()
+    }
+}
+
+/// Write-then-rename so a crash never leaves a truncated jar behind. Every
+/// write uses its own temporary file.
+fn write_file(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    static NEXT_TMP: AtomicU64 = AtomicU64::new(0);
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(format!(".{}.{}.tmp", std::process::id(), NEXT_TMP.fetch_add(1, Ordering::Relaxed)));
+    let tmp_path = PathBuf::from(tmp_path);
+    std::fs::write(&tmp_path, data)?;
+    std::fs::rename(&tmp_path, path).inspect_err(|_| {
+        let _ = std::fs::remove_file(&tmp_path);
+    })
+}
+
+fn parse_url(url: &str) -> Result<Url, NGenError> {
+    Url::parse(url).map_err(|e| NGenError::invalid_argument(format!("invalid url {}: {}", url, e)))
+}
@@ -1,6 +1,8 @@
 use hex;
-use aes::Aes256;
-use block_modes::{BlockMode, Ecb};
+use aes::{Aes128, Aes256};
+use aes_gcm::{Aes128Gcm, Aes256Gcm, Nonce};
+use aes_gcm::aead::{Aead, NewAead, Payload};
+use block_modes::{BlockMode, Cbc, Ecb};
 use block_modes::block_padding::Pkcs7;
 use rsa::PublicKey;
 use rsa::{
@@ -8,9 +10,20 @@ use rsa::{
     RsaPublicKey, 
     pkcs1::{
         DecodeRsaPrivateKey, 
-        DecodeRsaPublicKey
+        DecodeRsaPublicKey,
+        EncodeRsaPrivateKey,
+        EncodeRsaPublicKey
     }, 
+    pkcs8::{
+        DecodePrivateKey,
+        DecodePublicKey,
+        EncodePrivateKey,
+        EncodePublicKey,
+        LineEnding
+    },
     Pkcs1v15Encrypt, 
+    Pkcs1v15Sign,
+    Pss,
     Oaep
 };
 use rsa::rand_core::OsRng;
@@ -19,6 +32,7 @@ use sha1::Sha1;
 use sha2::Sha256;
 use base64::{Engine as _, engine::general_purpose};
 use std::error::Error;
+use crate::core::error::NGenError;
 
 pub fn str2bytes(s: String) -> Vec<u8> {
     s.into_bytes()
@@ -61,6 +75,92 @@ impl Aes256EcbPkcs5 {
     }
 }
 
+type Aes128Cbc = Cbc<Aes128, Pkcs7>;
+type Aes256Cbc = Cbc<Aes256, Pkcs7>;
+
+pub struct AesCbcPkcs5 {
+    key: Vec<u8>,
+}
+
+impl AesCbcPkcs5 {
+    pub fn new(key: &[u8]) -> Result<Self, Box<dyn Error>> {
+        if !matches!(key.len(), 16 | 32) {
+            return Err("Key must be 16 or 32 bytes (128 or 256 bits)".into());
+        }
+        Ok(Self { key: key.to_vec() })
+    }
+
+    pub fn enc(&self, iv: &[u8], data: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        if self.key.len() == 16 {
+            Ok(Aes128Cbc::new_from_slices(&self.key, iv)?.encrypt_vec(data))
+        } else {
+            Ok(Aes256Cbc::new_from_slices(&self.key, iv)?.encrypt_vec(data))
+        }
+    }
+
+    pub fn dec(&self, iv: &[u8], data: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        if self.key.len() == 16 {
+            Ok(Aes128Cbc::new_from_slices(&self.key, iv)?.decrypt_vec(data)?)
+        } else {
+            Ok(Aes256Cbc::new_from_slices(&self.key, iv)?.decrypt_vec(data)?)
+        }
+    }
+}
+
+pub const AES_GCM_NONCE_LEN: usize = 12;
+pub const AES_GCM_TAG_LEN: usize = 16;
+
+enum AesGcmCipher {
+    Aes128(Box<Aes128Gcm>),
+    Aes256(Box<Aes256Gcm>),
+}
+
+/// AES-GCM with a random 96-bit nonce per message.
+/// Output layout is `nonce || ciphertext || tag`, which `dec` expects back.
+pub struct AesGcm {
+    cipher: AesGcmCipher,
+}
+
+impl AesGcm {
+    pub fn new(key: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let cipher = match key.len() {
+            16 => AesGcmCipher::Aes128(Box::new(Aes128Gcm::new_from_slice(key).map_err(|_| "Invalid key")?)),
+            32 => AesGcmCipher::Aes256(Box::new(Aes256Gcm::new_from_slice(key).map_err(|_| "Invalid key")?)),
+            _ => return Err("Key must be 16 or 32 bytes (128 or 256 bits)".into()),
+        };
+        Ok(Self { cipher })
+    }
+
+    pub fn enc(&self, data: &[u8], aad: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        let nonce_bytes: [u8; AES_GCM_NONCE_LEN] = rand::random();
+        let nonce = Nonce::from_slice(&nonce_bytes);
+        let payload = Payload { msg: data, aad };
+        let sealed = match &self.cipher {
+            AesGcmCipher::Aes128(c) => c.encrypt(nonce, payload),
+            AesGcmCipher::Aes256(c) => c.encrypt(nonce, payload),
+        }.map_err(|_| "AES-GCM encryption failed")?;
+
+        let mut out = Vec::with_capacity(AES_GCM_NONCE_LEN + sealed.len());
+        out.extend_from_slice(&nonce_bytes);
+        out.extend_from_slice(&sealed);
+        Ok(out)
+    }
+
+    pub fn dec(&self, data: &[u8], aad: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        if data.len() < AES_GCM_NONCE_LEN + AES_GCM_TAG_LEN {
+            return Err("Ciphertext too short".into());
+        }
+        let (nonce_bytes, sealed) = data.split_at(AES_GCM_NONCE_LEN);
+        let nonce = Nonce::from_slice(nonce_bytes);
+        let payload = Payload { msg: sealed, aad };
+        let plain = match &self.cipher {
+            AesGcmCipher::Aes128(c) => c.decrypt(nonce, payload),
+            AesGcmCipher::Aes256(c) => c.decrypt(nonce, payload),
+        }.map_err(|_| "AES-GCM authentication failed")?;
+        Ok(plain)
+    }
+}
+
 #[derive(Clone, Copy)]
 pub enum RsaPadding {
     Pkcs1v15 = 0,
@@ -78,51 +178,167 @@ impl From<i32> for RsaPadding {
     }
 }
 
-pub fn rsa_enc(input: Vec<u8>, pub_key: Vec<u8>, padding: i32) -> Vec<u8> {
-    if !matches!(pub_key.len(), 16 | 24 | 32) {
-        return Vec::new();
+#[derive(Clone, Copy)]
+pub enum RsaKeyFormat {
+    Pkcs1Der = 0,
+    Pkcs1Pem = 1,
+    Pkcs8Der = 2,
+    Pkcs8Pem = 3,
+}
+
+impl TryFrom<i32> for RsaKeyFormat {
+    type Error = NGenError;
+
+    fn try_from(value: i32) -> Result<Self, NGenError> {
+        match value {
+            0 => Ok(RsaKeyFormat::Pkcs1Der),
+            1 => Ok(RsaKeyFormat::Pkcs1Pem),
+            2 => Ok(RsaKeyFormat::Pkcs8Der),
+            3 => Ok(RsaKeyFormat::Pkcs8Pem),
+            _ => Err(NGenError::invalid_argument(format!("Unknown RSA key format {}", value))),
+        }
     }
+}
 
-    let public_key = match RsaPublicKey::from_pkcs1_der(&pub_key) {
-        Ok(key) => key,
-        Err(_) => return Vec::new(),
-    };
+#[derive(Clone, Copy)]
+pub enum RsaSignScheme {
+    Pkcs1v15Sha256 = 0,
+    PssSha256 = 1,
+}
+
+impl TryFrom<i32> for RsaSignScheme {
+    type Error = NGenError;
+
+    fn try_from(value: i32) -> Result<Self, NGenError> {
+        match value {
+            0 => Ok(RsaSignScheme::Pkcs1v15Sha256),
+            1 => Ok(RsaSignScheme::PssSha256),
+            _ => Err(NGenError::invalid_argument(format!("Unknown RSA signature scheme {}", value))),
+        }
+    }
+}
+
+fn as_pem(key: &[u8]) -> Option<&str> {
+    let s = std::str::from_utf8(key).ok()?;
+    if s.trim_start().starts_with("-----BEGIN") { Some(s) } else { None }
+}
+
+/// Parses a private key in PKCS#1 or PKCS#8, DER or PEM.
+pub fn rsa_import_private_key(key: &[u8]) -> Result<RsaPrivateKey, Box<dyn Error>> {
+    match as_pem(key) {
+        Some(pem) if pem.contains("BEGIN RSA PRIVATE KEY") => Ok(RsaPrivateKey::from_pkcs1_pem(pem)?),
+        Some(pem) => Ok(RsaPrivateKey::from_pkcs8_pem(pem)?),
+        None => RsaPrivateKey::from_pkcs1_der(key)
+            .or_else(|_| RsaPrivateKey::from_pkcs8_der(key))
+            .map_err(|e| e.into()),
+    }
+}
+
+/// Parses a public key in PKCS#1 or SPKI (PKCS#8), DER or PEM.
+pub fn rsa_import_public_key(key: &[u8]) -> Result<RsaPublicKey, Box<dyn Error>> {
+    match as_pem(key) {
+        Some(pem) if pem.contains("BEGIN RSA PUBLIC KEY") => Ok(RsaPublicKey::from_pkcs1_pem(pem)?),
+        Some(pem) => Ok(RsaPublicKey::from_public_key_pem(pem)?),
+        None => RsaPublicKey::from_pkcs1_der(key)
+            .or_else(|_| RsaPublicKey::from_public_key_der(key))
+            .map_err(|e| e.into()),
+    }
+}
+
+pub fn rsa_export_private_key(key: &RsaPrivateKey, format: RsaKeyFormat) -> Result<Vec<u8>, Box<dyn Error>> {
+    Ok(match format {
+        RsaKeyFormat::Pkcs1Der => key.to_pkcs1_der()?.as_bytes().to_vec(),
+        RsaKeyFormat::Pkcs1Pem => key.to_pkcs1_pem(LineEnding::LF)?.as_bytes().to_vec(),
+        RsaKeyFormat::Pkcs8Der => key.to_pkcs8_der()?.as_bytes().to_vec(),
+        RsaKeyFormat::Pkcs8Pem => key.to_pkcs8_pem(LineEnding::LF)?.as_bytes().to_vec(),
+    })
+}
+
+pub fn rsa_export_public_key(key: &RsaPublicKey, format: RsaKeyFormat) -> Result<Vec<u8>, Box<dyn Error>> {
+    Ok(match format {
+        RsaKeyFormat::Pkcs1Der => key.to_pkcs1_der()?.as_bytes().to_vec(),
+        RsaKeyFormat::Pkcs1Pem => key.to_pkcs1_pem(LineEnding::LF)?.into_bytes(),
+        RsaKeyFormat::Pkcs8Der => key.to_public_key_der()?.as_bytes().to_vec(),
+        RsaKeyFormat::Pkcs8Pem => key.to_public_key_pem(LineEnding::LF)?.into_bytes(),
+    })
+}
+
+/// Generates a keypair and returns `(private_key, public_key)` encoded in `format`.
+pub fn rsa_generate(bits: usize, format: RsaKeyFormat) -> Result<(Vec<u8>, Vec<u8>), Box<dyn Error>> {
+    if !matches!(bits, 2048 | 3072 | 4096) {
+        return Err("Key size must be 2048, 3072 or 4096 bits".into());
+    }
+    let private_key = RsaPrivateKey::new(&mut OsRng, bits)?;
+    let public_key = private_key.to_public_key();
+    Ok((
+        rsa_export_private_key(&private_key, format)?,
+        rsa_export_public_key(&public_key, format)?,
+    ))
+}
+
+pub fn rsa_enc(input: Vec<u8>, pub_key: Vec<u8>, padding: i32) -> Result<Vec<u8>, Box<dyn Error>> {
+    let public_key = rsa_import_public_key(&pub_key)?;
 
     let mut rng = OsRng;
-    match RsaPadding::from(padding) {
+    let out = match RsaPadding::from(padding) {
         RsaPadding::Pkcs1v15 => {
-            public_key.encrypt(&mut rng, Pkcs1v15Encrypt, &input).unwrap_or_default()
+            public_key.encrypt(&mut rng, Pkcs1v15Encrypt, &input)?
         },
         RsaPadding::OaepSha256 => {
-            public_key.encrypt(&mut rng, Oaep::new::<Sha256>(), &input).unwrap_or_default()
+            public_key.encrypt(&mut rng, Oaep::new::<Sha256>(), &input)?
         },
         RsaPadding::None => {
-            public_key.encrypt(&mut rng, Pkcs1v15Encrypt, &input).unwrap_or_default()
+            public_key.encrypt(&mut rng, Pkcs1v15Encrypt, &input)?
         }
-    }
+    };
+    Ok(out)
 }
 
-pub fn rsa_dec(input: Vec<u8>, private_key: Vec<u8>, padding: i32) -> Vec<u8> {
-    if !matches!(private_key.len(), 16 | 24 | 32) {
-        return Vec::new();
-    }
+pub fn rsa_dec(input: Vec<u8>, private_key: Vec<u8>, padding: i32) -> Result<Vec<u8>, Box<dyn Error>> {
+    let private_key = rsa_import_private_key(&private_key)?;
 
-    let private_key = match RsaPrivateKey::from_pkcs1_der(&private_key) {
-        Ok(key) => key,
-        Err(_) => return Vec::new(),
-    };
-
-    match RsaPadding::from(padding) {
+    let out = match RsaPadding::from(padding) {
         RsaPadding::Pkcs1v15 => {
-            private_key.decrypt(Pkcs1v15Encrypt, &input).unwrap_or_default()
+            private_key.decrypt(Pkcs1v15Encrypt, &input)?
         },
         RsaPadding::OaepSha256 => {
-            private_key.decrypt(Oaep::new::<Sha256>(), &input).unwrap_or_default()
+            private_key.decrypt(Oaep::new::<Sha256>(), &input)?
         },
         RsaPadding::None => {
-            private_key.decrypt(Pkcs1v15Encrypt, &input).unwrap_or_default()
+            private_key.decrypt(Pkcs1v15Encrypt, &input)?
+        }
+    };
+    Ok(out)
+}
+
+/// Signs the SHA-256 digest of `data`.
+pub fn rsa_sign(data: &[u8], private_key: &[u8], scheme: RsaSignScheme) -> Result<Vec<u8>, Box<dyn Error>> {
+    let private_key = rsa_import_private_key(private_key)?;
+    let digest = hash_sha256(data);
+    let sig = match scheme {
+        RsaSignScheme::Pkcs1v15Sha256 => {
+            private_key.sign(Pkcs1v15Sign::new::<Sha256>(), &digest)?
+        },
+        RsaSignScheme::PssSha256 => {
+            private_key.sign_with_rng(&mut OsRng, Pss::new::<Sha256>(), &digest)?
+        }
+    };
+    Ok(sig)
+}
+
+/// Verifies a signature over the SHA-256 digest of `data`.
+pub fn rsa_verify(data: &[u8], signature: &[u8], public_key: &[u8], scheme: RsaSignScheme) -> Result<(), Box<dyn Error>> {
+    let public_key = rsa_import_public_key(public_key)?;
+    let digest = hash_sha256(data);
+    match scheme {
+        RsaSignScheme::Pkcs1v15Sha256 => {
+            public_key.verify(Pkcs1v15Sign::new::<Sha256>(), &digest, signature)?
+        },
+        RsaSignScheme::PssSha256 => {
+            public_key.verify(Pss::new::<Sha256>(), &digest, signature)?
         }
     }
+    Ok(())
 }
 
 pub fn hash<D: Digest>(data: &[u8]) -> Vec<u8> {
@@ -25,6 +25,22 @@ impl DB {
         Ok(())
     }
 
+    /// Runs `sql` with positional `params` and returns the number of changed rows
+    pub fn execute(&self, sql: &str, params: &[Value]) -> Result<usize, rusqlite::Error> {
+        self.conn.execute(sql, rusqlite::params_from_iter(params))
+    }
+
+    /// Runs `sql` with positional `params` and returns the column names and every row
+    pub fn query_rows(&self, sql: &str, params: &[Value]) -> Result<(Vec<String>, Vec<Vec<Value>>), rusqlite::Error> {
+        let mut stmt = self.conn.prepare(sql)?;
+        let columns: Vec<String> = stmt.column_names().iter().map(|s| s.to_string()).collect();
+        let rows = stmt.query_map(rusqlite::params_from_iter(params), |row| {
+            (0..columns.len()).map(|idx| row.get::<_, Value>(idx)).collect()
+        })?;
+        let rows = rows.collect::<Result<Vec<_>, _>>()?;
+        Ok((columns, rows))
+    }
+
     pub fn query(&mut self, sql: &str) -> Result<QueryResult, rusqlite::Error> {
         let stmt = self.conn.prepare(sql)?;
         let columns = stmt.column_names().iter().map(|s| s.to_string()).collect();
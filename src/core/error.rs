@@ -0,0 +1,135 @@
+use std::fmt;
+
+/// Stable numeric error codes shared by every module and exposed through the C ABI.
+/// Values must never be renumbered; append new variants at the end.
+#[repr(i32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorCode {
+    Ok = 0,
+    Unknown = 1,
+    InvalidArgument = 2,
+    NotFound = 3,
+    Io = 4,
+    Utf8 = 5,
+    Crypto = 6,
+    Database = 7,
+    Kv = 8,
+    Network = 9,
+    Timeout = 10,
+    Cancelled = 11,
+    Lua = 12,
+    Js = 13,
+    Compression = 14,
+    ChecksumMismatch = 15,
+    CertificatePinMismatch = 16,
+}
+
+#[derive(Debug, Clone)]
+pub struct NGenError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl NGenError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self { code, message: message.into() }
+    }
+
+    pub fn invalid_argument(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::InvalidArgument, message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::NotFound, message)
+    }
+
+    /// Converts a boxed error, recovering the specific code when the
+    /// underlying error is one of the library types we know about.
+    pub fn from_boxed(err: Box<dyn std::error::Error>, fallback: ErrorCode) -> Self {
+        let err = match err.downcast::<NGenError>() {
+            Ok(e) => return *e,
+            Err(err) => err,
+        };
+        let err = match err.downcast::<reqwest::Error>() {
+            Ok(e) => return (*e).into(),
+            Err(err) => err,
+        };
+        let err = match err.downcast::<std::io::Error>() {
+            Ok(e) => return (*e).into(),
+            Err(err) => err,
+        };
+        Self::new(fallback, err.to_string())
+    }
+}
+
+impl fmt::Display for NGenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for NGenError {}
+
+impl From<std::io::Error> for NGenError {
+    fn from(err: std::io::Error) -> Self {
+        let code = match err.kind() {
+            std::io::ErrorKind::NotFound => ErrorCode::NotFound,
+            std::io::ErrorKind::TimedOut => ErrorCode::Timeout,
+            _ => ErrorCode::Io,
+        };
+        Self::new(code, err.to_string())
+    }
+}
+
+impl From<std::str::Utf8Error> for NGenError {
+    fn from(err: std::str::Utf8Error) -> Self {
+        Self::new(ErrorCode::Utf8, err.to_string())
+    }
+}
+
+impl From<rusqlite::Error> for NGenError {
+    fn from(err: rusqlite::Error) -> Self {
+        let code = match err {
+            rusqlite::Error::QueryReturnedNoRows => ErrorCode::NotFound,
+            rusqlite::Error::InvalidColumnName(_) => ErrorCode::NotFound,
+            rusqlite::Error::NulError(_) | rusqlite::Error::InvalidParameterName(_) => ErrorCode::InvalidArgument,
+            _ => ErrorCode::Database,
+        };
+        Self::new(code, err.to_string())
+    }
+}
+
+impl From<redb::Error> for NGenError {
+    fn from(err: redb::Error) -> Self {
+        let code = match err {
+            redb::Error::TableDoesNotExist(_) => ErrorCode::NotFound,
+            redb::Error::Io(_) => ErrorCode::Io,
+            _ => ErrorCode::Kv,
+        };
+        Self::new(code, err.to_string())
+    }
+}
+
+impl From<reqwest::Error> for NGenError {
+    fn from(err: reqwest::Error) -> Self {
+        let code = if err.is_timeout() {
+            ErrorCode::Timeout
+        } else if err.is_builder() {
+            ErrorCode::InvalidArgument
+        } else {
+            ErrorCode::Network
+        };
+        Self::new(code, err.to_string())
+    }
+}
+
+impl From<mlua::Error> for NGenError {
+    fn from(err: mlua::Error) -> Self {
+        let code = if crate::core::lua::is_limit_error(&err) {
+            ErrorCode::Timeout
+        } else {
+            ErrorCode::Lua
+        };
+        Self::new(code, err.to_string())
+    }
+}
@@ -0,0 +1,606 @@
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use reqwest::StatusCode;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use serde_json::{json, Value};
+use crate::core::error::{ErrorCode, NGenError};
+use crate::core::kv::KV;
+use crate::core::net::{HttpBody, HttpMethod, HttpRequest, HttpResponse};
+
+const META_PREFIX: &str = "http-cache/meta/";
+const BODY_PREFIX: &str = "http-cache/body/";
+
+// Statuses that are cacheable by default (RFC 9110 section 15.1)
+const CACHEABLE_STATUSES: [u16; 6] = [200, 203, 300, 301, 404, 410];
+// Upper bound for heuristic freshness derived from Last-Modified
+const MAX_HEURISTIC_FRESHNESS: u64 = 24 * 60 * 60;
+
+#[derive(Clone, Debug)]
+pub struct HttpCacheConfig {
+    /// redb file holding the cache; only one client may open it at a time
+    pub path: PathBuf,
+    /// Entries are evicted least-recently-used first once bodies exceed this size
+    pub max_bytes: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct HttpCacheEntryInfo {
+    pub url: String,
+    pub status: u16,
+    pub size: u64,
+    /// Unix timestamps in seconds
+    pub stored_at: u64,
+    pub expires_at: u64,
+    pub last_access: u64,
+}
+
+pub enum CacheLookup {
+    Fresh(HttpResponse),
+    /// Stored but stale; send the request again with these conditional headers
+    Stale(Vec<(String, String)>),
+    Miss,
+}
+
+struct EntryMeta {
+    url: String,
+    /// URL the stored response came from, when redirects led elsewhere
+    final_url: Option<String>,
+    status: u16,
+    headers: Vec<(String, String)>,
+    vary: Vec<(String, Option<String>)>,
+    stored_at: u64,
+    expires_at: u64,
+    size: u64,
+    last_access: u64,
+}
+
+/// Private HTTP cache for GET responses following RFC 9111 freshness and
+/// validation rules, persisted in a `KV` store.
+pub struct HttpCache {
+    kv: KV,
+    max_bytes: u64,
+    // Serializes metadata updates and eviction
+    lock: Mutex<()>,
+}
+
+impl HttpCache {
+    pub fn open(config: &HttpCacheConfig) -> Result<Self, NGenError> {
+        let kv = KV::open(&config.path).map_err(NGenError::from)?;
+        Ok(Self {
+            kv,
+            max_bytes: config.max_bytes,
+            lock: Mutex::new(()),
+        })
+    }
+
+    /// Cache key for `request`, or `None` if the request must bypass the cache
+    pub fn key(request: &HttpRequest) -> Option<String> {
+        if request.method != HttpMethod::Get || !matches!(request.body, HttpBody::Empty) {
+            return None;
+        }
+        let bypass = ["range", "if-none-match", "if-modified-since", "authorization"];
+        if request.headers.iter().any(|(k, _)| bypass.contains(&k.to_ascii_lowercase().as_str())) {
+            return None;
+        }
+        if request_directives(request).iter().any(|(name, _)| name == "no-store") {
+            return None;
+        }
+        cache_url(request)
+    }
+
+    pub fn lookup(&self, request: &HttpRequest) -> Result<CacheLookup, NGenError> {
+        let Some(key) = Self::key(request) else {
+            return Ok(CacheLookup::Miss);
+        };
+        let _guard = self.lock.lock().unwrap();
+        let Some(mut meta) = self.read_meta(&key)? else {
+            return Ok(CacheLookup::Miss);
+        };
+        let vary_matches = meta.vary.iter()
+            .all(|(name, value)| request_header(request, name) == value.as_deref());
+        if !vary_matches {
+            return Ok(CacheLookup::Miss);
+        }
+
+        let now = unix_now();
+        let no_cache = request_directives(request).iter().any(|(name, _)| name == "no-cache");
+        if now < meta.expires_at && !no_cache {
+            let Some(body) = self.kv.read_bytes(&format!("{}{}", BODY_PREFIX, key))? else {
+                return Ok(CacheLookup::Miss);
+            };
+            meta.last_access = now;
+            self.write_meta(&key, &meta)?;
+            return Ok(CacheLookup::Fresh(meta.response(body)));
+        }
+
+        let mut conditional = Vec::new();
+        if let Some(etag) = meta.header("etag") {
+            conditional.push(("If-None-Match".to_string(), etag.to_string()));
+        }
+        if let Some(last_modified) = meta.header("last-modified") {
+            conditional.push(("If-Modified-Since".to_string(), last_modified.to_string()));
+        }
+        Ok(if conditional.is_empty() { CacheLookup::Miss } else { CacheLookup::Stale(conditional) })
+    }
+
+    /// Stores `response` if its status and Cache-Control allow it, then evicts
+    /// least-recently-used entries beyond the size limit.
+    pub fn store(&self, request: &HttpRequest, response: &HttpResponse) -> Result<(), NGenError> {
+        let Some(key) = Self::key(request) else {
+            return Ok(());
+        };
+        let body = response.body.as_deref().unwrap_or_default();
+        let _guard = self.lock.lock().unwrap();
+
+        if !CACHEABLE_STATUSES.contains(&response.status.as_u16())
+            || body.len() as u64 > self.max_bytes
+        {
+            self.remove_entry(&key)?;
+            return Ok(());
+        }
+        let headers = header_pairs(&response.headers);
+        let Some(vary) = vary_values(request, &headers) else {
+            self.remove_entry(&key)?;
+            return Ok(());
+        };
+        let Some(lifetime) = freshness_lifetime(&headers) else {
+            self.remove_entry(&key)?;
+            return Ok(());
+        };
+
+        let now = unix_now();
+        let meta = EntryMeta {
+            url: key.clone(),
+            final_url: response.redirected.then(|| response.url.clone()),
+            status: response.status.as_u16(),
+            headers,
+            vary,
+            stored_at: now,
+            expires_at: now + lifetime,
+            size: body.len() as u64,
+            last_access: now,
+        };
+        if lifetime == 0 && meta.header("etag").is_none() && meta.header("last-modified").is_none() {
+            // Could never be reused
+            self.remove_entry(&key)?;
+            return Ok(());
+        }
+
+        self.kv.write_bytes(&format!("{}{}", BODY_PREFIX, key), body)?;
+        self.write_meta(&key, &meta)?;
+        self.evict(&key)
+    }
+
+    /// Applies a 304 response to the stored entry and returns the cached response
+    /// it validated, or `None` if the entry is gone.
+    pub fn revalidate(
+        &self,
+        request: &HttpRequest,
+        not_modified: &HttpResponse,
+    ) -> Result<Option<HttpResponse>, NGenError> {
+        let Some(key) = Self::key(request) else {
+            return Ok(None);
+        };
+        let _guard = self.lock.lock().unwrap();
+        let Some(mut meta) = self.read_meta(&key)? else {
+            return Ok(None);
+        };
+        let Some(body) = self.kv.read_bytes(&format!("{}{}", BODY_PREFIX, key))? else {
+            return Ok(None);
+        };
+
+        let updated = header_pairs(&not_modified.headers);
+        meta.headers.retain(|(k, _)| !updated.iter().any(|(name, _)| k.eq_ignore_ascii_case(name)));
+        meta.headers.extend(updated);
+        let now = unix_now();
+        meta.stored_at = now;
+        meta.expires_at = now + freshness_lifetime(&meta.headers).unwrap_or(0);
+        meta.last_access = now;
+        self.write_meta(&key, &meta)?;
+        Ok(Some(meta.response(body)))
+    }
+
+    /// Drops the entry for `url`, e.g. after a successful unsafe request to it.
+    /// `url` is normalized the same way as the stored keys.
+    pub fn invalidate(&self, url: &str) -> Result<bool, NGenError> {
+        let key = reqwest::Url::parse(url).map_or_else(|_| url.to_string(), String::from);
+        let _guard = self.lock.lock().unwrap();
+        self.remove_entry(&key)
+    }
+
+    pub fn clear(&self) -> Result<(), NGenError> {
+        let _guard = self.lock.lock().unwrap();
+        for key in self.kv.bytes_keys(META_PREFIX)? {
+            self.remove_entry(&key[META_PREFIX.len()..])?;
+        }
+        Ok(())
+    }
+
+    pub fn entries(&self) -> Result<Vec<HttpCacheEntryInfo>, NGenError> {
+        let _guard = self.lock.lock().unwrap();
+        Ok(self.all_meta()?
+            .into_iter()
+            .map(|meta| HttpCacheEntryInfo {
+                url: meta.url,
+                status: meta.status,
+                size: meta.size,
+                stored_at: meta.stored_at,
+                expires_at: meta.expires_at,
+                last_access: meta.last_access,
+            })
+            .collect())
+    }
+
+    /// Evicts least-recently-used entries other than `keep` until under the size limit
+    fn evict(&self, keep: &str) -> Result<(), NGenError> {
+        let mut entries = self.all_meta()?;
+        let mut total: u64 = entries.iter().map(|meta| meta.size).sum();
+        if total <= self.max_bytes {
+            return Ok(());
+        }
+        entries.sort_by_key(|meta| meta.last_access);
+        for meta in entries.into_iter().filter(|meta| meta.url != keep) {
+            if total <= self.max_bytes {
+                break;
+            }
+            self.remove_entry(&meta.url)?;
+            total -= meta.size;
+        }
+        Ok(())
+    }
+
+    fn all_meta(&self) -> Result<Vec<EntryMeta>, NGenError> {
+        let mut entries = Vec::new();
+        for key in self.kv.bytes_keys(META_PREFIX)? {
+            if let Some(meta) = self.read_meta(&key[META_PREFIX.len()..])? {
+                entries.push(meta);
+            }
+        }
+        Ok(entries)
+    }
+
+    fn read_meta(&self, key: &str) -> Result<Option<EntryMeta>, NGenError> {
+        let Some(data) = self.kv.read_bytes(&format!("{}{}", META_PREFIX, key))? else {
+            return Ok(None);
+        };
+        Ok(serde_json::from_slice::<Value>(&data).ok().and_then(|value| EntryMeta::from_json(&value)))
+    }
+
+    fn write_meta(&self, key: &str, meta: &EntryMeta) -> Result<(), NGenError> {
+        let data = serde_json::to_vec(&meta.to_json())
+            .map_err(|e| NGenError::new(ErrorCode::Kv, e.to_string()))?;
+        Ok(self.kv.write_bytes(&format!("{}{}", META_PREFIX, key), &data)?)
+    }
+
+    fn remove_entry(&self, key: &str) -> Result<bool, NGenError> {
+        let removed = self.kv.remove_bytes(&format!("{}{}", META_PREFIX, key))?;
+        self.kv.remove_bytes(&format!("{}{}", BODY_PREFIX, key))?;
+        Ok(removed)
+    }
+}
+
+impl EntryMeta {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn response(&self, body: Vec<u8>) -> HttpResponse {
+        let mut headers = HeaderMap::new();
+        for (name, value) in &self.headers {
+            if let (Ok(name), Ok(value)) = (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_str(value)) {
+                headers.append(name, value);
+            }
+        }
+        HttpResponse {
+            status: StatusCode::from_u16(self.status).unwrap_or(StatusCode::OK),
+            headers,
+            body: Some(body),
+            url: self.final_url.clone().unwrap_or_else(|| self.url.clone()),
+            redirected: self.final_url.is_some(),
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "url": self.url,
+            "final_url": self.final_url,
+            "status": self.status,
+            "headers": self.headers,
+            "vary": self.vary,
+            "stored_at": self.stored_at,
+            "expires_at": self.expires_at,
+            "size": self.size,
+            "last_access": self.last_access,
+        })
+    }
+
+    fn from_json(value: &Value) -> Option<Self> {
+        let pairs = |value: &Value| -> Option<Vec<(String, Option<String>)>> {
+            value.as_array()?
+                .iter()
+                .map(|pair| Some((pair.get(0)?.as_str()?.to_string(), pair.get(1)?.as_str().map(str::to_string))))
+                .collect()
+        };
+        Some(Self {
+            url: value["url"].as_str()?.to_string(),
+            final_url: value["final_url"].as_str().map(str::to_string),
+            status: value["status"].as_u64()? as u16,
+            headers: pairs(&value["headers"])?
+                .into_iter()
+                .map(|(k, v)| (k, v.unwrap_or_default()))
+                .collect(),
+            vary: pairs(&value["vary"])?,
+            stored_at: value["stored_at"].as_u64()?,
+            expires_at: value["expires_at"].as_u64()?,
+            size: value["size"].as_u64()?,
+            last_access: value["last_access"].as_u64()?,
+        })
+    }
+}
+
+/// Absolute URL including the query parameters, used as cache key
+pub fn cache_url(request: &HttpRequest) -> Option<String> {
+    request.full_url()
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+fn header_pairs(headers: &HeaderMap) -> Vec<(String, String)> {
+    headers.iter()
+        .filter_map(|(k, v)| Some((k.as_str().to_string(), v.to_str().ok()?.to_string())))
+        .collect()
+}
+
+fn request_header<'a>(request: &'a HttpRequest, name: &str) -> Option<&'a str> {
+    request.headers.iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.as_str())
+}
+
+/// Lowercased Cache-Control directives with their unquoted arguments
+fn directives<'a>(values: impl Iterator<Item = &'a str>) -> Vec<(String, Option<String>)> {
+    values
+        .flat_map(|value| value.split(','))
+        .filter_map(|directive| {
+            let mut parts = directive.splitn(2, '=');
+            let name = parts.next()?.trim().to_ascii_lowercase();
+            let arg = parts.next().map(|arg| arg.trim().trim_matches('"').to_string());
+            (!name.is_empty()).then_some((name, arg))
+        })
+        .collect()
+}
+
+fn request_directives(request: &HttpRequest) -> Vec<(String, Option<String>)> {
+    directives(request.headers.iter()
+        .filter(|(k, _)| k.eq_ignore_ascii_case("cache-control"))
+        .map(|(_, v)| v.as_str()))
+}
+
+/// Request header values for the response's `Vary` names; `None` for `Vary: *`
+fn vary_values(request: &HttpRequest, headers: &[(String, String)]) -> Option<Vec<(String, Option<String>)>> {
+    let mut vary = Vec::new();
+    for (_, value) in headers.iter().filter(|(k, _)| k.eq_ignore_ascii_case("vary")) {
+        for name in value.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            if name == "*" {
+                return None;
+            }
+            vary.push((name.to_ascii_lowercase(), request_header(request, name).map(str::to_string)));
+        }
+    }
+    Some(vary)
+}
+
+/// Remaining freshness in seconds, or `None` if the response must not be stored
+fn freshness_lifetime(headers: &[(String, String)]) -> Option<u64> {
+    let header = |name: &str| {
+        headers.iter().find(|(k, _)| k.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+    };
+    let response_directives = directives(headers.iter()
+        .filter(|(k, _)| k.eq_ignore_ascii_case("cache-control"))
+        .map(|(_, v)| v.as_str()));
+    let directive = |name: &str| response_directives.iter().find(|(n, _)| n == name);
+
+    if directive("no-store").is_some() {
+        return None;
+    }
+    if directive("no-cache").is_some() {
+        return Some(0);
+    }
+
+    let date = header("date").and_then(|v| httpdate::parse_http_date(v).ok());
+    let age = header("age").and_then(|v| v.trim().parse::<u64>().ok()).unwrap_or(0);
+
+    let lifetime = if let Some(max_age) = directive("max-age").and_then(|(_, arg)| arg.as_deref()?.parse::<u64>().ok()) {
+        max_age
+    } else if let Some(expires) = header("expires") {
+        // An invalid Expires (e.g. "0") means already expired
+        httpdate::parse_http_date(expires).ok()
+            .and_then(|expires| expires.duration_since(date.unwrap_or_else(SystemTime::now)).ok())
+            .map_or(0, |d| d.as_secs())
+    } else if let Some(last_modified) = header("last-modified").and_then(|v| httpdate::parse_http_date(v).ok()) {
+        date.unwrap_or_else(SystemTime::now)
+            .duration_since(last_modified)
+            .map_or(0, |d| (d / 10).as_secs())
+            .min(MAX_HEURISTIC_FRESHNESS)
+    } else {
+        0
+    };
+
+    Some(lifetime.saturating_sub(age))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    /// Cache over a fresh redb file that is deleted on drop
+    struct TempCache {
+        cache: HttpCache,
+        path: PathBuf,
+    }
+
+    impl TempCache {
+        fn new(max_bytes: u64) -> Self {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+            let path = std::env::temp_dir().join(format!(
+                "ngenrs-http-cache-{}-{}.redb",
+                std::process::id(),
+                NEXT.fetch_add(1, Ordering::Relaxed),
+            ));
+            let cache = HttpCache::open(&HttpCacheConfig { path: path.clone(), max_bytes }).unwrap();
+            Self { cache, path }
+        }
+    }
+
+    impl Drop for TempCache {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+
+    fn pairs(headers: &[(&str, &str)]) -> Vec<(String, String)> {
+        headers.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    /// HTTP date `secs` seconds after a fixed point in time
+    fn http_date(secs: u64) -> String {
+        httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(1_700_000_000 + secs))
+    }
+
+    fn response(headers: &[(&str, &str)], body: &[u8]) -> HttpResponse {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.append(HeaderName::from_bytes(name.as_bytes()).unwrap(), HeaderValue::from_str(value).unwrap());
+        }
+        HttpResponse {
+            status: StatusCode::OK,
+            headers: map,
+            body: Some(body.to_vec()),
+            url: String::new(),
+            redirected: false,
+        }
+    }
+
+    fn get(url: &str) -> HttpRequest {
+        HttpRequest::new(HttpMethod::Get, url)
+    }
+
+    #[test]
+    fn max_age_takes_precedence_over_expires() {
+        let headers = pairs(&[("Cache-Control", "public, max-age=60"), ("Expires", &http_date(3600))]);
+        assert_eq!(freshness_lifetime(&headers), Some(60));
+    }
+
+    #[test]
+    fn age_is_subtracted_from_the_lifetime() {
+        assert_eq!(freshness_lifetime(&pairs(&[("Cache-Control", "max-age=60"), ("Age", "45")])), Some(15));
+        assert_eq!(freshness_lifetime(&pairs(&[("Cache-Control", "max-age=60"), ("Age", "90")])), Some(0));
+    }
+
+    #[test]
+    fn expires_is_relative_to_date() {
+        // Measured from the server's Date, not the local clock
+        let headers = pairs(&[("Date", &http_date(0)), ("Expires", &http_date(300))]);
+        assert_eq!(freshness_lifetime(&headers), Some(300));
+        assert_eq!(freshness_lifetime(&pairs(&[("Expires", "0")])), Some(0));
+        let past = pairs(&[("Date", &http_date(60)), ("Expires", &http_date(0))]);
+        assert_eq!(freshness_lifetime(&past), Some(0));
+    }
+
+    #[test]
+    fn heuristic_freshness_from_last_modified() {
+        let headers = pairs(&[("Date", &http_date(1000)), ("Last-Modified", &http_date(0))]);
+        assert_eq!(freshness_lifetime(&headers), Some(100));
+        let old = pairs(&[("Date", &http_date(100 * 24 * 60 * 60)), ("Last-Modified", &http_date(0))]);
+        assert_eq!(freshness_lifetime(&old), Some(MAX_HEURISTIC_FRESHNESS));
+        assert_eq!(freshness_lifetime(&[]), Some(0));
+    }
+
+    #[test]
+    fn no_store_and_no_cache() {
+        assert_eq!(freshness_lifetime(&pairs(&[("Cache-Control", "max-age=60, no-store")])), None);
+        assert_eq!(freshness_lifetime(&pairs(&[("Cache-Control", "no-cache"), ("Cache-Control", "max-age=60")])), Some(0));
+    }
+
+    #[test]
+    fn vary_records_request_header_values() {
+        let request = get("http://example.com/").header("Accept-Encoding", "gzip");
+        let headers = pairs(&[("Vary", "Accept-Encoding, X-Missing")]);
+        assert_eq!(vary_values(&request, &headers), Some(vec![
+            ("accept-encoding".to_string(), Some("gzip".to_string())),
+            ("x-missing".to_string(), None),
+        ]));
+        assert_eq!(vary_values(&request, &pairs(&[("Vary", "Accept, *")])), None);
+        assert_eq!(vary_values(&request, &[]), Some(Vec::new()));
+    }
+
+    #[test]
+    fn vary_mismatch_is_a_miss() {
+        let temp = TempCache::new(1024);
+        let request = get("http://example.com/vary").header("Accept-Language", "en");
+        let stored = response(&[("Cache-Control", "max-age=60"), ("Vary", "Accept-Language")], b"hello");
+        temp.cache.store(&request, &stored).unwrap();
+        assert!(matches!(temp.cache.lookup(&request).unwrap(), CacheLookup::Fresh(_)));
+        let other = get("http://example.com/vary").header("Accept-Language", "de");
+        assert!(matches!(temp.cache.lookup(&other).unwrap(), CacheLookup::Miss));
+    }
+
+    #[test]
+    fn revalidation_merges_headers_and_refreshes() {
+        let temp = TempCache::new(1024);
+        let request = get("http://example.com/doc");
+        let stored = response(&[("Cache-Control", "no-cache"), ("ETag", "\"v1\""), ("X-Kept", "yes")], b"body");
+        temp.cache.store(&request, &stored).unwrap();
+        let CacheLookup::Stale(conditional) = temp.cache.lookup(&request).unwrap() else {
+            panic!("expected a stale entry");
+        };
+        assert_eq!(conditional, pairs(&[("If-None-Match", "\"v1\"")]));
+
+        let mut not_modified = response(&[("Cache-Control", "max-age=120"), ("ETag", "\"v2\"")], b"");
+        not_modified.status = StatusCode::NOT_MODIFIED;
+        let revalidated = temp.cache.revalidate(&request, &not_modified).unwrap().unwrap();
+        assert_eq!(revalidated.status, StatusCode::OK);
+        assert_eq!(revalidated.body.as_deref(), Some(&b"body"[..]));
+        assert_eq!(revalidated.headers["etag"], "\"v2\"");
+        assert_eq!(revalidated.headers["cache-control"], "max-age=120");
+        assert_eq!(revalidated.headers["x-kept"], "yes");
+        assert_eq!(revalidated.headers.get_all("etag").iter().count(), 1);
+        assert!(matches!(temp.cache.lookup(&request).unwrap(), CacheLookup::Fresh(_)));
+
+        assert!(temp.cache.revalidate(&get("http://example.com/other"), &not_modified).unwrap().is_none());
+    }
+
+    #[test]
+    fn eviction_drops_least_recently_used_entries() {
+        let temp = TempCache::new(10);
+        let fresh = response(&[("Cache-Control", "max-age=60")], b"1234");
+        for (url, last_access) in [("http://example.com/a", 1), ("http://example.com/b", 2)] {
+            temp.cache.store(&get(url), &fresh).unwrap();
+            let mut meta = temp.cache.read_meta(url).unwrap().unwrap();
+            meta.last_access = last_access;
+            temp.cache.write_meta(url, &meta).unwrap();
+        }
+
+        temp.cache.store(&get("http://example.com/c"), &fresh).unwrap();
+        let mut urls: Vec<String> = temp.cache.entries().unwrap().into_iter().map(|e| e.url).collect();
+        urls.sort();
+        assert_eq!(urls, ["http://example.com/b", "http://example.com/c"]);
+
+        // The entry just stored is kept even when it is the least recently used
+        let mut meta = temp.cache.read_meta("http://example.com/c").unwrap().unwrap();
+        meta.last_access = 0;
+        meta.size = 8;
+        temp.cache.write_meta("http://example.com/c", &meta).unwrap();
+        temp.cache.evict("http://example.com/c").unwrap();
+        let urls: Vec<String> = temp.cache.entries().unwrap().into_iter().map(|e| e.url).collect();
+        assert_eq!(urls, ["http://example.com/c"]);
+    }
+}
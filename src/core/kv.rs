@@ -7,6 +7,7 @@ use std::path::Path;
 const INT_TABLE: TableDefinition<&str, i64> = TableDefinition::new("integers");
 const FLOAT_TABLE: TableDefinition<&str, f64> = TableDefinition::new("floats");
 const STRING_TABLE: TableDefinition<&str, &str> = TableDefinition::new("strings");
+const BYTES_TABLE: TableDefinition<&str, &[u8]> = TableDefinition::new("bytes");
 
 // static KV_STORE: Lazy<Mutex<KV>> = Lazy::new(|| {
 //     Mutex::new(KV::new("data.redb").expect("Failed to create KV store"))
@@ -69,4 +70,54 @@ impl KV {
         let table = read_txn.open_table(STRING_TABLE)?;
         Ok(table.get(key)?.map(|x| x.value().to_string()))
     }
-}
\ No newline at end of file
+
+    pub fn write_bytes(&self, key: &str, value: &[u8]) -> Result<(), Error> {
+        let write_txn = self.db.begin_write()?;
+        {
+            let mut table = write_txn.open_table(BYTES_TABLE)?;
+            table.insert(key, value)?;
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    pub fn read_bytes(&self, key: &str) -> Result<Option<Vec<u8>>, Error> {
+        let read_txn = self.db.begin_read()?;
+        let table = match read_txn.open_table(BYTES_TABLE) {
+            Ok(table) => table,
+            Err(redb::TableError::TableDoesNotExist(_)) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(table.get(key)?.map(|x| x.value().to_vec()))
+    }
+
+    /// Removes a bytes entry, returning whether it existed
+    pub fn remove_bytes(&self, key: &str) -> Result<bool, Error> {
+        let write_txn = self.db.begin_write()?;
+        let removed = {
+            let mut table = write_txn.open_table(BYTES_TABLE)?;
+            table.remove(key)?.is_some()
+        };
+        write_txn.commit()?;
+        Ok(removed)
+    }
+
+    /// Keys of the bytes table starting with `prefix`, in order
+    pub fn bytes_keys(&self, prefix: &str) -> Result<Vec<String>, Error> {
+        let read_txn = self.db.begin_read()?;
+        let table = match read_txn.open_table(BYTES_TABLE) {
+            Ok(table) => table,
+            Err(redb::TableError::TableDoesNotExist(_)) => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut keys = Vec::new();
+        for entry in table.range(prefix..)? {
+            let (key, _) = entry?;
+            if !key.value().starts_with(prefix) {
+                break;
+            }
+            keys.push(key.value().to_string());
+        }
+        Ok(keys)
+    }
+}
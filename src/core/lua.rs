@@ -1,130 +1,1133 @@
+use std::cell::Cell;
 use std::collections::HashMap;
+use std::fmt;
+use std::ops::Deref;
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
-use mlua::{Lua, Result, Function, UserData, FromLua};
-use std::path::Path;
+use mlua::{Lua, Result, Function, FromLua, UserData, UserDataMethods, Table, RegistryKey, StdLib, LuaOptions, HookTriggers, ChunkMode};
+use parking_lot::{ReentrantMutex, ReentrantMutexGuard};
+use reqwest::header::HeaderMap;
+use rusqlite::types::Value as SqlValue;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use crate::core::db::DB;
+use crate::core::error::{ErrorCode, NGenError};
+use crate::core::net::{DownloadOptions, HttpClient, HttpInterceptor, HttpMethod, HttpRequest, HttpResponse, RUNTIME};
+
+/// Coroutine scheduler; receives the native `addTimer` and returns `await(start, [callback])`,
+/// which host modules use to yield the running coroutine until an operation finishes.
+const ASYNC_MODULE: &str = r#"
+local add_timer = ...
+local coroutine = coroutine
+
+-- Resumes `co` with the results of a host operation; errors reach the host through `poll`
+local function step(co, ...)
+    local ok, err = coroutine.resume(co, ...)
+    if not ok then
+        error(err, 0)
+    end
+end
+
+local function pack(...)
+    return { n = select('#', ...), ... }
+end
+
+-- `table.unpack`, which sandboxed states may lack
+local function unpack(values, i, n)
+    if i <= n then
+        return values[i], unpack(values, i + 1, n)
+    end
+end
+
+-- Calls `start(done)`. `done` is the callback if one is given; otherwise the running
+-- coroutine yields until `done(...)` is called and the call returns its arguments.
+-- A `done` called before `start` returns is returned from directly.
+local function await(start, callback)
+    if callback then
+        start(callback)
+        return
+    end
+    local co, main
+    if coroutine then
+        co, main = coroutine.running()
+    end
+    if main ~= false then
+        error("a callback is required outside a coroutine", 2)
+    end
+    local waiting, early = false, nil
+    start(function(...)
+        if waiting then
+            waiting = false
+            step(co, ...)
+        elseif not early then
+            early = pack(...)
+        end
+    end)
+    if early then
+        return unpack(early, 1, early.n)
+    end
+    waiting = true
+    return coroutine.yield()
+end
+
+async = {}
+
+function async.run(fn, ...)
+    if not coroutine then
+        error("async: the coroutine library is not loaded", 2)
+    end
+    local co = coroutine.create(fn)
+    step(co, ...)
+    return co
+end
+
+function async.sleep(seconds, callback)
+    return await(function(done) add_timer(seconds, done) end, callback)
+end
+
+function async.await(start)
+    return await(start)
+end
+
+return await
+"#;
+
+/// Lua side of the `http` module; receives the native `request(spec, callback)`
+/// and the scheduler's `await`.
+const HTTP_MODULE: &str = r#"
+local request, await = ...
+
+local function call(spec, callback)
+    return await(function(done) request(spec, done) end, callback)
+end
+
+local function spec_from(opts, fields)
+    local spec = {}
+    for k, v in pairs(opts or {}) do
+        spec[k] = v
+    end
+    for k, v in pairs(fields) do
+        spec[k] = v
+    end
+    return spec
+end
+
+local function split(opts, callback)
+    if type(opts) == "function" then
+        return nil, opts
+    end
+    return opts, callback
+end
+
+http = {}
+
+function http.request(spec, callback)
+    return call(spec, callback)
+end
+
+function http.get(url, opts, callback)
+    opts, callback = split(opts, callback)
+    return call(spec_from(opts, { method = "GET", url = url }), callback)
+end
+
+function http.post(url, body, opts, callback)
+    opts, callback = split(opts, callback)
+    return call(spec_from(opts, { method = "POST", url = url, body = body }), callback)
+end
+
+function http.download(url, path, opts, callback)
+    opts, callback = split(opts, callback)
+    return call(spec_from(opts, { method = "GET", url = url, path = path }), callback)
+end
+"#;
+
+/// Lua side of the `db` module; receives the native `open(path)`, whose connections
+/// have `query(sql, params, callback)` and `exec(sql, params, callback)`, and `await`.
+const DB_MODULE: &str = r#"
+local open, await = ...
+
+local function split(params, callback)
+    if type(params) == "function" then
+        return nil, params
+    end
+    return params, callback
+end
+
+local Connection = {}
+Connection.__index = Connection
+
+function Connection:query(sql, params, callback)
+    params, callback = split(params, callback)
+    local conn = self.conn
+    return await(function(done) conn:query(sql, params, done) end, callback)
+end
+
+function Connection:exec(sql, params, callback)
+    params, callback = split(params, callback)
+    local conn = self.conn
+    return await(function(done) conn:exec(sql, params, done) end, callback)
+end
+
+db = {}
+
+function db.open(path)
+    return setmetatable({ conn = open(path) }, Connection)
+end
+"#;
+
+/// Installed in sandboxed states after the standard libraries; receives the native
+/// `is_limit_error(err)`. Keeps scripts from catching execution limit errors and
+/// from loading bytecode or files.
+const SANDBOX_PRELUDE: &str = r#"
+local is_limit_error = ...
+local raw_pcall, raw_xpcall, raw_load, error = pcall, xpcall, load, error
+
+local function rethrow(ok, ...)
+    if not ok and is_limit_error((...)) then
+        error((...), 0)
+    end
+    return ok, ...
+end
+
+function pcall(...)
+    return rethrow(raw_pcall(...))
+end
+
+function xpcall(f, handler, ...)
+    return rethrow(raw_xpcall(f, function(err)
+        if is_limit_error(err) then
+            return err
+        end
+        return handler(err)
+    end, ...))
+end
+
+if coroutine then
+    local resume, close = coroutine.resume, coroutine.close
+    function coroutine.resume(...)
+        return rethrow(resume(...))
+    end
+    function coroutine.close(...)
+        return rethrow(close(...))
+    end
+end
+
+function load(chunk, name, _, env)
+    return raw_load(chunk, name, "t", env)
+end
+
+dofile, loadfile = nil, nil
+"#;
+
+/// VM instructions between two checks of the execution budget
+const HOOK_INSTRUCTIONS: u32 = 1000;
+
+/// Restrictions for running untrusted scripts
+#[derive(Clone, Debug)]
+pub struct LuaSandbox {
+    /// Standard libraries to open. `package` and `debug` are never opened; see
+    /// `module_dir` for `require`.
+    pub libs: StdLib,
+    /// Bytes the state may allocate; allocations beyond it raise a memory error
+    pub memory_limit: Option<usize>,
+    /// VM instructions a script may execute each time the host calls into the
+    /// bridge (loading a chunk, calling a function or polling)
+    pub instruction_limit: Option<u64>,
+    /// Longest a script may run each time the host calls into the bridge
+    pub execution_timeout: Option<Duration>,
+    /// Directory `require("a.b")` loads `a/b.lua` from; without it `require` fails
+    pub module_dir: Option<PathBuf>,
+}
+
+impl Default for LuaSandbox {
+    fn default() -> Self {
+        Self {
+            libs: StdLib::COROUTINE | StdLib::TABLE | StdLib::STRING | StdLib::UTF8 | StdLib::MATH,
+            memory_limit: None,
+            instruction_limit: None,
+            execution_timeout: None,
+            module_dir: None,
+        }
+    }
+}
+
+/// Raised from the instruction hook once a sandboxed script runs out of budget
+#[derive(Debug)]
+struct ExecutionLimitExceeded(&'static str);
+
+impl fmt::Display for ExecutionLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for ExecutionLimitExceeded {}
+
+/// Whether `error` was raised by a sandbox instruction or time limit
+pub(crate) fn is_limit_error(error: &mlua::Error) -> bool {
+    match error {
+        mlua::Error::CallbackError { cause, .. } => is_limit_error(cause),
+        mlua::Error::ExternalError(e) => e.downcast_ref::<ExecutionLimitExceeded>().is_some(),
+        _ => false,
+    }
+}
+
+/// Instruction and time budget of a sandboxed state, kept as app data
+struct ExecutionBudget {
+    instruction_limit: Option<u64>,
+    timeout: Option<Duration>,
+    executed: Cell<u64>,
+    deadline: Cell<Option<Instant>>,
+    // How deeply the host has entered the state; the outermost entry renews the budget
+    depth: Cell<usize>,
+}
+
+/// Lock on the Lua state that tracks entries for the execution budget
+struct LuaGuard<'a>(ReentrantMutexGuard<'a, Lua>);
+
+impl Deref for LuaGuard<'_> {
+    type Target = Lua;
+
+    fn deref(&self) -> &Lua {
+        &self.0
+    }
+}
+
+impl Drop for LuaGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(budget) = self.0.app_data_ref::<ExecutionBudget>() {
+            budget.depth.set(budget.depth.get() - 1);
+        }
+    }
+}
+
+fn lock_lua(lua: &ReentrantMutex<Lua>) -> LuaGuard<'_> {
+    let guard = lua.lock();
+    if let Some(budget) = guard.app_data_ref::<ExecutionBudget>() {
+        if budget.depth.get() == 0 {
+            budget.executed.set(0);
+            budget.deadline.set(budget.timeout.map(|timeout| Instant::now() + timeout));
+        }
+        budget.depth.set(budget.depth.get() + 1);
+    }
+    LuaGuard(guard)
+}
 
 #[derive(Clone)]
 struct TimerHandle(usize);
 
+impl UserData for TimerHandle {}
+
 struct TimerEntry {
-    end_time: Instant,
-    callback: String,  // Store function name instead of Function
+    deadline: Instant,
+    // Orders timers due at the same instant by when they were (re)scheduled
+    seq: u64,
+    callback: RegistryKey,
+    interval: Option<Duration>,
 }
 
-impl UserData for TimerHandle {}
+/// Shortest `addInterval` period, so a zero interval can't spin the event loop
+const MIN_INTERVAL: Duration = Duration::from_millis(1);
 
+#[derive(Default)]
 struct TimerState {
     next_id: usize,
-    active_timers: HashMap<usize, TimerEntry>,  // Removed lifetime parameter
+    next_seq: u64,
+    active_timers: HashMap<usize, TimerEntry>,
+}
+
+impl TimerState {
+    fn schedule(&mut self, delay: Duration, callback: RegistryKey, interval: Option<Duration>) -> TimerHandle {
+        self.next_id += 1;
+        self.next_seq += 1;
+        self.active_timers.insert(self.next_id, TimerEntry {
+            deadline: Instant::now() + delay,
+            seq: self.next_seq,
+            callback,
+            interval,
+        });
+        TimerHandle(self.next_id)
+    }
+}
+
+/// Calls the timers that are due, in deadline order; intervals are rescheduled
+/// from now. Timers added by the callbacks wait for the next poll.
+fn fire_timers(timers: &Mutex<TimerState>, lua: &Lua) -> Result<()> {
+    let now = Instant::now();
+    let mut due: Vec<(Instant, u64, usize)> = timers.lock().unwrap().active_timers.iter()
+        .filter(|(_, timer)| timer.deadline <= now)
+        .map(|(id, timer)| (timer.deadline, timer.seq, *id))
+        .collect();
+    due.sort_unstable();
+
+    let mut first_error = None;
+    for (_, seq, id) in due {
+        // Released before the call, the callback may add or remove timers
+        let callback = {
+            let mut timers = timers.lock().unwrap();
+            let next_seq = timers.next_seq + 1;
+            // An earlier callback may have removed it
+            let Some(timer) = timers.active_timers.get_mut(&id).filter(|timer| timer.seq == seq) else {
+                continue;
+            };
+            match timer.interval {
+                Some(interval) => {
+                    timer.deadline = now + interval;
+                    timer.seq = next_seq;
+                    let callback = lua.registry_value::<Function>(&timer.callback);
+                    timers.next_seq = next_seq;
+                    callback
+                }
+                None => {
+                    let timer = timers.active_timers.remove(&id).unwrap();
+                    let callback = lua.registry_value::<Function>(&timer.callback);
+                    if let Err(e) = lua.remove_registry_value(timer.callback) {
+                        first_error.get_or_insert(e);
+                    }
+                    callback
+                }
+            }
+        };
+        if let Err(e) = callback.and_then(|callback| callback.call::<_, ()>(())) {
+            first_error.get_or_insert(e);
+        }
+    }
+    first_error.map_or(Ok(()), Err)
+}
+
+/// Result of a host operation, passed to its callback by `poll`
+enum Outcome {
+    Http {
+        result: std::result::Result<HttpResponse, NGenError>,
+        path: Option<PathBuf>,
+    },
+    Rows(std::result::Result<(Vec<String>, Vec<Vec<SqlValue>>), NGenError>),
+    Changes(std::result::Result<usize, NGenError>),
+}
+
+struct Completion {
+    id: u64,
+    outcome: Outcome,
+}
+
+/// Host operations (HTTP requests, database queries) running off the Lua thread
+#[derive(Default)]
+struct AsyncState {
+    // Created on first use unless set with `LuaBridge::set_http_client`
+    client: Option<HttpClient>,
+    next_id: u64,
+    // Callbacks of operations still in flight, keyed by operation id
+    pending: HashMap<u64, RegistryKey>,
+    completed: Vec<Completion>,
+}
+
+impl AsyncState {
+    fn start(&mut self, callback: RegistryKey) -> u64 {
+        self.next_id += 1;
+        self.pending.insert(self.next_id, callback);
+        self.next_id
+    }
+}
+
+/// Connection behind a script `db` handle; statements run on the blocking thread pool
+#[derive(Clone)]
+struct LuaDb {
+    db: Arc<Mutex<DB>>,
+    ops: Arc<Mutex<AsyncState>>,
+}
+
+impl LuaDb {
+    fn run(&self, lua: &Lua, sql: String, params: Option<Table>, callback: Function, exec: bool) -> Result<()> {
+        let params = match params {
+            Some(params) => (1..=params.raw_get::<_, Option<i64>>("n")?.unwrap_or(params.raw_len()))
+                .map(|i| params.raw_get::<_, mlua::Value>(i).and_then(|value| sql_value(&value)))
+                .collect::<Result<Vec<_>>>()?,
+            None => Vec::new(),
+        };
+        let id = self.ops.lock().unwrap().start(lua.create_registry_value(callback)?);
+        let this = self.clone();
+        RUNTIME.spawn_blocking(move || {
+            let db = this.db.lock().unwrap();
+            let outcome = if exec {
+                Outcome::Changes(db.execute(&sql, &params).map_err(NGenError::from))
+            } else {
+                Outcome::Rows(db.query_rows(&sql, &params).map_err(NGenError::from))
+            };
+            this.ops.lock().unwrap().completed.push(Completion { id, outcome });
+        });
+        Ok(())
+    }
+}
+
+impl UserData for LuaDb {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("query", |lua, this, (sql, params, callback): (String, Option<Table>, Function)| {
+            this.run(lua, sql, params, callback, false)
+        });
+        methods.add_method("exec", |lua, this, (sql, params, callback): (String, Option<Table>, Function)| {
+            this.run(lua, sql, params, callback, true)
+        });
+    }
 }
 
 pub struct LuaBridge {
-    lua: Lua,
+    // Shared with HTTP interceptors, which may call into Lua from other threads.
+    // Reentrant so a host function called from Lua can trigger them on the same thread.
+    lua: Arc<ReentrantMutex<Lua>>,
     timers: Arc<Mutex<TimerState>>,  // Removed lifetime parameter
+    ops: Arc<Mutex<AsyncState>>,
 }
 
 impl LuaBridge {
+    /// Bridge for trusted scripts, with every standard library but `debug`,
+    /// including `io` and `os`
     pub fn new() -> Result<Self> {
-        let lua = Lua::new();
-        let timers = Arc::new(Mutex::new(TimerState {
-            next_id: 1,
-            active_timers: HashMap::new(),
-        }));
+        Self::with_lua(Lua::new(), true)
+    }
+
+    /// Bridge for untrusted scripts, restricted by `sandbox`
+    pub fn with_sandbox(sandbox: LuaSandbox) -> Result<Self> {
+        let libs = sandbox.libs ^ (sandbox.libs & (StdLib::PACKAGE | StdLib::DEBUG));
+        let lua = Lua::new_with(libs, LuaOptions::default())?;
+
+        let is_limit = lua.create_function(|_, err: mlua::Value| {
+            Ok(matches!(err, mlua::Value::Error(ref e) if is_limit_error(e)))
+        })?;
+        lua.load(SANDBOX_PRELUDE).set_name("=sandbox")?.call::<_, ()>(is_limit)?;
+
+        let module_dir = sandbox.module_dir;
+        let require = lua.create_function(move |lua, name: String| sandbox_require(lua, module_dir.as_deref(), &name))?;
+        lua.globals().set("require", require)?;
+
+        if let Some(limit) = sandbox.memory_limit {
+            lua.set_memory_limit(limit)?;
+        }
+        if sandbox.instruction_limit.is_some() || sandbox.execution_timeout.is_some() {
+            lua.set_app_data(ExecutionBudget {
+                instruction_limit: sandbox.instruction_limit,
+                timeout: sandbox.execution_timeout,
+                executed: Cell::new(0),
+                deadline: Cell::new(None),
+                depth: Cell::new(0),
+            });
+            let triggers = HookTriggers {
+                every_nth_instruction: Some(HOOK_INSTRUCTIONS),
+                ..Default::default()
+            };
+            lua.set_hook(triggers, |lua, _| {
+                let Some(budget) = lua.app_data_ref::<ExecutionBudget>() else {
+                    return Ok(());
+                };
+                budget.executed.set(budget.executed.get() + HOOK_INSTRUCTIONS as u64);
+                if budget.instruction_limit.is_some_and(|limit| budget.executed.get() > limit) {
+                    return Err(mlua::Error::external(ExecutionLimitExceeded("instruction limit exceeded")));
+                }
+                if budget.deadline.get().is_some_and(|deadline| Instant::now() >= deadline) {
+                    return Err(mlua::Error::external(ExecutionLimitExceeded("execution timed out")));
+                }
+                Ok(())
+            })?;
+        }
+        Self::with_lua(lua, false)
+    }
 
-        let bridge = LuaBridge { lua, timers };
+    fn with_lua(lua: Lua, trusted: bool) -> Result<Self> {
+        let timers = Arc::new(Mutex::new(TimerState::default()));
+
+        let bridge = LuaBridge {
+            lua: Arc::new(ReentrantMutex::new(lua)),
+            timers,
+            ops: Arc::new(Mutex::new(AsyncState::default())),
+        };
         bridge.init_timer_api()?;
+        bridge.init_async_api(trusted)?;
         Ok(bridge)
     }
 
+    /// Registers `addTimer(delay, fn)`, `addInterval(interval, fn)` and
+    /// `removeTimer(handle)`; delays are in seconds. Callbacks run from `poll`.
+    /// Intervals repeat no faster than `MIN_INTERVAL`. For older scripts,
+    /// `addTimer({delay, name})` still calls the global function `name`, and
+    /// `pollTimers()` fires due timers from within Lua.
     fn init_timer_api(&self) -> Result<()> {
-        let timers_add = self.timers.clone();
-        
-        self.export_function("addTimer", move |lua, value: mlua::Value| {
-            let table = mlua::Table::from_lua(value, lua)?;
-            let delay: f64 = table.get(1)?;
-            let callback_name: String = table.get(2)?;  // Get function name as string
-            
-            let handle = {
-                let mut state = timers_add.lock().unwrap();
-                let id = state.next_id;
-                state.next_id += 1;
-                state.active_timers.insert(id, TimerEntry {
-                    end_time: Instant::now() + Duration::from_secs_f64(delay),
-                    callback: callback_name,  // Store function name
-                });
-                TimerHandle(id)
+        let lua = lock_lua(&self.lua);
+        let globals = lua.globals();
+
+        let timers = self.timers.clone();
+        globals.set("addTimer", lua.create_function(move |lua, (delay, callback): (mlua::Value, Option<Function>)| {
+            let (delay, callback) = match (delay, callback) {
+                (mlua::Value::Table(legacy), None) => {
+                    let delay: f64 = legacy.get(1)?;
+                    let name: String = legacy.get(2)?;
+                    let callback = lua.create_function(move |lua, ()| {
+                        lua.globals().get::<_, Function>(name.as_str())?.call::<_, ()>(())
+                    })?;
+                    (delay, callback)
+                }
+                (delay, Some(callback)) => (f64::from_lua(delay, lua)?, callback),
+                (_, None) => return Err(mlua::Error::RuntimeError("addTimer: callback is required".to_string())),
             };
-            Ok(handle)
-        })?;
+            let delay = seconds(delay)?;
+            let callback = lua.create_registry_value(callback)?;
+            Ok(timers.lock().unwrap().schedule(delay, callback, None))
+        })?)?;
+
+        let timers = self.timers.clone();
+        globals.set("addInterval", lua.create_function(move |lua, (interval, callback): (f64, Function)| {
+            let interval = seconds(interval)?.max(MIN_INTERVAL);
+            let callback = lua.create_registry_value(callback)?;
+            Ok(timers.lock().unwrap().schedule(interval, callback, Some(interval)))
+        })?)?;
+
+        let timers = self.timers.clone();
+        globals.set("pollTimers", lua.create_function(move |lua, ()| fire_timers(&timers, lua))?)?;
+
+        let timers = self.timers.clone();
+        globals.set("removeTimer", lua.create_function(move |lua, handle: TimerHandle| {
+            let removed = timers.lock().unwrap().active_timers.remove(&handle.0);
+            if let Some(timer) = removed {
+                lua.remove_registry_value(timer.callback)?;
+            }
+            Ok(())
+        })?)?;
+        Ok(())
+    }
 
-        let timers_poll = self.timers.clone();
-        self.export_function("pollTimers", move |lua, _: mlua::Value| {
-            let mut state = timers_poll.lock().unwrap();
-            let now = Instant::now();
-            let mut expired = Vec::new();
-            
-            state.active_timers.retain(|id, entry| {
-                if entry.end_time <= now {
-                    expired.push((*id, entry.callback.clone()));
-                    false
-                } else {
-                    true
+    /// Time until the next timer is due, zero if one is overdue
+    pub fn next_timer_delay(&self) -> Option<Duration> {
+        let timers = self.timers.lock().unwrap();
+        let deadline = timers.active_timers.values().map(|timer| timer.deadline).min()?;
+        Some(deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Registers the coroutine scheduler and the host modules built on it.
+    /// `async.run(fn, ...)` runs `fn` as a coroutine, in which `http`, `db` and
+    /// `async.sleep(seconds)` calls without a callback yield until they finish.
+    /// `async.await(start)` does the same for any `start(done)` style function.
+    /// The `http` and `db` modules are left out of sandboxed states, as downloads
+    /// and databases write to arbitrary paths.
+    fn init_async_api(&self, trusted: bool) -> Result<()> {
+        let lua = lock_lua(&self.lua);
+        let add_timer: Function = lua.globals().get("addTimer")?;
+        let await_fn: Function = lua.load(ASYNC_MODULE).set_name("=async")?.call(add_timer)?;
+        if trusted {
+            self.init_http_api(&lua, await_fn.clone())?;
+            self.init_db_api(&lua, await_fn)?;
+        }
+        Ok(())
+    }
+
+    /// Registers the global `http` table. `http.get(url, [opts], [cb])`,
+    /// `http.post(url, body, [opts], [cb])`, `http.download(url, path, [opts], [cb])`
+    /// and `http.request(spec, [cb])` take `opts`/`spec` fields `method`, `url`,
+    /// `headers`, `query`, `body`, `form` and `path`, where `body` and `form` are
+    /// exclusive. Requests run on the Tokio runtime; `cb(rsp, err)` is called from
+    /// `poll` with `rsp` as `{status, headers, body}` (`path` instead of `body` for
+    /// downloads), or with `nil` and a message on failure. Inside a coroutine the
+    /// callback may be omitted and the call yields until the response arrives.
+    fn init_http_api<'lua>(&self, lua: &'lua Lua, await_fn: Function<'lua>) -> Result<()> {
+        let http = self.ops.clone();
+        let request = lua.create_function(move |lua, (spec, callback): (Table, Function)| {
+            let (request, path) = request_from_spec(&spec)?;
+            let client = {
+                let mut state = http.lock().unwrap();
+                match &state.client {
+                    Some(client) => client.clone(),
+                    None => {
+                        let client = HttpClient::new(None)
+                            .map_err(|e| mlua::Error::RuntimeError(e.to_string()))?;
+                        state.client = Some(client.clone());
+                        client
+                    }
                 }
+            };
+            let id = http.lock().unwrap().start(lua.create_registry_value(callback)?);
+
+            let http = http.clone();
+            RUNTIME.spawn(async move {
+                let result = match &path {
+                    Some(path) => {
+                        client.download_request(request, path, &DownloadOptions::default(), |_, _| {}).await
+                    }
+                    None => client.send(request).await,
+                }
+                .map_err(|e| NGenError::from_boxed(e, ErrorCode::Network));
+                http.lock().unwrap().completed.push(Completion { id, outcome: Outcome::Http { result, path } });
             });
-            
-            // Look up and call functions by name
-            for (_, func_name) in expired {
-                let func: Function = lua.globals().get(&*func_name)?;  // Added dereference here
-                func.call::<_, ()>(())?;
-            }
-            
             Ok(())
         })?;
-    
-        let timers_remove = self.timers.clone();
-        self.export_function("removeTimer", move |lua, value: mlua::Value| {
-            let handle = TimerHandle::from_lua(value, lua)?;
-            let mut state = timers_remove.lock().unwrap();
-            state.active_timers.remove(&handle.0);
-            Ok(())
+        lua.load(HTTP_MODULE).set_name("=http")?.call((request, await_fn))
+    }
+
+    /// Registers the global `db` table. `db.open(path)` opens an SQLite database
+    /// whose `conn:query(sql, [params], [cb])` calls `cb(rows, err)` with an array
+    /// of `{column = value}` rows and `conn:exec(sql, [params], [cb])` calls
+    /// `cb(changes, err)`. `params` is an array bound to the `?` placeholders; its
+    /// `n` field, as set by `table.pack`, counts trailing nils.
+    /// Statements run on a worker thread; without a callback the call yields.
+    fn init_db_api<'lua>(&self, lua: &'lua Lua, await_fn: Function<'lua>) -> Result<()> {
+        let ops = self.ops.clone();
+        let open = lua.create_function(move |_, path: String| {
+            let db = DB::open(&path).map_err(|e| mlua::Error::RuntimeError(format!("db: {}", e)))?;
+            Ok(LuaDb { db: Arc::new(Mutex::new(db)), ops: ops.clone() })
         })?;
-    
-        Ok(())
+        lua.load(DB_MODULE).set_name("=db")?.call((open, await_fn))
+    }
+
+    /// Uses `client` for requests made through the `http` module
+    pub fn set_http_client(&self, client: HttpClient) {
+        self.ops.lock().unwrap().client = Some(client);
+    }
+
+    /// Calls the callbacks of finished `http` requests and `db` statements, then the
+    /// timers that are due, on the current thread; coroutines waiting on them resume.
+    /// Returns the number of operations in flight and timers still scheduled.
+    /// Every callback runs even if one fails; the first failure is returned.
+    pub fn poll(&self) -> Result<usize> {
+        let completed = std::mem::take(&mut self.ops.lock().unwrap().completed);
+        let lua = lock_lua(&self.lua);
+        let mut first_error = None;
+        for completion in completed {
+            // Released before the call, the callback may start new operations
+            let key = self.ops.lock().unwrap().pending.remove(&completion.id);
+            let Some(key) = key else {
+                continue;
+            };
+            let outcome = (|| {
+                let callback: Function = lua.registry_value(&key)?;
+                lua.remove_registry_value(key)?;
+                let result = match completion.outcome {
+                    Outcome::Http { result, path } => result.map(|response| {
+                        response_table(&lua, &response, path.as_deref()).map(mlua::Value::Table)
+                    }),
+                    Outcome::Rows(result) => result.map(|(columns, rows)| rows_table(&lua, &columns, rows).map(mlua::Value::Table)),
+                    Outcome::Changes(result) => result.map(|changes| Ok(mlua::Value::Integer(changes as i64))),
+                };
+                match result {
+                    Ok(value) => callback.call::<_, ()>(value?),
+                    Err(e) => callback.call::<_, ()>((mlua::Value::Nil, e.message)),
+                }
+            })();
+            if let Err(e) = outcome {
+                first_error.get_or_insert(e);
+            }
+        }
+        if let Err(e) = fire_timers(&self.timers, &lua) {
+            first_error.get_or_insert(e);
+        }
+        match first_error {
+            Some(e) => Err(e),
+            None => {
+                let timers = self.timers.lock().unwrap().active_timers.len();
+                Ok(self.ops.lock().unwrap().pending.len() + timers)
+            }
+        }
     }
 
     pub fn load_file(&self, path: &str) -> Result<()> {
         let path = Path::new(path);
-        self.lua.load(path).exec()
+        lock_lua(&self.lua).load(path).exec()
     }
 
     pub fn load_string(&self, script: &str) -> Result<()> {
-        self.lua.load(script).exec()
+        lock_lua(&self.lua).load(script).exec()
+    }
+
+    /// Runs the global function `func_name` as a coroutine until it first yields
+    /// or returns; it continues from `poll` as the operations it waits on finish.
+    /// Errors after the first yield are returned by `poll`.
+    pub fn spawn(&self, func_name: &str, arg: &str) -> Result<()> {
+        let lua = lock_lua(&self.lua);
+        let func: Function = lua.globals().get(func_name)?;
+        lua.create_thread(func)?.resume::<_, mlua::MultiValue>(arg)?;
+        Ok(())
     }
 
     pub fn call_function(&self, func_name: &str, arg: &str) -> Result<String> {
-        let func: Function = self.lua.globals().get(func_name)?;
+        let lua = lock_lua(&self.lua);
+        let func: Function = lua.globals().get(func_name)?;
         func.call::<_, String>(arg)
     }
 
+    /// Calls the global function `func_name` with `args` converted by `json_to_lua`
+    /// and returns all of its results converted by `lua_to_json`
+    pub fn call(&self, func_name: &str, args: &[Value]) -> Result<Vec<Value>> {
+        let lua = lock_lua(&self.lua);
+        let func: Function = lua.globals().get(func_name)?;
+        let args = args.iter()
+            .map(|arg| json_to_lua(&lua, arg))
+            .collect::<Result<Vec<_>>>()?;
+        let results = func.call::<_, mlua::MultiValue>(mlua::MultiValue::from_vec(args))?;
+        results.iter().map(lua_to_json).collect()
+    }
+
+    /// `call` returning the first result, null if there is none
+    pub fn call_json(&self, func_name: &str, args: &[Value]) -> Result<Value> {
+        Ok(self.call(func_name, args)?.into_iter().next().unwrap_or(Value::Null))
+    }
+
+    /// `call_json` with the result deserialized into `R`
+    pub fn call_as<R: DeserializeOwned>(&self, func_name: &str, args: &[Value]) -> Result<R> {
+        serde_json::from_value(self.call_json(func_name, args)?)
+            .map_err(|e| mlua::Error::RuntimeError(format!("{}: unexpected result: {}", func_name, e)))
+    }
+
+    /// Installs the global Lua functions named `on_request` and `on_response` as an
+    /// interceptor on `client`; either may be `None`. Returns the id for
+    /// `HttpClient::remove_interceptor`. See `LuaInterceptor` for the calling convention.
+    /// The interceptor keeps this Lua state alive until it is removed.
+    /// Hooks lock this Lua state on a runtime worker, so scripts running here must not
+    /// make blocking requests through `client` or they deadlock.
+    pub fn add_http_interceptor(
+        &self,
+        client: &HttpClient,
+        on_request: Option<&str>,
+        on_response: Option<&str>,
+    ) -> u64 {
+        client.add_interceptor(Arc::new(LuaInterceptor {
+            lua: self.lua.clone(),
+            on_request: on_request.map(str::to_string),
+            on_response: on_response.map(str::to_string),
+        }))
+    }
+
     // Export Rust function to Lua context
     pub fn export_function<'a, F, R>(&self, name: &str, func: F) -> Result<()>
     where
-        F: Fn(&Lua, mlua::Value) -> Result<R> + 'static,
+        F: Fn(&Lua, mlua::Value) -> Result<R> + Send + 'static,
         R: for<'lua> mlua::ToLuaMulti<'lua>,
     {
-        let lua_func = self.lua.create_function(func)?;
-        self.lua.globals().set(name, lua_func)
+        let lua = lock_lua(&self.lua);
+        let lua_func = lua.create_function(func)?;
+        lua.globals().set(name, lua_func)
     }
 
     // Generic version that works with any Rust function
     pub fn export_rust_fn<F, A, R>(&self, name: &str, func: F) -> Result<()>
     where
-        F: Fn(A) -> R + 'static,
+        F: Fn(A) -> R + Send + 'static,
         A: for<'lua> mlua::FromLuaMulti<'lua>,
         R: for<'lua> mlua::ToLuaMulti<'lua>,
     {
-        let lua_func = self.lua.create_function(move |_, args| Ok(func(args)))?;
-        self.lua.globals().set(name, lua_func)
+        let lua = lock_lua(&self.lua);
+        let lua_func = lua.create_function(move |_, args| Ok(func(args)))?;
+        lua.globals().set(name, lua_func)
     }
-}
\ No newline at end of file
+}
+
+/// HTTP interceptor calling global Lua functions by name.
+///
+/// `on_request(req)` gets `{method, url, headers, body}` where `url` includes the
+/// query, `headers` maps name to value and `body` is the encoded body (absent for
+/// multipart). Edits to `url`, `headers` and `body` are sent; returning a table
+/// uses it instead, returning `false` rejects the request. Fields missing from
+/// the result are left unchanged.
+/// `on_response(req, rsp)` gets `rsp` as `{status, headers, body}`; returning
+/// `false` fails the request.
+struct LuaInterceptor {
+    lua: Arc<ReentrantMutex<Lua>>,
+    on_request: Option<String>,
+    on_response: Option<String>,
+}
+
+impl HttpInterceptor for LuaInterceptor {
+    fn on_request(&self, request: &mut HttpRequest) -> std::result::Result<(), NGenError> {
+        let Some(name) = &self.on_request else {
+            return Ok(());
+        };
+        let lua = lock_lua(&self.lua);
+        let func: Function = lua.globals().get(name.as_str())?;
+        let table = request_table(&lua, request)?;
+        let edited = match func.call::<_, mlua::Value>(table.clone())? {
+            mlua::Value::Boolean(false) => {
+                return Err(NGenError::new(ErrorCode::Cancelled, "Request rejected by Lua interceptor"));
+            }
+            mlua::Value::Table(returned) => returned,
+            _ => table,
+        };
+
+        let headers = match edited.get::<_, Option<Table>>("headers")? {
+            Some(headers) => Some(headers.pairs::<String, String>().collect::<Result<Vec<_>>>()?),
+            None => None,
+        };
+        let body: Option<mlua::String> = edited.get("body")?;
+        request.apply_script_edits(edited.get("url")?, headers, body.map(|b| b.as_bytes().to_vec()));
+        Ok(())
+    }
+
+    fn on_response(&self, request: &HttpRequest, response: &HttpResponse) -> std::result::Result<(), NGenError> {
+        let Some(name) = &self.on_response else {
+            return Ok(());
+        };
+        let lua = lock_lua(&self.lua);
+        let func: Function = lua.globals().get(name.as_str())?;
+
+        let rsp = response_table(&lua, response, None)?;
+        match func.call::<_, mlua::Value>((request_table(&lua, request)?, rsp))? {
+            mlua::Value::Boolean(false) => {
+                Err(NGenError::new(ErrorCode::Cancelled, "Response rejected by Lua interceptor"))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Tables nested deeper than this are treated as cyclic
+const MAX_VALUE_DEPTH: usize = 256;
+
+/// Converts JSON to a Lua value: arrays become sequences, objects tables with
+/// string keys and null becomes nil
+pub fn json_to_lua<'lua>(lua: &'lua Lua, value: &Value) -> Result<mlua::Value<'lua>> {
+    Ok(match value {
+        Value::Null => mlua::Value::Nil,
+        Value::Bool(value) => mlua::Value::Boolean(*value),
+        Value::Number(number) => match number.as_i64() {
+            Some(integer) => mlua::Value::Integer(integer),
+            None => mlua::Value::Number(number.as_f64().unwrap_or(f64::NAN)),
+        },
+        Value::String(text) => mlua::Value::String(lua.create_string(text)?),
+        Value::Array(items) => {
+            let table = lua.create_table_with_capacity(items.len() as i32, 0)?;
+            for (i, item) in items.iter().enumerate() {
+                table.raw_set(i + 1, json_to_lua(lua, item)?)?;
+            }
+            mlua::Value::Table(table)
+        }
+        Value::Object(entries) => {
+            let table = lua.create_table_with_capacity(0, entries.len() as i32)?;
+            for (key, value) in entries {
+                table.raw_set(key.as_str(), json_to_lua(lua, value)?)?;
+            }
+            mlua::Value::Table(table)
+        }
+    })
+}
+
+/// Converts a Lua value to JSON. A table whose keys are exactly `1..n` becomes an
+/// array, any other table an object (number keys as strings, so `{}` is an
+/// object). Non-finite numbers become null, strings that aren't UTF-8 are
+/// converted lossily; functions, userdata, threads and cyclic tables are errors.
+pub fn lua_to_json(value: &mlua::Value) -> Result<Value> {
+    value_to_json(value, 0)
+}
+
+fn value_to_json(value: &mlua::Value, depth: usize) -> Result<Value> {
+    Ok(match value {
+        mlua::Value::Nil => Value::Null,
+        mlua::Value::Boolean(value) => Value::Bool(*value),
+        mlua::Value::Integer(integer) => Value::from(*integer),
+        mlua::Value::Number(number) => {
+            if number.fract() == 0.0 && number.abs() < 9_007_199_254_740_992.0 {
+                Value::from(*number as i64)
+            } else {
+                serde_json::Number::from_f64(*number).map_or(Value::Null, Value::Number)
+            }
+        }
+        mlua::Value::String(text) => Value::String(String::from_utf8_lossy(text.as_bytes()).into_owned()),
+        mlua::Value::Table(table) => {
+            if depth >= MAX_VALUE_DEPTH {
+                return Err(mlua::Error::RuntimeError("table nested too deeply or cyclic".to_string()));
+            }
+            let entries = table.clone().pairs::<mlua::Value, mlua::Value>().collect::<Result<Vec<_>>>()?;
+            // Keys are distinct, so `len` of them all within `1..=len` are exactly that range
+            let len = table.raw_len() as usize;
+            let indices = (len > 0 && entries.len() == len)
+                .then(|| {
+                    entries.iter()
+                        .map(|(key, _)| match key {
+                            mlua::Value::Integer(i) if (1..=len as i64).contains(i) => Some(*i as usize - 1),
+                            _ => None,
+                        })
+                        .collect::<Option<Vec<_>>>()
+                })
+                .flatten();
+            if let Some(indices) = indices {
+                let mut items = vec![Value::Null; len];
+                for (index, (_, value)) in indices.into_iter().zip(&entries) {
+                    if let Some(item) = items.get_mut(index) {
+                        *item = value_to_json(value, depth + 1)?;
+                    }
+                }
+                Value::Array(items)
+            } else {
+                let mut object = serde_json::Map::new();
+                for (key, value) in &entries {
+                    let key = match key {
+                        mlua::Value::String(text) => String::from_utf8_lossy(text.as_bytes()).into_owned(),
+                        mlua::Value::Integer(integer) => integer.to_string(),
+                        mlua::Value::Number(number) => number.to_string(),
+                        other => {
+                            return Err(mlua::Error::RuntimeError(format!("can't convert a {} key to JSON", other.type_name())));
+                        }
+                    };
+                    object.insert(key, value_to_json(value, depth + 1)?);
+                }
+                Value::Object(object)
+            }
+        }
+        other => return Err(mlua::Error::RuntimeError(format!("can't convert a {} to JSON", other.type_name()))),
+    })
+}
+
+/// `require` of sandboxed states: loads `a/b.lua` for `a.b` from `module_dir` as
+/// text, once per name
+fn sandbox_require<'lua>(lua: &'lua Lua, module_dir: Option<&Path>, name: &str) -> Result<mlua::Value<'lua>> {
+    const LOADED: &str = "ngenrs.sandbox.loaded";
+    let loaded = match lua.named_registry_value::<_, Option<Table>>(LOADED)? {
+        Some(loaded) => loaded,
+        None => {
+            let loaded = lua.create_table()?;
+            lua.set_named_registry_value(LOADED, loaded.clone())?;
+            loaded
+        }
+    };
+    let module: mlua::Value = loaded.get(name)?;
+    if module != mlua::Value::Nil {
+        return Ok(module);
+    }
+
+    let valid = name.split('.').all(|segment| {
+        !segment.is_empty() && segment.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    });
+    if !valid {
+        return Err(mlua::Error::RuntimeError(format!("require: invalid module name '{}'", name)));
+    }
+    let Some(dir) = module_dir else {
+        return Err(mlua::Error::RuntimeError(format!("require: module '{}' not found", name)));
+    };
+    let mut path = dir.join(name.replace('.', "/"));
+    path.set_extension("lua");
+    let source = std::fs::read(&path)
+        .map_err(|e| mlua::Error::RuntimeError(format!("require: module '{}' not found: {}", name, e)))?;
+
+    let chunk = lua.load(&source).set_name(format!("@{}", path.display()))?.set_mode(ChunkMode::Text);
+    let module = match chunk.call::<_, mlua::Value>(name)? {
+        mlua::Value::Nil => mlua::Value::Boolean(true),
+        module => module,
+    };
+    loaded.set(name, module.clone())?;
+    Ok(module)
+}
+
+/// Array of `{column = value}` rows; NULL columns are absent
+fn rows_table<'lua>(lua: &'lua Lua, columns: &[String], rows: Vec<Vec<SqlValue>>) -> Result<Table<'lua>> {
+    let table = lua.create_table_with_capacity(rows.len() as i32, 0)?;
+    for (i, row) in rows.into_iter().enumerate() {
+        let entry = lua.create_table_with_capacity(0, columns.len() as i32)?;
+        for (column, value) in columns.iter().zip(row) {
+            let value = match value {
+                SqlValue::Null => mlua::Value::Nil,
+                SqlValue::Integer(value) => mlua::Value::Integer(value),
+                SqlValue::Real(value) => mlua::Value::Number(value),
+                SqlValue::Text(text) => mlua::Value::String(lua.create_string(&text)?),
+                SqlValue::Blob(bytes) => mlua::Value::String(lua.create_string(&bytes)?),
+            };
+            entry.raw_set(column.as_str(), value)?;
+        }
+        table.raw_set(i + 1, entry)?;
+    }
+    Ok(table)
+}
+
+/// SQL parameter from a script value; strings that aren't UTF-8 bind as blobs
+fn sql_value(value: &mlua::Value) -> Result<SqlValue> {
+    Ok(match value {
+        mlua::Value::Nil => SqlValue::Null,
+        mlua::Value::Boolean(value) => SqlValue::Integer(*value as i64),
+        mlua::Value::Integer(value) => SqlValue::Integer(*value),
+        mlua::Value::Number(value) => SqlValue::Real(*value),
+        mlua::Value::String(text) => match text.to_str() {
+            Ok(text) => SqlValue::Text(text.to_string()),
+            Err(_) => SqlValue::Blob(text.as_bytes().to_vec()),
+        },
+        other => {
+            return Err(mlua::Error::RuntimeError(format!("db: can't bind a {} parameter", other.type_name())));
+        }
+    })
+}
+
+/// Delay in seconds from a script
+fn seconds(value: f64) -> Result<Duration> {
+    Duration::try_from_secs_f64(value.max(0.0))
+        .map_err(|_| mlua::Error::RuntimeError(format!("invalid delay {}", value)))
+}
+
+fn request_table<'lua>(lua: &'lua Lua, request: &HttpRequest) -> Result<Table<'lua>> {
+    let table = lua.create_table()?;
+    table.set("method", request.method.as_str())?;
+    table.set("url", request.full_url().unwrap_or_else(|| request.url.clone()))?;
+    let headers = lua.create_table()?;
+    for (key, value) in &request.headers {
+        headers.set(key.as_str(), value.as_str())?;
+    }
+    table.set("headers", headers)?;
+    if let Some(body) = request.body_bytes() {
+        table.set("body", lua.create_string(&body)?)?;
+    }
+    Ok(table)
+}
+/// `{status, headers, body}`, or `path` in place of `body` for downloads
+fn response_table<'lua>(lua: &'lua Lua, response: &HttpResponse, path: Option<&Path>) -> Result<Table<'lua>> {
+    let table = lua.create_table()?;
+    table.set("status", response.status.as_u16())?;
+    table.set("headers", headers_table(lua, &response.headers)?)?;
+    match (path, &response.body) {
+        (Some(path), _) if response.status.is_success() => table.set("path", path.to_string_lossy())?,
+        (_, Some(body)) => table.set("body", lua.create_string(body)?)?,
+        _ => {}
+    }
+    Ok(table)
+}
+
+/// Header names map to their values, repeated headers joined with ", "
+fn headers_table<'lua>(lua: &'lua Lua, headers: &HeaderMap) -> Result<Table<'lua>> {
+    let table = lua.create_table()?;
+    for key in headers.keys() {
+        let values: Vec<&[u8]> = headers.get_all(key).iter().map(|value| value.as_bytes()).collect();
+        table.set(key.as_str(), lua.create_string(&values.join(&b", "[..]))?)?;
+    }
+    Ok(table)
+}
+
+fn request_from_spec(spec: &Table) -> Result<(HttpRequest, Option<PathBuf>)> {
+    let method = match spec.get::<_, Option<String>>("method")? {
+        Some(name) => HttpMethod::from_name(&name)
+            .ok_or_else(|| mlua::Error::RuntimeError(format!("http: unknown method {}", name)))?,
+        None => HttpMethod::Get,
+    };
+    let url: String = spec.get::<_, Option<String>>("url")?
+        .ok_or_else(|| mlua::Error::RuntimeError("http: url is required".to_string()))?;
+    let mut request = HttpRequest::new(method, &url);
+
+    if let Some(headers) = spec.get::<_, Option<Table>>("headers")? {
+        for pair in headers.pairs::<String, String>() {
+            let (key, value) = pair?;
+            request = request.header(&key, &value);
+        }
+    }
+    if let Some(query) = spec.get::<_, Option<Table>>("query")? {
+        for pair in query.pairs::<String, String>() {
+            let (key, value) = pair?;
+            request = request.query(&key, &value);
+        }
+    }
+    let form = spec.get::<_, Option<Table>>("form")?;
+    let body = spec.get::<_, Option<mlua::String>>("body")?;
+    match (form, body) {
+        (Some(_), Some(_)) => {
+            return Err(mlua::Error::RuntimeError("http: form and body can't both be set".to_string()));
+        }
+        (Some(form), None) => {
+            for pair in form.pairs::<String, String>() {
+                let (key, value) = pair?;
+                request = request.form_field(&key, &value);
+            }
+        }
+        (None, Some(body)) => request = request.body(body.as_bytes()),
+        (None, None) => {}
+    }
+
+    let path = spec.get::<_, Option<String>>("path")?.map(PathBuf::from);
+    Ok((request, path))
+}
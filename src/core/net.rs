@@ -1,52 +1,770 @@
 use std::collections::HashMap;
-use std::path::Path;
-use reqwest::Client;
-use reqwest::header::HeaderMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use once_cell::sync::Lazy;
+use reqwest::{Client, Method, StatusCode};
+use reqwest::header::{HeaderMap, CONTENT_RANGE, ETAG, LAST_MODIFIED};
+use reqwest::multipart;
 use futures::StreamExt;
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use std::borrow::Borrow;
 use serde_json::Value;
+use crate::core::crypto::{base64_encode, bytes2hex, hash_sha256, rsa_export_private_key, rsa_import_private_key, RsaKeyFormat};
+use crate::core::error::{ErrorCode, NGenError};
+use crate::core::cookie_jar::CookieJar;
+use crate::core::http_cache::{cache_url, CacheLookup, HttpCache, HttpCacheConfig};
 
+pub const DEFAULT_USER_AGENT: &str = concat!("ngenrs/", env!("CARGO_PKG_VERSION"));
+
+/// Runtime behind the blocking and async C API and the script bridges' network calls
+pub(crate) static RUNTIME: Lazy<tokio::runtime::Runtime> = Lazy::new(|| {
+    tokio::runtime::Runtime::new().expect("Failed to create Tokio runtime")
+});
+
+#[derive(Clone)]
 pub struct HttpClient {
     client: Client,
+    retry: RetryPolicy,
+    read_timeout: Option<Duration>,
+    pins: HashMap<String, Vec<String>>,
+    cache: Option<Arc<HttpCache>>,
+    cookie_jar: Option<Arc<CookieJar>>,
+    // Mirrors the reqwest settings for connections reqwest does not manage (WebSockets)
+    tls_connector: native_tls::TlsConnector,
+    connect_timeout: Option<Duration>,
+    user_agent: String,
+    interceptors: Arc<RwLock<InterceptorChain>>,
+}
+
+/// Hook around every request sent by an `HttpClient`, for cross-cutting concerns
+/// such as auth headers, request signing, logging or URL rewriting.
+/// `on_request` runs before each attempt, retries included, and may rewrite the
+/// request. `on_response` sees the response to the rewritten request; its body is
+/// `None` for streamed and downloaded responses. An error from either hook fails
+/// the request. Fresh responses served from the HTTP cache skip both hooks.
+pub trait HttpInterceptor: Send + Sync {
+    fn on_request(&self, _request: &mut HttpRequest) -> Result<(), NGenError> {
+        Ok(())
+    }
+
+    fn on_response(&self, _request: &HttpRequest, _response: &HttpResponse) -> Result<(), NGenError> {
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct InterceptorChain {
+    next_id: u64,
+    interceptors: Vec<(u64, Arc<dyn HttpInterceptor>)>,
+}
+
+/// Retries failed attempts with exponential backoff. Connection errors, timeouts
+/// and responses whose status is listed in `retry_statuses` are retried, for
+/// idempotent methods only unless `retry_non_idempotent` is set.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Total attempts including the first; 1 disables retries
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Randomize each delay between half and the full backoff
+    pub jitter: bool,
+    pub retry_statuses: Vec<u16>,
+    /// Also retry POST and PATCH, which may repeat their side effects
+    pub retry_non_idempotent: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 1,
+            initial_backoff: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(10),
+            jitter: true,
+            retry_statuses: vec![429, 502, 503, 504],
+            retry_non_idempotent: false,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before retrying after the given (1-based) failed attempt
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 1u32 << attempt.saturating_sub(1).min(16);
+        let delay = self.initial_backoff.saturating_mul(factor).min(self.max_backoff);
+        if self.jitter {
+            let half = delay / 2;
+            half + half.mul_f64(rand::random::<f64>())
+        } else {
+            delay
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ProxyConfig {
+    /// Proxy URL used for all schemes, e.g. `http://proxy:8080` or `socks5://...`
+    pub url: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Comma-separated hosts that bypass the proxy
+    pub no_proxy: Option<String>,
+}
+
+/// Client certificate presented for mutual TLS
+#[derive(Clone, Debug)]
+pub enum ClientIdentity {
+    /// PEM certificate chain and its private key (PKCS#8, or PKCS#1 for RSA)
+    Pem { cert: Vec<u8>, key: Vec<u8> },
+    /// DER-encoded PKCS#12 archive
+    Pkcs12 { der: Vec<u8>, password: String },
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct HttpClientConfig {
+    pub ca_cert_path: Option<PathBuf>,
+    pub connect_timeout: Option<Duration>,
+    /// Maximum wait for the response headers and between chunks of the body
+    pub read_timeout: Option<Duration>,
+    /// Deadline for a whole attempt, from connecting until the body is read
+    pub total_timeout: Option<Duration>,
+    pub retry: RetryPolicy,
+    /// `None` keeps the default limit of 10, `Some(0)` disables redirects
+    pub max_redirects: Option<usize>,
+    pub proxy: Option<ProxyConfig>,
+    /// Defaults to `DEFAULT_USER_AGENT`
+    pub user_agent: Option<String>,
+    pub identity: Option<ClientIdentity>,
+    /// Host -> accepted SPKI SHA-256 pins, base64 encoded (an optional `sha256/`
+    /// prefix is allowed). A handshake with a pinned host whose leaf certificate
+    /// key matches none of its pins is aborted before anything is sent, failing
+    /// with `ErrorCode::CertificatePinMismatch`. Pinned clients use rustls and
+    /// verify chains against the Mozilla root set plus `ca_cert_path`.
+    pub pins: HashMap<String, Vec<String>>,
+    /// Response cache used by `send` and the helpers built on it
+    pub cache: Option<HttpCacheConfig>,
+    /// Cookies are neither sent nor stored without a jar; it may be shared between clients
+    pub cookie_jar: Option<Arc<CookieJar>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HttpMethod {
+    Get = 0,
+    Post = 1,
+    Put = 2,
+    Patch = 3,
+    Delete = 4,
+    Head = 5,
+    Options = 6,
+}
+
+impl From<i32> for HttpMethod {
+    fn from(value: i32) -> Self {
+        match value {
+            1 => HttpMethod::Post,
+            2 => HttpMethod::Put,
+            3 => HttpMethod::Patch,
+            4 => HttpMethod::Delete,
+            5 => HttpMethod::Head,
+            6 => HttpMethod::Options,
+            _ => HttpMethod::Get,
+        }
+    }
+}
+
+impl HttpMethod {
+    /// Parses a method name case-insensitively
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_uppercase().as_str() {
+            "GET" => Some(HttpMethod::Get),
+            "POST" => Some(HttpMethod::Post),
+            "PUT" => Some(HttpMethod::Put),
+            "PATCH" => Some(HttpMethod::Patch),
+            "DELETE" => Some(HttpMethod::Delete),
+            "HEAD" => Some(HttpMethod::Head),
+            "OPTIONS" => Some(HttpMethod::Options),
+            _ => None,
+        }
+    }
+
+    /// Whether repeating the request has the same effect as sending it once
+    pub fn is_idempotent(&self) -> bool {
+        !matches!(self, HttpMethod::Post | HttpMethod::Patch)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HttpMethod::Get => "GET",
+            HttpMethod::Post => "POST",
+            HttpMethod::Put => "PUT",
+            HttpMethod::Patch => "PATCH",
+            HttpMethod::Delete => "DELETE",
+            HttpMethod::Head => "HEAD",
+            HttpMethod::Options => "OPTIONS",
+        }
+    }
+}
+
+impl From<HttpMethod> for Method {
+    fn from(value: HttpMethod) -> Self {
+        match value {
+            HttpMethod::Get => Method::GET,
+            HttpMethod::Post => Method::POST,
+            HttpMethod::Put => Method::PUT,
+            HttpMethod::Patch => Method::PATCH,
+            HttpMethod::Delete => Method::DELETE,
+            HttpMethod::Head => Method::HEAD,
+            HttpMethod::Options => Method::OPTIONS,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum MultipartField {
+    Text(String),
+    Bytes {
+        data: Vec<u8>,
+        filename: Option<String>,
+        mime: Option<String>,
+    },
+    File {
+        path: PathBuf,
+        mime: Option<String>,
+    },
+}
+
+#[derive(Clone, Debug, Default)]
+pub enum HttpBody {
+    #[default]
+    Empty,
+    Bytes(Vec<u8>),
+    Json(Value),
+    Form(Vec<(String, String)>),
+    Multipart(Vec<(String, MultipartField)>),
+}
+
+/// Method-agnostic request description; every `HttpClient` call is sent through one of these.
+#[derive(Clone, Debug)]
+pub struct HttpRequest {
+    pub method: HttpMethod,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub query: Vec<(String, String)>,
+    pub body: HttpBody,
+}
+
+impl HttpRequest {
+    pub fn new(method: HttpMethod, url: &str) -> Self {
+        Self {
+            method,
+            url: url.to_string(),
+            headers: Vec::new(),
+            query: Vec::new(),
+            body: HttpBody::Empty,
+        }
+    }
+
+    pub fn header(mut self, key: &str, value: &str) -> Self {
+        self.headers.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn headers<K, V>(mut self, headers: Option<HashMap<K, V>>) -> Self
+    where
+        K: Borrow<str>,
+        V: Borrow<str>,
+    {
+        if let Some(headers_map) = headers {
+            for (key, value) in headers_map {
+                self.headers.push((key.borrow().to_string(), value.borrow().to_string()));
+            }
+        }
+        self
+    }
+
+    /// Removes every header named `key`, ignoring case
+    pub fn remove_header(mut self, key: &str) -> Self {
+        self.headers.retain(|(name, _)| !name.eq_ignore_ascii_case(key));
+        self
+    }
+
+    pub fn query(mut self, key: &str, value: &str) -> Self {
+        self.query.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = HttpBody::Bytes(body.into());
+        self
+    }
+
+    pub fn json(mut self, value: Value) -> Self {
+        self.body = HttpBody::Json(value);
+        self
+    }
+
+    /// Appends an `application/x-www-form-urlencoded` field, replacing any non-form body
+    pub fn form_field(mut self, key: &str, value: &str) -> Self {
+        if !matches!(self.body, HttpBody::Form(_)) {
+            self.body = HttpBody::Form(Vec::new());
+        }
+        if let HttpBody::Form(fields) = &mut self.body {
+            fields.push((key.to_string(), value.to_string()));
+        }
+        self
+    }
+
+    /// Appends a `multipart/form-data` part, replacing any non-multipart body
+    pub fn multipart_field(mut self, name: &str, field: MultipartField) -> Self {
+        if !matches!(self.body, HttpBody::Multipart(_)) {
+            self.body = HttpBody::Multipart(Vec::new());
+        }
+        if let HttpBody::Multipart(parts) = &mut self.body {
+            parts.push((name.to_string(), field));
+        }
+        self
+    }
+
+    /// `url` with the `query` pairs appended, or `None` if `url` does not parse
+    pub fn full_url(&self) -> Option<String> {
+        let mut url = reqwest::Url::parse(&self.url).ok()?;
+        if !self.query.is_empty() {
+            url.query_pairs_mut().extend_pairs(&self.query);
+        }
+        Some(url.into())
+    }
+
+    /// Applies the fields a script interceptor saw and possibly edited: `url`
+    /// includes the query, `headers` replace the current ones, and a `body` that
+    /// differs from `body_bytes` replaces the body. `None` leaves a field alone.
+    pub(crate) fn apply_script_edits(
+        &mut self,
+        url: Option<String>,
+        headers: Option<Vec<(String, String)>>,
+        body: Option<Vec<u8>>,
+    ) {
+        if let Some(url) = url
+            && self.full_url().as_ref() != Some(&url)
+        {
+            self.url = url;
+            self.query.clear();
+        }
+        if let Some(headers) = headers {
+            self.headers = headers;
+        }
+        if let Some(body) = body
+            && self.body_bytes().as_ref() != Some(&body)
+        {
+            self.body = HttpBody::Bytes(body);
+        }
+    }
+
+    /// Body bytes as they will be sent. `None` for multipart bodies, which are
+    /// only encoded at send time.
+    pub fn body_bytes(&self) -> Option<Vec<u8>> {
+        match &self.body {
+            HttpBody::Empty => Some(Vec::new()),
+            HttpBody::Bytes(bytes) => Some(bytes.clone()),
+            HttpBody::Json(value) => serde_json::to_vec(value).ok(),
+            HttpBody::Form(fields) => {
+                let mut url = reqwest::Url::parse("http://localhost/").ok()?;
+                url.query_pairs_mut().extend_pairs(fields);
+                Some(url.query().unwrap_or_default().as_bytes().to_vec())
+            }
+            HttpBody::Multipart(_) => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct DownloadOptions {
+    /// Continue an existing partial file instead of truncating it
+    pub resume: bool,
+    /// Hex-encoded SHA-256 the finished file must match; the file is removed on mismatch
+    pub expected_sha256: Option<String>,
 }
 
 pub struct HttpResponse {
     pub status: reqwest::StatusCode,
     pub headers: HeaderMap,
-    pub body: Option<String>,
+    pub body: Option<Vec<u8>>,
+    /// URL the response came from, after any redirects
+    pub url: String,
+    /// Whether redirects were followed to reach `url`
+    pub redirected: bool,
+}
+
+impl HttpResponse {
+    /// Status, headers and final URL of `response` to `sent`, without the body
+    fn head(sent: &HttpRequest, response: &reqwest::Response) -> Self {
+        let requested = sent.full_url().and_then(|url| reqwest::Url::parse(&url).ok());
+        Self {
+            status: response.status(),
+            headers: response.headers().clone(),
+            body: None,
+            url: response.url().to_string(),
+            redirected: requested.as_ref() != Some(response.url()),
+        }
+    }
+
+    /// Body decoded as UTF-8, with invalid sequences replaced
+    pub fn text(&self) -> Option<String> {
+        self.body.as_ref().map(|body| String::from_utf8_lossy(body).into_owned())
+    }
 }
 
 impl HttpClient {
     pub fn new(ca_cert_path: Option<&Path>) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::with_config(HttpClientConfig {
+            ca_cert_path: ca_cert_path.map(Path::to_path_buf),
+            ..Default::default()
+        })
+    }
+
+    pub fn with_config(config: HttpClientConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        let user_agent = config.user_agent.clone().unwrap_or_else(|| DEFAULT_USER_AGENT.to_string());
         let mut builder = reqwest::Client::builder()
-            .danger_accept_invalid_certs(false);
+            .danger_accept_invalid_certs(false)
+            .user_agent(user_agent.as_str());
+        let mut tls_builder = native_tls::TlsConnector::builder();
 
-        if let Some(cert_path) = ca_cert_path {
+        let mut ca_cert = None;
+        if let Some(cert_path) = &config.ca_cert_path {
             let cert = std::fs::read(cert_path)?;
             builder = builder.add_root_certificate(
                 reqwest::Certificate::from_pem(&cert)?
             );
+            tls_builder.add_root_certificate(native_tls::Certificate::from_pem(&cert)?);
+            ca_cert = Some(cert);
+        }
+
+        if let Some(identity) = &config.identity {
+            let (identity, native_identity) = load_identity(identity)?;
+            builder = builder.identity(identity);
+            tls_builder.identity(native_identity);
+        }
+
+        let pins: HashMap<String, Vec<String>> = config.pins.iter()
+            .map(|(host, pins)| {
+                let pins = pins.iter()
+                    .map(|pin| pin.trim().trim_start_matches("sha256/").to_string())
+                    .collect();
+                (host.to_ascii_lowercase(), pins)
+            })
+            .collect();
+
+        // native-tls has no hook into certificate verification, so pinned clients
+        // use rustls and check the pins inside the handshake
+        if !pins.is_empty() {
+            builder = builder.use_preconfigured_tls(
+                pinned_tls_config(&pins, ca_cert.as_deref(), config.identity.as_ref())?
+            );
+        }
+
+        if let Some(jar) = &config.cookie_jar {
+            builder = builder.cookie_provider(jar.clone());
+        }
+
+        if let Some(timeout) = config.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        if let Some(timeout) = config.total_timeout {
+            builder = builder.timeout(timeout);
+        }
+
+        if let Some(max_redirects) = config.max_redirects {
+            builder = builder.redirect(match max_redirects {
+                0 => reqwest::redirect::Policy::none(),
+                n => reqwest::redirect::Policy::limited(n),
+            });
+        }
+
+        if let Some(proxy_config) = &config.proxy {
+            let mut proxy = reqwest::Proxy::all(proxy_config.url.as_str())?;
+            if let Some(username) = &proxy_config.username {
+                proxy = proxy.basic_auth(username, proxy_config.password.as_deref().unwrap_or(""));
+            }
+            if let Some(no_proxy) = &proxy_config.no_proxy {
+                proxy = proxy.no_proxy(reqwest::NoProxy::from_string(no_proxy));
+            }
+            builder = builder.proxy(proxy);
         }
 
         Ok(Self {
-            client: builder.build()?
+            client: builder.build()?,
+            tls_connector: tls_builder.build()?,
+            cache: match &config.cache {
+                Some(cache_config) => Some(Arc::new(HttpCache::open(cache_config)?)),
+                None => None,
+            },
+            cookie_jar: config.cookie_jar.clone(),
+            connect_timeout: config.connect_timeout,
+            user_agent,
+            interceptors: Arc::new(RwLock::new(InterceptorChain::default())),
+            retry: config.retry,
+            read_timeout: config.read_timeout,
+            pins,
         })
     }
 
-    async fn execute_request(
+    fn verify_host_pins(&self, host: &str, peer_certificate: Option<&[u8]>) -> Result<(), NGenError> {
+        check_pins(&self.pins, host, peer_certificate)
+    }
+
+    async fn build_multipart(
+        parts: Vec<(String, MultipartField)>,
+    ) -> Result<multipart::Form, Box<dyn std::error::Error>> {
+        let mut form = multipart::Form::new();
+        for (name, field) in parts {
+            form = match field {
+                MultipartField::Text(value) => form.text(name, value),
+                MultipartField::Bytes { data, filename, mime } => {
+                    let mut part = multipart::Part::bytes(data);
+                    if let Some(filename) = filename {
+                        part = part.file_name(filename);
+                    }
+                    if let Some(mime) = mime {
+                        part = part.mime_str(&mime)?;
+                    }
+                    form.part(name, part)
+                }
+                MultipartField::File { path, mime } => {
+                    let data = tokio::fs::read(&path).await?;
+                    let mut part = multipart::Part::bytes(data);
+                    if let Some(filename) = path.file_name() {
+                        part = part.file_name(filename.to_string_lossy().into_owned());
+                    }
+                    if let Some(mime) = mime {
+                        part = part.mime_str(&mime)?;
+                    }
+                    form.part(name, part)
+                }
+            };
+        }
+        Ok(form)
+    }
+
+    async fn build_request(
         &self,
-        request: reqwest::RequestBuilder,
-    ) -> Result<HttpResponse, Box<dyn std::error::Error>> {
-        let response = request.send().await?;
-        let status = response.status();
-        let headers = response.headers().clone();
-        let body = response.text().await.ok();
-
-        Ok(HttpResponse {
-            status,
-            headers,
-            body,
-        })
+        request: HttpRequest,
+    ) -> Result<reqwest::RequestBuilder, Box<dyn std::error::Error>> {
+        let mut builder = self.client.request(request.method.into(), &request.url);
+
+        for (key, value) in &request.headers {
+            builder = builder.header(key.as_str(), value.as_str());
+        }
+
+        if !request.query.is_empty() {
+            builder = builder.query(&request.query);
+        }
+
+        builder = match request.body {
+            HttpBody::Empty => builder,
+            HttpBody::Bytes(bytes) => builder.body(bytes),
+            HttpBody::Json(value) => builder.json(&value),
+            HttpBody::Form(fields) => builder.form(&fields),
+            HttpBody::Multipart(parts) => builder.multipart(Self::build_multipart(parts).await?),
+        };
+
+        Ok(builder)
+    }
+
+    /// Appends `interceptor` to the chain shared by this client and its clones.
+    /// Request hooks run in registration order, response hooks in reverse order.
+    /// Returns an id for `remove_interceptor`.
+    ///
+    /// Hooks run on the runtime's worker threads. Script interceptors take their
+    /// bridge's lock there, so a thread holding that lock must not block on a
+    /// request through this client.
+    pub fn add_interceptor(&self, interceptor: Arc<dyn HttpInterceptor>) -> u64 {
+        let mut chain = self.interceptors.write().unwrap();
+        chain.next_id += 1;
+        let id = chain.next_id;
+        chain.interceptors.push((id, interceptor));
+        id
+    }
+
+    pub fn remove_interceptor(&self, id: u64) -> bool {
+        let mut chain = self.interceptors.write().unwrap();
+        let len = chain.interceptors.len();
+        chain.interceptors.retain(|(existing, _)| *existing != id);
+        chain.interceptors.len() != len
+    }
+
+    fn interceptors(&self) -> Vec<Arc<dyn HttpInterceptor>> {
+        self.interceptors.read().unwrap()
+            .interceptors.iter()
+            .map(|(_, interceptor)| interceptor.clone())
+            .collect()
+    }
+
+    fn intercept_response(&self, request: &HttpRequest, response: &HttpResponse) -> Result<(), NGenError> {
+        for interceptor in self.interceptors().iter().rev() {
+            interceptor.on_response(request, response)?;
+        }
+        Ok(())
+    }
+
+    /// Sends `request`, retrying according to the client's `RetryPolicy`.
+    /// The request is rebuilt for every attempt so streamed bodies can be replayed,
+    /// and passed through the interceptors each time. Returns the request as last sent.
+    async fn send_with_retry(
+        &self,
+        request: HttpRequest,
+    ) -> Result<(HttpRequest, reqwest::Response), Box<dyn std::error::Error>> {
+        let retryable = request.method.is_idempotent() || self.retry.retry_non_idempotent;
+        let mut attempt = 1;
+        loop {
+            let mut prepared = request.clone();
+            for interceptor in self.interceptors() {
+                interceptor.on_request(&mut prepared)?;
+            }
+            let builder = self.build_request(prepared.clone()).await?;
+            // `None` when the read timeout elapsed before the response headers arrived
+            let result = match self.read_timeout {
+                Some(timeout) => tokio::time::timeout(timeout, builder.send()).await.ok(),
+                None => Some(builder.send().await),
+            };
+            if let Some(Err(e)) = &result
+                && let Some(pin_error) = pin_error(e)
+            {
+                return Err(pin_error.into());
+            }
+            let retry = retryable && attempt < self.retry.max_attempts && match &result {
+                Some(Ok(response)) => self.retry.retry_statuses.contains(&response.status().as_u16()),
+                Some(Err(e)) => e.is_connect() || e.is_timeout(),
+                None => true,
+            };
+            if !retry {
+                let response = result.ok_or_else(|| {
+                    NGenError::new(ErrorCode::Timeout, "Timed out waiting for response headers")
+                })??;
+                return Ok((prepared, response));
+            }
+            drop(result);
+            tokio::time::sleep(self.retry.backoff(attempt)).await;
+            attempt += 1;
+        }
+    }
+
+    /// Next body chunk, failing with `ErrorCode::Timeout` if the read timeout elapses first
+    async fn next_chunk<S, T>(&self, stream: &mut S) -> Result<Option<T>, NGenError>
+    where
+        S: futures::Stream<Item = reqwest::Result<T>> + Unpin,
+    {
+        let next = match self.read_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, stream.next()).await
+                .map_err(|_| NGenError::new(ErrorCode::Timeout, "Timed out reading response body"))?,
+            None => stream.next().await,
+        };
+        next.transpose().map_err(NGenError::from)
+    }
+
+    async fn read_body(&self, response: reqwest::Response) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let mut body = Vec::new();
+        let mut stream = response.bytes_stream();
+        while let Some(chunk) = self.next_chunk(&mut stream).await? {
+            body.extend_from_slice(&chunk);
+        }
+        Ok(body)
+    }
+
+    pub fn cache(&self) -> Option<&HttpCache> {
+        self.cache.as_deref()
+    }
+
+    pub fn cookie_jar(&self) -> Option<&CookieJar> {
+        self.cookie_jar.as_deref()
+    }
+
+    /// Sends `request` and buffers the body. With a cache configured, fresh GET
+    /// responses are served from it, stale ones are revalidated with
+    /// `If-None-Match`/`If-Modified-Since`, and successful unsafe requests
+    /// invalidate the stored entry for their URL. Cache failures never fail the request.
+    pub async fn send(&self, request: HttpRequest) -> Result<HttpResponse, Box<dyn std::error::Error>> {
+        let Some(cache) = &self.cache else {
+            return self.fetch(request).await;
+        };
+
+        if HttpCache::key(&request).is_none() {
+            let unsafe_method = !matches!(request.method, HttpMethod::Get | HttpMethod::Head | HttpMethod::Options);
+            let url = cache_url(&request);
+            let response = self.fetch(request).await?;
+            if unsafe_method && response.status.is_success() && let Some(url) = url {
+                let _ = with_cache(cache, move |cache| cache.invalidate(&url)).await;
+            }
+            return Ok(response);
+        }
+
+        let lookup = {
+            let request = request.clone();
+            with_cache(cache, move |cache| cache.lookup(&request)).await.unwrap_or(CacheLookup::Miss)
+        };
+        let response = match lookup {
+            CacheLookup::Fresh(response) => return Ok(response),
+            CacheLookup::Stale(conditional) => {
+                let mut revalidation = request.clone();
+                for (key, value) in &conditional {
+                    revalidation = revalidation.header(key, value);
+                }
+                let response = self.fetch(revalidation).await?;
+                if response.status == StatusCode::NOT_MODIFIED {
+                    let request = request.clone();
+                    let not_modified = HttpResponse {
+                        status: StatusCode::NOT_MODIFIED,
+                        headers: response.headers.clone(),
+                        body: None,
+                        url: response.url.clone(),
+                        redirected: response.redirected,
+                    };
+                    let revalidated = with_cache(cache, move |cache| cache.revalidate(&request, &not_modified)).await;
+                    if let Ok(Some(cached)) = revalidated {
+                        return Ok(cached);
+                    }
+                }
+                response
+            }
+            CacheLookup::Miss => self.fetch(request.clone()).await?,
+        };
+        Ok(with_cache(cache, move |cache| {
+            let _ = cache.store(&request, &response);
+            Ok(response)
+        }).await?)
+    }
+
+    async fn fetch(&self, request: HttpRequest) -> Result<HttpResponse, Box<dyn std::error::Error>> {
+        let (sent, response) = self.send_with_retry(request).await?;
+        let mut result = HttpResponse::head(&sent, &response);
+        result.body = Some(self.read_body(response).await?);
+        self.intercept_response(&sent, &result)?;
+        Ok(result)
+    }
+
+    /// Sends `request` and hands the body to `on_chunk` as it arrives instead of
+    /// buffering it. Returning `false` from `on_chunk` aborts the transfer.
+    /// The returned response carries status and headers only.
+    pub async fn send_streaming<F>(
+        &self,
+        request: HttpRequest,
+        mut on_chunk: F,
+    ) -> Result<HttpResponse, Box<dyn std::error::Error>>
+    where
+        F: FnMut(&[u8]) -> bool,
+    {
+        let (sent, response) = self.send_with_retry(request).await?;
+        let result = HttpResponse::head(&sent, &response);
+        self.intercept_response(&sent, &result)?;
+
+        let mut stream = response.bytes_stream();
+        while let Some(chunk) = self.next_chunk(&mut stream).await? {
+            if !on_chunk(&chunk) {
+                return Err(NGenError::new(ErrorCode::Cancelled, "Stream aborted by callback").into());
+            }
+        }
+
+        Ok(result)
     }
 
     pub async fn get<K, V>(
@@ -59,19 +777,13 @@ impl HttpClient {
         K: Borrow<str>,
         V: Borrow<str>,
     {
-        let mut request = self.client.get(url);
-
-        if let Some(headers_map) = headers {
-            for (key, value) in headers_map {
-                request = request.header(key.borrow(), value.borrow());
-            }
-        }
+        let mut request = HttpRequest::new(HttpMethod::Get, url).headers(headers);
 
         if let Some(body_content) = body {
-            request = request.body(body_content.to_string());
+            request = request.body(body_content);
         }
 
-        self.execute_request(request).await
+        self.send(request).await
     }
 
     pub async fn post<K, V>(
@@ -85,13 +797,7 @@ impl HttpClient {
         K: Borrow<str>,
         V: Borrow<str>,
     {
-        let mut request = self.client.post(url);
-
-        if let Some(headers_map) = headers {
-            for (key, value) in headers_map {
-                request = request.header(key.borrow(), value.borrow());
-            }
-        }
+        let mut request = HttpRequest::new(HttpMethod::Post, url).headers(headers);
 
         if let Some(params_map) = params {
             let json_map = params_map.into_iter()
@@ -100,13 +806,33 @@ impl HttpClient {
                         .map(|val| (k.borrow().to_string(), val))
                         .ok()
                 })
-                .collect::<HashMap<String, Value>>();
-            request = request.json(&json_map);
+                .collect::<serde_json::Map<String, Value>>();
+            request = request.json(Value::Object(json_map));
         } else if let Some(body_content) = body {
-            request = request.body(body_content.to_string());
+            request = request.body(body_content);
         }
 
-        self.execute_request(request).await
+        self.send(request).await
+    }
+
+    pub async fn put(&self, request: HttpRequest) -> Result<HttpResponse, Box<dyn std::error::Error>> {
+        self.send(HttpRequest { method: HttpMethod::Put, ..request }).await
+    }
+
+    pub async fn patch(&self, request: HttpRequest) -> Result<HttpResponse, Box<dyn std::error::Error>> {
+        self.send(HttpRequest { method: HttpMethod::Patch, ..request }).await
+    }
+
+    pub async fn delete(&self, request: HttpRequest) -> Result<HttpResponse, Box<dyn std::error::Error>> {
+        self.send(HttpRequest { method: HttpMethod::Delete, ..request }).await
+    }
+
+    pub async fn head(&self, request: HttpRequest) -> Result<HttpResponse, Box<dyn std::error::Error>> {
+        self.send(HttpRequest { method: HttpMethod::Head, ..request }).await
+    }
+
+    pub async fn options(&self, request: HttpRequest) -> Result<HttpResponse, Box<dyn std::error::Error>> {
+        self.send(HttpRequest { method: HttpMethod::Options, ..request }).await
     }
 
     pub async fn download<K, V>(
@@ -119,31 +845,835 @@ impl HttpClient {
         K: Borrow<str>,
         V: Borrow<str>,
     {
-        let mut request = self.client.get(url);
+        let request = HttpRequest::new(HttpMethod::Get, url).headers(headers);
+        self.download_request(request, output_path, &DownloadOptions::default(), |_, _| {}).await
+    }
 
-        if let Some(headers_map) = headers {
-            for (key, value) in headers_map {
-                request = request.header(key.borrow(), value.borrow());
+    /// Streams the response body of `request` into `output_path`.
+    ///
+    /// With `options.resume`, an existing partial file is continued with a `Range`
+    /// request guarded by `If-Range`, using the ETag/Last-Modified saved next to it
+    /// by the interrupted download. If the server no longer matches, the file is
+    /// downloaded again from scratch. `on_progress` receives bytes written so far
+    /// (including the resumed prefix) and the total size when known.
+    /// Non-2xx responses are returned with their body and nothing is written.
+    pub async fn download_request<P>(
+        &self,
+        request: HttpRequest,
+        output_path: &Path,
+        options: &DownloadOptions,
+        mut on_progress: P,
+    ) -> Result<HttpResponse, Box<dyn std::error::Error>>
+    where
+        P: FnMut(u64, Option<u64>),
+    {
+        let validator_path = download_validator_path(output_path);
+        let mut resume = options.resume;
+
+        loop {
+            let mut attempt = request.clone();
+            let mut offset = 0;
+
+            if resume {
+                let existing = tokio::fs::metadata(output_path).await.map(|m| m.len()).unwrap_or(0);
+                if existing > 0
+                    && let Ok(validator) = tokio::fs::read_to_string(&validator_path).await
+                {
+                    offset = existing;
+                    attempt = attempt
+                        .header("Range", &format!("bytes={}-", offset))
+                        .header("If-Range", validator.trim());
+                }
+            }
+
+            let (sent, response) = self.send_with_retry(attempt).await?;
+            let head = HttpResponse::head(&sent, &response);
+            let status = head.status;
+            let headers = head.headers.clone();
+
+            if status == StatusCode::RANGE_NOT_SATISFIABLE && offset > 0 {
+                resume = false;
+                continue;
+            }
+
+            if !status.is_success() {
+                let response = HttpResponse { body: self.read_body(response).await.ok(), ..head };
+                self.intercept_response(&sent, &response)?;
+                return Ok(response);
+            }
+            self.intercept_response(&sent, &head)?;
+
+            let partial = offset > 0 && status == StatusCode::PARTIAL_CONTENT;
+            if !partial {
+                offset = 0;
             }
+            let total = if partial {
+                content_range_total(&headers).or(response.content_length().map(|len| offset + len))
+            } else {
+                response.content_length()
+            };
+
+            let validator = headers.get(ETAG)
+                .or(headers.get(LAST_MODIFIED))
+                .and_then(|v| v.to_str().ok());
+            match validator {
+                Some(validator) => tokio::fs::write(&validator_path, validator).await?,
+                None => { let _ = tokio::fs::remove_file(&validator_path).await; }
+            }
+
+            let mut file = if partial {
+                tokio::fs::OpenOptions::new().append(true).open(output_path).await?
+            } else {
+                tokio::fs::File::create(output_path).await?
+            };
+
+            // Hash as we go; a resumed download first replays the bytes already on disk
+            let mut hasher = match &options.expected_sha256 {
+                Some(_) if partial => Some(hash_file_prefix(output_path, offset).await?),
+                Some(_) => Some(Sha256::new()),
+                None => None,
+            };
+
+            // Stream the response body to file
+            let mut received = offset;
+            let mut stream = response.bytes_stream();
+            while let Some(chunk) = self.next_chunk(&mut stream).await? {
+                file.write_all(&chunk).await?;
+                if let Some(hasher) = hasher.as_mut() {
+                    hasher.update(&chunk);
+                }
+                received += chunk.len() as u64;
+                on_progress(received, total);
+            }
+            file.flush().await?;
+            let _ = tokio::fs::remove_file(&validator_path).await;
+
+            if let (Some(expected), Some(hasher)) = (&options.expected_sha256, hasher) {
+                let actual = bytes2hex(&hasher.finalize());
+                if !actual.eq_ignore_ascii_case(expected.trim()) {
+                    let _ = tokio::fs::remove_file(output_path).await;
+                    return Err(NGenError::new(
+                        ErrorCode::ChecksumMismatch,
+                        format!("SHA-256 mismatch: expected {}, got {}", expected, actual),
+                    ).into());
+                }
+            }
+
+            return Ok(head);
         }
+    }
+}
 
-        let response = request.send().await?;
-        let status = response.status();
-        let headers = response.headers().clone();
+/// Runs a cache operation on the blocking pool, since redb commits and eviction
+/// scans are synchronous file I/O
+async fn with_cache<T, F>(cache: &Arc<HttpCache>, op: F) -> Result<T, NGenError>
+where
+    F: FnOnce(&HttpCache) -> Result<T, NGenError> + Send + 'static,
+    T: Send + 'static,
+{
+    let cache = cache.clone();
+    tokio::task::spawn_blocking(move || op(&cache)).await
+        .map_err(|e| NGenError::new(ErrorCode::Unknown, e.to_string()))?
+}
 
-        // Stream the response body to file
-        let mut file = tokio::fs::File::create(output_path).await?;
-        let mut stream = response.bytes_stream(); // Now this will work
+/// SHA-256 state over the first `len` bytes of an existing file, read in chunks
+async fn hash_file_prefix(path: &Path, len: u64) -> std::io::Result<Sha256> {
+    let mut hasher = Sha256::new();
+    let mut file = tokio::fs::File::open(path).await?.take(len);
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            return Ok(hasher);
+        }
+        hasher.update(&buf[..n]);
+    }
+}
+
+fn download_validator_path(output_path: &Path) -> PathBuf {
+    let mut name = output_path.as_os_str().to_owned();
+    name.push(".etag");
+    PathBuf::from(name)
+}
 
-        while let Some(chunk) = stream.next().await {
-            let chunk = chunk?;
-            tokio::io::copy(&mut chunk.as_ref(), &mut file).await?;
+/// Total length from a `Content-Range: bytes start-end/total` header
+fn content_range_total(headers: &HeaderMap) -> Option<u64> {
+    let value = headers.get(CONTENT_RANGE)?.to_str().ok()?;
+    value.rsplit('/').next()?.parse().ok()
+}
+
+/// Builds the identity for both reqwest and the raw native-tls connector
+fn load_identity(
+    identity: &ClientIdentity,
+) -> Result<(reqwest::Identity, native_tls::Identity), Box<dyn std::error::Error>> {
+    match identity {
+        ClientIdentity::Pem { cert, key } => {
+            // native-tls only accepts PKCS#8 keys, so convert PKCS#1 RSA keys first
+            let key = match rsa_import_private_key(key) {
+                Ok(rsa_key) => rsa_export_private_key(&rsa_key, RsaKeyFormat::Pkcs8Pem)?,
+                Err(_) => key.clone(),
+            };
+            Ok((
+                reqwest::Identity::from_pkcs8_pem(cert, &key)?,
+                native_tls::Identity::from_pkcs8(cert, &key)?,
+            ))
         }
+        ClientIdentity::Pkcs12 { der, password } => Ok((
+            reqwest::Identity::from_pkcs12_der(der, password)?,
+            native_tls::Identity::from_pkcs12(der, password)?,
+        )),
+    }
+}
 
-        Ok(HttpResponse {
-            status,
-            headers,
-            body: None,
-        })
+/// rustls identity for pinned clients, from the same material as `load_identity`
+fn rustls_identity(
+    identity: &ClientIdentity,
+) -> Result<(Vec<rustls::Certificate>, rustls::PrivateKey), Box<dyn std::error::Error>> {
+    match identity {
+        ClientIdentity::Pem { cert, key } => {
+            let chain = rustls_pemfile::certs(&mut cert.as_slice())?
+                .into_iter()
+                .map(rustls::Certificate)
+                .collect();
+            let key = rustls_pemfile::read_all(&mut key.as_slice())?
+                .into_iter()
+                .find_map(|item| match item {
+                    rustls_pemfile::Item::PKCS8Key(der)
+                    | rustls_pemfile::Item::RSAKey(der)
+                    | rustls_pemfile::Item::ECKey(der) => Some(der),
+                    _ => None,
+                })
+                .ok_or_else(|| NGenError::invalid_argument("No private key in client key PEM"))?;
+            Ok((chain, rustls::PrivateKey(key)))
+        }
+        ClientIdentity::Pkcs12 { der, password } => {
+            let store = p12_keystore::KeyStore::from_pkcs12(der, password)?;
+            let (_, key_chain) = store.private_key_chain()
+                .ok_or_else(|| NGenError::invalid_argument("No private key in PKCS#12 archive"))?;
+            let chain = key_chain.chain().iter()
+                .map(|cert| rustls::Certificate(cert.as_der().to_vec()))
+                .collect();
+            Ok((chain, rustls::PrivateKey(key_chain.key().to_vec())))
+        }
+    }
+}
+
+/// TLS settings for clients with pins: the usual chain verification against the
+/// Mozilla roots plus `ca_cert`, followed by the pin check, all during the handshake
+fn pinned_tls_config(
+    pins: &HashMap<String, Vec<String>>,
+    ca_cert: Option<&[u8]>,
+    identity: Option<&ClientIdentity>,
+) -> Result<rustls::ClientConfig, Box<dyn std::error::Error>> {
+    let mut roots = rustls::RootCertStore::empty();
+    let mut trusted = Vec::new();
+    roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+        rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
+            anchor.subject,
+            anchor.spki,
+            anchor.name_constraints,
+        )
+    }));
+    if let Some(pem) = ca_cert {
+        for der in rustls_pemfile::certs(&mut &pem[..])? {
+            // Self-signed server certificates are not valid roots for webpki
+            let _ = roots.add(&rustls::Certificate(der.clone()));
+            trusted.push(der);
+        }
+    }
+
+    let builder = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(PinnedCertVerifier {
+            inner: rustls::client::WebPkiVerifier::new(roots, None),
+            trusted,
+            pins: pins.clone(),
+        }));
+    Ok(match identity {
+        Some(identity) => {
+            let (chain, key) = rustls_identity(identity)?;
+            builder.with_client_auth_cert(chain, key)?
+        }
+        None => builder.with_no_client_auth(),
+    })
+}
+
+struct PinnedCertVerifier {
+    inner: rustls::client::WebPkiVerifier,
+    /// DER of the certificates from `ca_cert_path`
+    trusted: Vec<Vec<u8>>,
+    pins: HashMap<String, Vec<String>>,
+}
+
+impl rustls::client::ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &rustls::Certificate,
+        intermediates: &[rustls::Certificate],
+        server_name: &rustls::ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: std::time::SystemTime,
+    ) -> Result<rustls::client::ServerCertVerified, rustls::Error> {
+        let host = match server_name {
+            rustls::ServerName::DnsName(name) => name.as_ref().to_ascii_lowercase(),
+            rustls::ServerName::IpAddress(ip) => ip.to_string(),
+            _ => String::new(),
+        };
+        let verified = match self.inner.verify_server_cert(
+            end_entity, intermediates, server_name, scts, ocsp_response, now,
+        ) {
+            Ok(verified) => verified,
+            // As native-tls does, trust a certificate that was itself configured as a
+            // root (typically self-signed), but only for pinned hosts
+            Err(_) if self.pins.contains_key(&host) && self.trusted.contains(&end_entity.0) => {
+                rustls::client::ServerCertVerified::assertion()
+            }
+            Err(e) => return Err(e),
+        };
+        check_pins(&self.pins, &host, Some(&end_entity.0)).map_err(|e| {
+            rustls::Error::InvalidCertificate(rustls::CertificateError::Other(Arc::new(e)))
+        })?;
+        Ok(verified)
+    }
+}
+
+/// Checks a server's leaf certificate against the pins configured for `host`
+fn check_pins(
+    pins: &HashMap<String, Vec<String>>,
+    host: &str,
+    peer_certificate: Option<&[u8]>,
+) -> Result<(), NGenError> {
+    let Some(pins) = pins.get(&host.to_ascii_lowercase()) else {
+        return Ok(());
+    };
+
+    let Some(spki) = peer_certificate.and_then(certificate_spki) else {
+        return Err(NGenError::new(
+            ErrorCode::CertificatePinMismatch,
+            format!("No server certificate available to verify pins for {}", host),
+        ));
+    };
+
+    let actual = String::from_utf8(base64_encode(&hash_sha256(spki))).unwrap_or_default();
+    if pins.contains(&actual) {
+        Ok(())
+    } else {
+        Err(NGenError::new(
+            ErrorCode::CertificatePinMismatch,
+            format!("Certificate pin mismatch for {}: sha256/{}", host, actual),
+        ))
+    }
+}
+
+/// Pin mismatch raised by `PinnedCertVerifier`, found among a request error's sources
+fn pin_error(err: &reqwest::Error) -> Option<NGenError> {
+    let mut current: Option<&(dyn std::error::Error + 'static)> = Some(err);
+    while let Some(e) = current {
+        if let Some(rustls::Error::InvalidCertificate(rustls::CertificateError::Other(other))) =
+            e.downcast_ref::<rustls::Error>()
+        {
+            return other.downcast_ref::<NGenError>().cloned();
+        }
+        // `io::Error::source` skips the error it wraps, so step into it explicitly
+        current = match e.downcast_ref::<std::io::Error>().and_then(|io| io.get_ref()) {
+            Some(inner) => Some(inner),
+            None => e.source(),
+        };
     }
-}
\ No newline at end of file
+    None
+}
+
+/// (tag, whole element, contents, remainder)
+type DerElement<'a> = (u8, &'a [u8], &'a [u8], &'a [u8]);
+
+/// Splits one DER element off `input`
+fn der_element(input: &[u8]) -> Option<DerElement<'_>> {
+    let (&tag, rest) = input.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (len, rest) = if first & 0x80 == 0 {
+        (first as usize, rest)
+    } else {
+        let count = (first & 0x7f) as usize;
+        if count == 0 || count > 4 || rest.len() < count {
+            return None;
+        }
+        let len = rest[..count].iter().fold(0usize, |acc, b| (acc << 8) | *b as usize);
+        (len, &rest[count..])
+    };
+    if rest.len() < len {
+        return None;
+    }
+    let header_len = input.len() - rest.len();
+    Some((tag, &input[..header_len + len], &rest[..len], &rest[len..]))
+}
+
+/// DER `SubjectPublicKeyInfo` of an X.509 certificate
+fn certificate_spki(cert: &[u8]) -> Option<&[u8]> {
+    let (_, _, certificate, _) = der_element(cert)?;
+    let (_, _, tbs, _) = der_element(certificate)?;
+    let mut rest = tbs;
+    // Optional explicit [0] version
+    if rest.first() == Some(&0xa0) {
+        rest = der_element(rest)?.3;
+    }
+    // serialNumber, signature, issuer, validity, subject
+    for _ in 0..5 {
+        rest = der_element(rest)?.3;
+    }
+    der_element(rest).map(|(_, spki, _, _)| spki)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WsMessage {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WsState {
+    Connecting = 0,
+    Open = 1,
+    Reconnecting = 2,
+    Closed = 3,
+}
+
+pub enum WsEvent {
+    State(WsState),
+    Message(WsMessage),
+    /// Close frame received from the server: code and reason
+    PeerClosed(u16, String),
+    /// Connection or protocol failure; a reconnect follows if enabled
+    Error(NGenError),
+}
+
+#[derive(Clone, Debug)]
+pub struct WsConfig {
+    pub headers: Vec<(String, String)>,
+    /// Ping interval; the connection is considered dead when nothing arrives
+    /// for two intervals. `None` disables keepalive.
+    pub ping_interval: Option<Duration>,
+    /// Reconnect backoff after a dropped connection; `max_attempts` counts
+    /// consecutive failed reconnects (0 = unlimited). `None` disables reconnecting.
+    /// A normal close (1000) from the server never triggers a reconnect.
+    pub reconnect: Option<RetryPolicy>,
+}
+
+impl Default for WsConfig {
+    fn default() -> Self {
+        Self {
+            headers: Vec::new(),
+            ping_interval: Some(Duration::from_secs(30)),
+            reconnect: Some(RetryPolicy {
+                max_attempts: 0,
+                initial_backoff: Duration::from_millis(500),
+                max_backoff: Duration::from_secs(30),
+                ..Default::default()
+            }),
+        }
+    }
+}
+
+enum WsCommand {
+    Send(WsMessage),
+    Close(u16, String),
+}
+
+enum WsSessionEnd {
+    Closed,
+    PeerClosed(u16),
+    Failed(NGenError),
+}
+
+/// Handle to a WebSocket connection driven by the future returned from
+/// `HttpClient::websocket`. Messages sent while reconnecting are queued.
+/// Dropping every handle closes the connection with code 1000.
+#[derive(Clone)]
+pub struct WebSocket {
+    commands: tokio::sync::mpsc::UnboundedSender<WsCommand>,
+}
+
+impl WebSocket {
+    pub fn send(&self, message: WsMessage) -> Result<(), NGenError> {
+        self.commands.send(WsCommand::Send(message))
+            .map_err(|_| NGenError::new(ErrorCode::Network, "WebSocket is closed"))
+    }
+
+    pub fn close(&self, code: u16, reason: &str) -> Result<(), NGenError> {
+        self.commands.send(WsCommand::Close(code, reason.to_string()))
+            .map_err(|_| NGenError::new(ErrorCode::Network, "WebSocket is closed"))
+    }
+}
+
+type WsStream = tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+impl HttpClient {
+    /// Creates a WebSocket for `url` (ws:// or wss://) using this client's TLS
+    /// settings, pins, connect timeout and User-Agent. Nothing happens until the
+    /// returned future is spawned; it runs until the socket is closed, reporting
+    /// everything through `on_event`.
+    pub fn websocket<F>(
+        &self,
+        url: &str,
+        config: WsConfig,
+        on_event: F,
+    ) -> (WebSocket, impl std::future::Future<Output = ()> + Send + 'static)
+    where
+        F: FnMut(WsEvent) + Send + 'static,
+    {
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let client = self.clone();
+        let url = url.to_string();
+        let driver = async move {
+            client.drive_websocket(url, config, rx, on_event).await;
+        };
+        (WebSocket { commands: tx }, driver)
+    }
+
+    async fn connect_websocket(&self, url: &str, config: &WsConfig) -> Result<WsStream, NGenError> {
+        use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+        use tokio_tungstenite::tungstenite::http::{HeaderName, HeaderValue};
+
+        let invalid = |e: &dyn std::fmt::Display| NGenError::invalid_argument(e.to_string());
+        let mut request = url.into_client_request().map_err(|e| invalid(&e))?;
+        let headers = request.headers_mut();
+        headers.insert("User-Agent", HeaderValue::from_str(&self.user_agent).map_err(|e| invalid(&e))?);
+        for (key, value) in &config.headers {
+            headers.insert(
+                HeaderName::from_bytes(key.as_bytes()).map_err(|e| invalid(&e))?,
+                HeaderValue::from_str(value).map_err(|e| invalid(&e))?,
+            );
+        }
+        if let Some(jar) = &self.cookie_jar {
+            let cookies = reqwest::Url::parse(url).ok()
+                .and_then(|url| reqwest::cookie::CookieStore::cookies(jar.as_ref(), &url))
+                .and_then(|value| HeaderValue::from_bytes(value.as_bytes()).ok());
+            if let Some(cookies) = cookies {
+                headers.insert("Cookie", cookies);
+            }
+        }
+        let host = request.uri().host().unwrap_or_default()
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string();
+        let secure = request.uri().scheme_str() == Some("wss");
+        let port = request.uri().port_u16().unwrap_or(if secure { 443 } else { 80 });
+
+        let network = |e: &dyn std::fmt::Display| NGenError::new(ErrorCode::Network, e.to_string());
+        // The TLS handshake is done here rather than by tungstenite so the pins are
+        // checked before the upgrade request (headers, cookies) is sent
+        let connect = async {
+            let tcp = tokio::net::TcpStream::connect((host.as_str(), port)).await
+                .map_err(|e| network(&e))?;
+            let stream = if secure {
+                let connector = tokio_native_tls::TlsConnector::from(self.tls_connector.clone());
+                let tls = connector.connect(&host, tcp).await.map_err(|e| network(&e))?;
+                let peer_certificate = tls.get_ref()
+                    .peer_certificate().ok().flatten()
+                    .and_then(|cert| cert.to_der().ok());
+                self.verify_host_pins(&host, peer_certificate.as_deref())?;
+                tokio_tungstenite::MaybeTlsStream::NativeTls(tls)
+            } else {
+                tokio_tungstenite::MaybeTlsStream::Plain(tcp)
+            };
+            tokio_tungstenite::client_async_with_config(request, stream, None).await
+                .map_err(|e| network(&e))
+        };
+        let (stream, _) = match self.connect_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, connect).await
+                .map_err(|_| NGenError::new(ErrorCode::Timeout, "WebSocket connect timed out"))??,
+            None => connect.await?,
+        };
+        Ok(stream)
+    }
+
+    async fn drive_websocket<F>(
+        &self,
+        url: String,
+        config: WsConfig,
+        mut commands: tokio::sync::mpsc::UnboundedReceiver<WsCommand>,
+        mut on_event: F,
+    ) where
+        F: FnMut(WsEvent),
+    {
+        let mut pending = std::collections::VecDeque::new();
+        let mut failures = 0;
+        on_event(WsEvent::State(WsState::Connecting));
+
+        loop {
+            let end = match self.connect_websocket(&url, &config).await {
+                Ok(stream) => {
+                    failures = 0;
+                    on_event(WsEvent::State(WsState::Open));
+                    run_websocket(stream, &config, &mut pending, &mut commands, &mut on_event).await
+                }
+                Err(e) => WsSessionEnd::Failed(e),
+            };
+
+            match end {
+                WsSessionEnd::Closed | WsSessionEnd::PeerClosed(1000) => break,
+                WsSessionEnd::PeerClosed(_) => {}
+                WsSessionEnd::Failed(e) => on_event(WsEvent::Error(e)),
+            }
+
+            let Some(policy) = &config.reconnect else { break };
+            failures += 1;
+            if policy.max_attempts > 0 && failures > policy.max_attempts {
+                break;
+            }
+            on_event(WsEvent::State(WsState::Reconnecting));
+
+            // Keep accepting commands while waiting so a close request is honoured
+            let backoff = tokio::time::sleep(policy.backoff(failures));
+            tokio::pin!(backoff);
+            let closed = loop {
+                tokio::select! {
+                    _ = &mut backoff => break false,
+                    command = commands.recv() => match command {
+                        Some(WsCommand::Send(message)) => pending.push_back(message),
+                        Some(WsCommand::Close(..)) | None => break true,
+                    },
+                }
+            };
+            if closed {
+                break;
+            }
+        }
+
+        on_event(WsEvent::State(WsState::Closed));
+    }
+}
+
+async fn run_websocket<F>(
+    stream: WsStream,
+    config: &WsConfig,
+    pending: &mut std::collections::VecDeque<WsMessage>,
+    commands: &mut tokio::sync::mpsc::UnboundedReceiver<WsCommand>,
+    on_event: &mut F,
+) -> WsSessionEnd
+where
+    F: FnMut(WsEvent),
+{
+    use futures::SinkExt;
+    use tokio_tungstenite::tungstenite::Message;
+    use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+
+    fn to_frame(message: WsMessage) -> Message {
+        match message {
+            WsMessage::Text(text) => Message::Text(text),
+            WsMessage::Binary(data) => Message::Binary(data),
+        }
+    }
+    let failed = |e: tokio_tungstenite::tungstenite::Error| {
+        WsSessionEnd::Failed(NGenError::new(ErrorCode::Network, e.to_string()))
+    };
+
+    let (mut sink, mut stream) = stream.split();
+    while let Some(message) = pending.pop_front() {
+        if let Err(e) = sink.send(to_frame(message.clone())).await {
+            pending.push_front(message);
+            return failed(e);
+        }
+    }
+
+    let mut keepalive = config.ping_interval.map(|period| {
+        tokio::time::interval_at(tokio::time::Instant::now() + period, period)
+    });
+    let mut last_seen = tokio::time::Instant::now();
+
+    loop {
+        let tick = async {
+            match keepalive.as_mut() {
+                Some(interval) => interval.tick().await,
+                None => std::future::pending().await,
+            }
+        };
+
+        tokio::select! {
+            frame = stream.next() => {
+                last_seen = tokio::time::Instant::now();
+                match frame {
+                    Some(Ok(Message::Text(text))) => on_event(WsEvent::Message(WsMessage::Text(text))),
+                    Some(Ok(Message::Binary(data))) => on_event(WsEvent::Message(WsMessage::Binary(data))),
+                    Some(Ok(Message::Close(frame))) => {
+                        let (code, reason) = frame
+                            .map(|f| (u16::from(f.code), f.reason.into_owned()))
+                            .unwrap_or((1005, String::new()));
+                        on_event(WsEvent::PeerClosed(code, reason));
+                        return WsSessionEnd::PeerClosed(code);
+                    }
+                    // Pings are answered by tungstenite itself
+                    Some(Ok(_)) => {}
+                    Some(Err(e)) => return failed(e),
+                    None => return WsSessionEnd::Failed(NGenError::new(ErrorCode::Network, "WebSocket connection lost")),
+                }
+            }
+            command = commands.recv() => {
+                let (code, reason) = match command {
+                    Some(WsCommand::Send(message)) => {
+                        if let Err(e) = sink.send(to_frame(message.clone())).await {
+                            pending.push_back(message);
+                            return failed(e);
+                        }
+                        continue;
+                    }
+                    Some(WsCommand::Close(code, reason)) => (code, reason),
+                    None => (1000, String::new()),
+                };
+                let frame = CloseFrame { code: code.into(), reason: reason.into() };
+                let _ = sink.send(Message::Close(Some(frame))).await;
+                // Give the server a moment to echo the close frame
+                let _ = tokio::time::timeout(Duration::from_secs(5), async {
+                    while let Some(Ok(frame)) = stream.next().await {
+                        if matches!(frame, Message::Close(_)) {
+                            break;
+                        }
+                    }
+                }).await;
+                return WsSessionEnd::Closed;
+            }
+            _ = tick => {
+                let period = config.ping_interval.unwrap_or_default();
+                if last_seen.elapsed() > period * 2 {
+                    return WsSessionEnd::Failed(NGenError::new(ErrorCode::Timeout, "WebSocket keepalive timed out"));
+                }
+                if let Err(e) = sink.send(Message::Ping(Vec::new())).await {
+                    return failed(e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/tls");
+    /// SPKI SHA-256 of `server.pem`
+    const SERVER_PIN: &str = "/9t9Jab6sm9Bp26ebuIbJgrWgXMS7pD9Wiz4o1rKtvM=";
+    const WRONG_PIN: &str = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+
+    /// HTTPS server for `localhost` signed by the fixture CA. Answers every
+    /// request with `200 ok` and counts the requests that reached it.
+    fn serve_tls() -> (u16, Arc<AtomicUsize>) {
+        let cert = std::fs::read(Path::new(FIXTURES).join("server.pem")).unwrap();
+        let key = std::fs::read(Path::new(FIXTURES).join("server.key")).unwrap();
+        let identity = native_tls::Identity::from_pkcs8(&cert, &key).unwrap();
+        let acceptor = native_tls::TlsAcceptor::new(identity).unwrap();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let Ok(mut tls) = acceptor.accept(stream) else { continue };
+                let mut buf = [0u8; 4096];
+                if matches!(tls.read(&mut buf), Ok(n) if n > 0) {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    let _ = tls.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok");
+                }
+            }
+        });
+        (port, requests)
+    }
+
+    /// Plain HTTP server redirecting every request to `location`
+    fn serve_redirect(location: String) -> u16 {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut buf = [0u8; 4096];
+                let _ = stream.read(&mut buf);
+                let response = format!(
+                    "HTTP/1.1 302 Found\r\nLocation: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    location,
+                );
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        port
+    }
+
+    fn client_with_pin(host: &str, pin: &str) -> HttpClient {
+        HttpClient::with_config(HttpClientConfig {
+            ca_cert_path: Some(Path::new(FIXTURES).join("ca.pem")),
+            pins: HashMap::from([(host.to_string(), vec![format!("sha256/{}", pin)])]),
+            ..Default::default()
+        }).unwrap()
+    }
+
+    fn error_code(err: Box<dyn std::error::Error>) -> ErrorCode {
+        NGenError::from_boxed(err, ErrorCode::Unknown).code
+    }
+
+    #[test]
+    fn pinned_host_with_matching_key_is_accepted() {
+        let (port, requests) = serve_tls();
+        let client = client_with_pin("localhost", SERVER_PIN);
+        let url = format!("https://localhost:{}/", port);
+        let response = RUNTIME.block_on(client.send(HttpRequest::new(HttpMethod::Get, &url))).unwrap();
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.body.as_deref(), Some(&b"ok"[..]));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn pin_mismatch_fails_before_the_request_is_sent() {
+        let (port, requests) = serve_tls();
+        let client = client_with_pin("localhost", WRONG_PIN);
+        let request = HttpRequest::new(HttpMethod::Post, &format!("https://localhost:{}/", port))
+            .header("Authorization", "Bearer secret")
+            .body("payload");
+        let err = RUNTIME.block_on(client.send(request)).err().unwrap();
+        assert_eq!(error_code(err), ErrorCode::CertificatePinMismatch);
+        assert_eq!(requests.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn hosts_without_pins_are_not_checked() {
+        let (port, requests) = serve_tls();
+        let client = client_with_pin("example.com", WRONG_PIN);
+        let url = format!("https://localhost:{}/", port);
+        let response = RUNTIME.block_on(client.send(HttpRequest::new(HttpMethod::Get, &url))).unwrap();
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn redirects_to_a_pinned_host_are_checked() {
+        let (port, requests) = serve_tls();
+        let redirect_port = serve_redirect(format!("https://localhost:{}/", port));
+        let client = client_with_pin("localhost", WRONG_PIN);
+        let url = format!("http://127.0.0.1:{}/", redirect_port);
+        let err = RUNTIME.block_on(client.send(HttpRequest::new(HttpMethod::Get, &url))).err().unwrap();
+        assert_eq!(error_code(err), ErrorCode::CertificatePinMismatch);
+        assert_eq!(requests.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn websocket_pins_are_checked_before_the_upgrade_request() {
+        let connect = |pin: &str| {
+            let (port, requests) = serve_tls();
+            let errors = Arc::new(std::sync::Mutex::new(Vec::new()));
+            let seen = errors.clone();
+            let config = WsConfig { reconnect: None, ..Default::default() };
+            let url = format!("wss://localhost:{}/", port);
+            let (_ws, driver) = client_with_pin("localhost", pin).websocket(&url, config, move |event| {
+                if let WsEvent::Error(e) = event {
+                    seen.lock().unwrap().push(e.code);
+                }
+            });
+            RUNTIME.block_on(driver);
+            let errors = errors.lock().unwrap().clone();
+            (errors, requests.load(Ordering::SeqCst))
+        };
+
+        assert_eq!(connect(WRONG_PIN), (vec![ErrorCode::CertificatePinMismatch], 0));
+        // The fixture server is not a WebSocket server, so the upgrade itself fails
+        assert_eq!(connect(SERVER_PIN), (vec![ErrorCode::Network], 1));
+    }
+}
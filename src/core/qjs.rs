@@ -1,43 +1,757 @@
-use crate::c::util::{cstr_to_rust, ngenrs_free_cstr, rust_to_cstr};
+use crate::c::util::cstr_to_rust;
+use crate::core::error::{ErrorCode, NGenError};
+use crate::core::net::{HttpClient, HttpInterceptor, HttpMethod, HttpRequest, HttpResponse, RUNTIME};
 use libquickjs_ng_sys::{
     JS_Call, JS_Eval, JS_FreeValue, JS_GetException, JS_GetGlobalObject, JS_GetPropertyStr,
     JS_HasException, JS_NewContext, JS_NewRuntime, JS_NewStringLen, JS_SetPropertyStr,
     JSContext, JSRuntime, JSValue,
 };
-use std::ffi::CString;
+use parking_lot::{ReentrantMutex, ReentrantMutexGuard};
+use serde_json::{json, Value};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::ffi::{c_char, c_int, CStr, CString};
 use std::fs;
-use std::path::Path;
-use std::sync::{Arc, Mutex};
+use std::ops::Deref;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// WHATWG `fetch`, `Headers`, `Request` and `Response` on top of the native
+/// `send(method, url, headers, body, onload, onerror)`, `encode` and `decode`.
+/// Bodies are kept as ArrayBuffers; streams and `AbortSignal` are not supported.
+const FETCH_PRELUDE: &str = r#"
+(function (native) {
+    const bodies = new WeakMap();
+
+    function toBuffer(body) {
+        if (body === null || body === undefined) {
+            return null;
+        }
+        if (body instanceof ArrayBuffer) {
+            return body.slice(0);
+        }
+        if (ArrayBuffer.isView(body)) {
+            return body.buffer.slice(body.byteOffset, body.byteOffset + body.byteLength);
+        }
+        return native.encode(String(body));
+    }
+
+    class Headers {
+        #map = new Map();
+
+        constructor(init) {
+            if (init instanceof Headers || Array.isArray(init)) {
+                for (const [name, value] of init) {
+                    this.append(name, value);
+                }
+            } else if (init) {
+                for (const name of Object.keys(init)) {
+                    this.append(name, init[name]);
+                }
+            }
+        }
+
+        append(name, value) {
+            const key = String(name).toLowerCase();
+            const previous = this.#map.get(key);
+            this.#map.set(key, previous === undefined ? String(value) : previous + ", " + value);
+        }
+        set(name, value) { this.#map.set(String(name).toLowerCase(), String(value)); }
+        get(name) { return this.#map.get(String(name).toLowerCase()) ?? null; }
+        has(name) { return this.#map.has(String(name).toLowerCase()); }
+        delete(name) { this.#map.delete(String(name).toLowerCase()); }
+
+        forEach(callback, thisArg) {
+            for (const [name, value] of this) {
+                callback.call(thisArg, value, name, this);
+            }
+        }
+        entries() {
+            return [...this.#map.entries()].sort((a, b) => (a[0] < b[0] ? -1 : a[0] > b[0] ? 1 : 0)).values();
+        }
+        keys() { return [...this.entries()].map(([name]) => name).values(); }
+        values() { return [...this.entries()].map(([, value]) => value).values(); }
+        [Symbol.iterator]() { return this.entries(); }
+    }
+
+    class Body {
+        constructor(body) {
+            bodies.set(this, { buffer: toBuffer(body), used: false });
+        }
+
+        get bodyUsed() { return bodies.get(this).used; }
+
+        arrayBuffer() {
+            const state = bodies.get(this);
+            if (state.used) {
+                return Promise.reject(new TypeError("Body has already been consumed"));
+            }
+            state.used = true;
+            return Promise.resolve(state.buffer ?? new ArrayBuffer(0));
+        }
+        bytes() { return this.arrayBuffer().then((buffer) => new Uint8Array(buffer)); }
+        text() { return this.arrayBuffer().then(native.decode); }
+        json() { return this.text().then(JSON.parse); }
+    }
+
+    class Request extends Body {
+        constructor(input, init = {}) {
+            const source = input instanceof Request ? input : null;
+            if (source && source.bodyUsed) {
+                throw new TypeError("Request body has already been consumed");
+            }
+            super(init.body !== undefined ? init.body : source ? bodies.get(source).buffer : null);
+            this.url = source ? source.url : String(input);
+            this.method = String(init.method ?? source?.method ?? "GET").toUpperCase();
+            this.headers = new Headers(init.headers ?? source?.headers);
+            if (typeof init.body === "string" && !this.headers.has("content-type")) {
+                this.headers.set("content-type", "text/plain;charset=UTF-8");
+            }
+            if (init.body != null && (this.method === "GET" || this.method === "HEAD")) {
+                throw new TypeError("Request with GET/HEAD method cannot have body");
+            }
+        }
+
+        clone() { return new Request(this); }
+    }
+
+    class Response extends Body {
+        constructor(body = null, init = {}) {
+            super(body);
+            this.status = init.status ?? 200;
+            this.statusText = init.statusText ?? "";
+            this.headers = new Headers(init.headers);
+            this.type = "default";
+            this.url = "";
+            this.redirected = false;
+        }
+
+        get ok() { return this.status >= 200 && this.status < 300; }
+
+        clone() {
+            if (this.bodyUsed) {
+                throw new TypeError("Response body has already been consumed");
+            }
+            const copy = new Response(bodies.get(this).buffer, this);
+            copy.type = this.type;
+            copy.url = this.url;
+            return copy;
+        }
+
+        static json(data, init = {}) {
+            const headers = new Headers(init.headers);
+            if (!headers.has("content-type")) {
+                headers.set("content-type", "application/json");
+            }
+            return new Response(JSON.stringify(data), { ...init, headers });
+        }
+
+        static error() {
+            const response = new Response(null, { status: 0 });
+            response.type = "error";
+            return response;
+        }
+    }
+
+    function fetch(input, init) {
+        return new Promise((resolve, reject) => {
+            const request = new Request(input, init);
+            native.send(
+                request.method,
+                request.url,
+                [...request.headers],
+                bodies.get(request).buffer,
+                (status, statusText, headers, buffer, url, redirected) => {
+                    const empty = request.method === "HEAD" || status === 204 || status === 304;
+                    const response = new Response(empty ? null : buffer, { status, statusText, headers });
+                    response.type = "basic";
+                    response.url = url;
+                    response.redirected = redirected;
+                    resolve(response);
+                },
+                (message) => reject(new TypeError(message)),
+            );
+        });
+    }
+
+    globalThis.Headers = Headers;
+    globalThis.Request = Request;
+    globalThis.Response = Response;
+    globalThis.fetch = fetch;
+})
+"#;
+
+/// Callbacks of a `fetch` waiting for its response
+struct PendingFetch {
+    onload: JSValue,
+    onerror: JSValue,
+}
+
+struct FetchCompletion {
+    id: u64,
+    result: Result<HttpResponse, String>,
+}
+
+#[derive(Default)]
+struct FetchState {
+    // Created on first use unless set with `JSBridge::set_http_client`
+    client: Option<HttpClient>,
+    next_id: u64,
+    pending: HashMap<u64, PendingFetch>,
+}
+
+/// A `setTimeout`/`setInterval` callback waiting for its deadline
+struct Timer {
+    deadline: Instant,
+    // Scheduling order, breaks ties between timers with the same deadline
+    seq: u64,
+    callback: JSValue,
+    args: Vec<JSValue>,
+    interval: Option<Duration>,
+}
+
+#[derive(Default)]
+struct TimerState {
+    next_id: i32,
+    next_seq: u64,
+    timers: HashMap<i32, Timer>,
+    clock: Clock,
+}
+
+/// Shortest `setInterval` period, so a zero interval can't spin the event loop
+const MIN_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Time source of the timers; tests swap in a manual one to control firing order
+#[derive(Default)]
+enum Clock {
+    #[default]
+    System,
+    #[cfg_attr(not(test), allow(dead_code))]
+    Manual(Instant),
+}
+
+impl Clock {
+    fn now(&self) -> Instant {
+        match self {
+            Clock::System => Instant::now(),
+            Clock::Manual(now) => *now,
+        }
+    }
+}
+
+/// Results of host operations finished on other threads, drained by `JSBridge::poll`
+#[derive(Default)]
+struct Completions {
+    fetches: Mutex<Vec<FetchCompletion>>,
+    ready: Condvar,
+}
+
+impl Completions {
+    fn push_fetch(&self, completion: FetchCompletion) {
+        self.fetches.lock().unwrap().push(completion);
+        self.ready.notify_all();
+    }
+
+    /// Blocks until a completion is queued or `timeout` passes
+    fn wait(&self, timeout: Option<Duration>) {
+        let fetches = self.fetches.lock().unwrap();
+        if !fetches.is_empty() {
+            return;
+        }
+        match timeout {
+            Some(timeout) => drop(self.ready.wait_timeout(fetches, timeout).unwrap()),
+            None => drop(self.ready.wait(fetches).unwrap()),
+        }
+    }
+}
+
+/// Code of an ES module supplied by the host
+#[derive(Clone, Debug)]
+pub enum ModuleSource {
+    Source(String),
+    /// Module bytecode from `qjsc`; must be compiled under the name it is imported as
+    Bytecode(Vec<u8>),
+}
+
+type ModuleResolver = Arc<dyn Fn(&str) -> Option<ModuleSource> + Send>;
+
+/// Where `import` looks for modules: registered modules first, then the resolver,
+/// then files under the base directory (or the working directory if unset)
+#[derive(Default)]
+struct ModuleLoader {
+    base_dir: Option<PathBuf>,
+    modules: HashMap<String, ModuleSource>,
+    resolver: Option<ModuleResolver>,
+}
+
+/// Looks up `name`; the loader is not borrowed while the resolver runs, so it may register modules
+fn find_module(loader: &RefCell<ModuleLoader>, name: &str) -> Result<ModuleSource, String> {
+    let (registered, resolver) = {
+        let loader = loader.borrow();
+        (loader.modules.get(name).cloned(), loader.resolver.clone())
+    };
+    if let Some(source) = registered {
+        return Ok(source);
+    }
+    if let Some(resolver) = resolver
+        && let Some(source) = resolver(name)
+    {
+        return Ok(source);
+    }
+    let path = match &loader.borrow().base_dir {
+        Some(dir) => {
+            // Only plain relative paths stay inside the directory
+            let escapes = Path::new(name).components()
+                .any(|component| !matches!(component, Component::Normal(_) | Component::CurDir));
+            if escapes {
+                return Err("outside the module directory".to_string());
+            }
+            dir.join(name)
+        }
+        None => PathBuf::from(name),
+    };
+    let source = if path.extension().is_some_and(|ext| ext == "qbc") {
+        fs::read(&path).map(ModuleSource::Bytecode)
+    } else {
+        fs::read_to_string(&path).map(ModuleSource::Source)
+    };
+    source.map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Closure behind an exported host function, called with the context and borrowed
+/// arguments; returns an owned value, or the exception marker after throwing
+type HostFn = Box<dyn Fn(*mut JSContext, &[JSValue]) -> JSValue + Send>;
+
+/// Host-side state of a runtime, reachable from native functions through the runtime opaque
+#[derive(Default)]
+struct HostState {
+    // Class of the objects owning `HostFn` closures
+    host_fn_class: libquickjs_ng_sys::JSClassID,
+    fetch: RefCell<FetchState>,
+    timers: RefCell<TimerState>,
+    modules: RefCell<ModuleLoader>,
+    // Set by `JSBridge::interrupt` from any thread, consumed by the interrupt handler
+    interrupt: Arc<AtomicBool>,
+    execution_timeout: Option<Duration>,
+    // Deadline of the outermost entry into the runtime, and how deeply it is entered
+    deadline: Cell<Option<Instant>>,
+    depth: Cell<usize>,
+    completions: Arc<Completions>,
+    // Rejected promises without a handler yet, with the rejection reason
+    rejections: RefCell<Vec<(JSValue, String)>>,
+}
+
+/// Runtime and context, freed once the bridge and every interceptor using them are gone
+struct JsState {
+    rt: *mut JSRuntime,
+    ctx: *mut JSContext,
+    host: Box<HostState>,
+}
+
+// QuickJS is single-threaded; all access is serialized through the bridge's lock
+unsafe impl Send for JsState {}
+
+impl Drop for JsState {
+    fn drop(&mut self) {
+        unsafe {
+            for (_, pending) in self.host.fetch.get_mut().pending.drain() {
+                JS_FreeValue(self.ctx, pending.onload);
+                JS_FreeValue(self.ctx, pending.onerror);
+            }
+            for (_, timer) in self.host.timers.get_mut().timers.drain() {
+                free_timer(self.ctx, timer);
+            }
+            for (promise, _) in self.host.rejections.get_mut().drain(..) {
+                JS_FreeValue(self.ctx, promise);
+            }
+            libquickjs_ng_sys::JS_FreeContext(self.ctx);
+            libquickjs_ng_sys::JS_FreeRuntime(self.rt);
+        }
+    }
+}
+
+/// Host state of the runtime owning `ctx`
+unsafe fn host<'a>(ctx: *mut JSContext) -> &'a HostState {
+    unsafe { &*(libquickjs_ng_sys::JS_GetRuntimeOpaque(libquickjs_ng_sys::JS_GetRuntime(ctx)) as *const HostState) }
+}
+
+/// Structured value exchanged with JS.
+///
+/// Numbers are `f64` as in JS, BigInts arrive as decimal strings, and functions
+/// and symbols as `Undefined`. Objects keep their own enumerable string keys in
+/// property order. Converting to `serde_json::Value` follows `JSON.stringify`:
+/// `Undefined` is dropped from objects and null elsewhere, non-finite numbers are
+/// null and `Bytes` becomes an array of numbers.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum JsValue {
+    #[default]
+    Undefined,
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<JsValue>),
+    Object(Vec<(String, JsValue)>),
+    /// From an ArrayBuffer or Uint8Array; passed to JS as a Uint8Array
+    Bytes(Vec<u8>),
+}
+
+impl From<Value> for JsValue {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Null => JsValue::Null,
+            Value::Bool(value) => JsValue::Bool(value),
+            Value::Number(number) => JsValue::Number(number.as_f64().unwrap_or(f64::NAN)),
+            Value::String(text) => JsValue::String(text),
+            Value::Array(items) => JsValue::Array(items.into_iter().map(JsValue::from).collect()),
+            Value::Object(entries) => {
+                JsValue::Object(entries.into_iter().map(|(key, value)| (key, value.into())).collect())
+            }
+        }
+    }
+}
+
+impl From<JsValue> for Value {
+    fn from(value: JsValue) -> Self {
+        match value {
+            JsValue::Undefined | JsValue::Null => Value::Null,
+            JsValue::Bool(value) => Value::Bool(value),
+            JsValue::Number(number) => {
+                if number.fract() == 0.0 && number.abs() < 9_007_199_254_740_992.0 {
+                    Value::from(number as i64)
+                } else {
+                    serde_json::Number::from_f64(number).map_or(Value::Null, Value::Number)
+                }
+            }
+            JsValue::String(text) => Value::String(text),
+            JsValue::Array(items) => Value::Array(items.into_iter().map(Value::from).collect()),
+            JsValue::Object(entries) => Value::Object(
+                entries.into_iter()
+                    .filter(|(_, value)| *value != JsValue::Undefined)
+                    .map(|(key, value)| (key, value.into()))
+                    .collect(),
+            ),
+            JsValue::Bytes(bytes) => Value::Array(bytes.into_iter().map(Value::from).collect()),
+        }
+    }
+}
+
+/// Resource limits of a JS runtime; `None` keeps the QuickJS default
+#[derive(Clone, Debug, Default)]
+pub struct JsRuntimeConfig {
+    /// Bytes the runtime may allocate; exceeding it throws an out-of-memory error
+    pub memory_limit: Option<usize>,
+    /// Bytes of native stack scripts may use (1 MiB by default)
+    pub max_stack_size: Option<usize>,
+    /// Allocated bytes that trigger a garbage collection
+    pub gc_threshold: Option<usize>,
+    /// Longest a script may run each time the host calls into the runtime, covering
+    /// evaluation, a function call or one event loop turn. Exceeding it throws an
+    /// uncatchable `InternalError: interrupted`.
+    pub execution_timeout: Option<Duration>,
+}
 
 pub struct JSBridge {
-    rt: Arc<Mutex<*mut JSRuntime>>,
-    ctx: Arc<Mutex<*mut JSContext>>,
+    // Shared with HTTP interceptors, which may call into JS from other threads.
+    // Reentrant so a host function called from JS can trigger them on the same thread.
+    state: Arc<ReentrantMutex<JsState>>,
+    // Reachable without the lock, which a runaway script holds
+    interrupt: Arc<AtomicBool>,
+}
+
+/// Lock on the runtime; the outermost one arms the execution deadline
+struct StateGuard<'a>(ReentrantMutexGuard<'a, JsState>);
+
+impl Deref for StateGuard<'_> {
+    type Target = JsState;
+
+    fn deref(&self) -> &JsState {
+        &self.0
+    }
+}
+
+impl Drop for StateGuard<'_> {
+    fn drop(&mut self) {
+        let host = &self.0.host;
+        host.depth.set(host.depth.get() - 1);
+        if host.depth.get() == 0 {
+            host.deadline.set(None);
+        }
+    }
+}
+
+fn lock_state(state: &ReentrantMutex<JsState>) -> StateGuard<'_> {
+    let guard = state.lock();
+    // QuickJS measures stack overflow from the stack it was last entered on
+    unsafe { libquickjs_ng_sys::JS_UpdateStackTop(guard.rt) };
+    let host = &guard.host;
+    if host.depth.get() == 0 {
+        host.deadline.set(host.execution_timeout.map(|timeout| Instant::now() + timeout));
+    }
+    host.depth.set(host.depth.get() + 1);
+    StateGuard(guard)
 }
 
 impl JSBridge {
     pub fn new() -> Self {
+        Self::with_config(JsRuntimeConfig::default())
+    }
+
+    pub fn with_config(config: JsRuntimeConfig) -> Self {
         unsafe {
             let rt = JS_NewRuntime();
+            let mut host = Box::new(HostState {
+                execution_timeout: config.execution_timeout,
+                ..Default::default()
+            });
+            libquickjs_ng_sys::JS_NewClassID(rt, &mut host.host_fn_class);
+            let class = libquickjs_ng_sys::JSClassDef {
+                class_name: c"HostFunction".as_ptr(),
+                finalizer: Some(finalize_host_function),
+                gc_mark: None,
+                call: None,
+                exotic: std::ptr::null_mut(),
+            };
+            libquickjs_ng_sys::JS_NewClass(rt, host.host_fn_class, &class);
             let ctx = JS_NewContext(rt);
+            libquickjs_ng_sys::JS_SetRuntimeOpaque(rt, &*host as *const HostState as *mut libc::c_void);
+            libquickjs_ng_sys::JS_SetHostPromiseRejectionTracker(rt, Some(track_rejection), std::ptr::null_mut());
+            libquickjs_ng_sys::JS_SetModuleLoaderFunc(
+                rt,
+                Some(normalize_module),
+                Some(load_module),
+                std::ptr::null_mut(),
+            );
+            libquickjs_ng_sys::JS_SetInterruptHandler(
+                rt,
+                Some(interrupt_handler),
+                &*host as *const HostState as *mut libc::c_void,
+            );
 
-            JSBridge {
-                rt: Arc::new(Mutex::new(rt)),
-                ctx: Arc::new(Mutex::new(ctx)),
+            let interrupt = host.interrupt.clone();
+            let bridge = JSBridge {
+                state: Arc::new(ReentrantMutex::new(JsState { rt, ctx, host })),
+                interrupt,
+            };
+            // Without the prelude the bridge still runs scripts, just without `fetch`
+            let _ = bridge.init_fetch_api();
+            bridge.init_timer_api();
+
+            // Applied last so the built-in APIs always load
+            if let Some(limit) = config.memory_limit {
+                libquickjs_ng_sys::JS_SetMemoryLimit(rt, limit);
+            }
+            if let Some(size) = config.max_stack_size {
+                libquickjs_ng_sys::JS_SetMaxStackSize(rt, size);
+            }
+            if let Some(threshold) = config.gc_threshold {
+                libquickjs_ng_sys::JS_SetGCThreshold(rt, threshold);
             }
+            bridge
         }
     }
 
+    /// Aborts the running script with an uncatchable `InternalError: interrupted`.
+    /// Callable from any thread; if no script is running, the next one is aborted.
+    pub fn interrupt(&self) {
+        self.interrupt.store(true, Ordering::SeqCst);
+    }
+
+    fn init_fetch_api(&self) -> Result<(), String> {
+        unsafe {
+            let state = lock_state(&self.state);
+            let ctx = state.ctx;
+            let prelude = JS_Eval(
+                ctx,
+                CString::new(FETCH_PRELUDE).unwrap().as_ptr(),
+                FETCH_PRELUDE.len(),
+                c"<fetch>".as_ptr(),
+                libquickjs_ng_sys::JS_EVAL_TYPE_GLOBAL as i32,
+            );
+            if libquickjs_ng_sys::JS_Ext_IsException(prelude) {
+                return Err(take_exception(ctx));
+            }
+
+            let native = libquickjs_ng_sys::JS_NewObject(ctx);
+            define_functions(ctx, native, &[
+                (c"send", fetch_send, 6),
+                (c"encode", fetch_encode, 1),
+                (c"decode", fetch_decode, 1),
+            ]);
+
+            let undefined = libquickjs_ng_sys::JS_Ext_NewSpecialValue(libquickjs_ng_sys::JS_TAG_UNDEFINED, 0);
+            let mut argv = [native];
+            let result = JS_Call(ctx, prelude, undefined, 1, argv.as_mut_ptr());
+            let outcome = if libquickjs_ng_sys::JS_Ext_IsException(result) {
+                Err(take_exception(ctx))
+            } else {
+                Ok(())
+            };
+            JS_FreeValue(ctx, result);
+            JS_FreeValue(ctx, native);
+            JS_FreeValue(ctx, prelude);
+            outcome
+        }
+    }
+
+    /// Registers `setTimeout`, `setInterval`, `clearTimeout`, `clearInterval` and
+    /// `queueMicrotask`. Timers fire from `poll` in deadline order, ties broken by
+    /// scheduling order, with Promise jobs drained after each callback.
+    fn init_timer_api(&self) {
+        unsafe {
+            let state = lock_state(&self.state);
+            let ctx = state.ctx;
+            let global = JS_GetGlobalObject(ctx);
+            define_functions(ctx, global, &[
+                (c"setTimeout", set_timeout, 2),
+                (c"setInterval", set_interval, 2),
+                (c"clearTimeout", clear_timer, 1),
+                (c"clearInterval", clear_timer, 1),
+                (c"queueMicrotask", queue_microtask, 1),
+            ]);
+            JS_FreeValue(ctx, global);
+        }
+    }
+
+    /// Time until the next timer is due (zero if one is overdue), `None` without timers.
+    /// Hosts driving `poll` themselves can sleep this long when nothing else is pending.
+    pub fn next_timer_delay(&self) -> Option<Duration> {
+        let state = lock_state(&self.state);
+        let now = state.host.timers.borrow().clock.now();
+        next_timer_deadline(&state).map(|deadline| deadline.saturating_duration_since(now))
+    }
+
+    /// Uses `client` for `fetch` instead of a default client
+    pub fn set_http_client(&self, client: HttpClient) {
+        let state = lock_state(&self.state);
+        state.host.fetch.borrow_mut().client = Some(client);
+    }
+
+    /// Runs queued Promise jobs until the queue is empty and returns how many ran.
+    /// Stops at the first job that throws; promises rejected without a handler are
+    /// reported as errors once the queue is drained.
+    pub fn run_pending_jobs(&self) -> Result<usize, String> {
+        let state = lock_state(&self.state);
+        unsafe {
+            let ran = run_jobs(&state)?;
+            report_rejections(&state)?;
+            Ok(ran)
+        }
+    }
+
+    /// One turn of the event loop: settles finished `fetch` calls, runs the Promise
+    /// jobs they queue, then fires the timers that are due. Returns the number of
+    /// host operations (fetches and timers) still pending. Timers scheduled while
+    /// polling wait for the next turn. Every due callback runs even if one throws;
+    /// the first error is returned. Promises rejected without a handler, including
+    /// ones left by loading scripts or calling functions, are reported here.
+    pub fn poll(&self) -> Result<usize, String> {
+        let state = lock_state(&self.state);
+        let settled = unsafe { settle_fetches(&state) };
+        let ran = unsafe { run_jobs(&state) };
+        let fired = unsafe { fire_timers(&state) };
+        let rejected = unsafe { report_rejections(&state) };
+        settled.and(ran).and(fired).and(rejected)?;
+        Ok(pending_operations(&state))
+    }
+
+    /// Polls until no host operation is pending, sleeping while there is nothing
+    /// to do. Fails if `timeout` passes first; the bridge is unlocked while waiting.
+    pub fn run_until_idle(&self, timeout: Option<Duration>) -> Result<(), String> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let completions = lock_state(&self.state).host.completions.clone();
+        loop {
+            let pending = self.poll()?;
+            if pending == 0 {
+                return Ok(());
+            }
+            let now = Instant::now();
+            if deadline.is_some_and(|deadline| now >= deadline) {
+                return Err(format!("Timed out with {} pending operations", pending));
+            }
+            let wake = [deadline, next_timer_deadline(&lock_state(&self.state))]
+                .into_iter()
+                .flatten()
+                .min();
+            completions.wait(wake.map(|wake| wake.saturating_duration_since(now)));
+        }
+    }
+
+    /// Installs the global JS functions named `on_request` and `on_response` as an
+    /// interceptor on `client`; either may be `None`. Returns the id for
+    /// `HttpClient::remove_interceptor`. See `JsInterceptor` for the calling convention.
+    /// The interceptor keeps this context alive until it is removed.
+    /// Hooks lock this context on a runtime worker, so scripts running here must not
+    /// make blocking requests through `client` or they deadlock.
+    pub fn add_http_interceptor(
+        &self,
+        client: &HttpClient,
+        on_request: Option<&str>,
+        on_response: Option<&str>,
+    ) -> Result<u64, String> {
+        let name = |name: Option<&str>| name.map(CString::new).transpose().map_err(|e| e.to_string());
+        Ok(client.add_interceptor(Arc::new(JsInterceptor {
+            state: self.state.clone(),
+            on_request: name(on_request)?,
+            on_response: name(on_response)?,
+        })))
+    }
+
+    /// Directory that module imports are resolved against; files outside it can't be imported
+    pub fn set_module_base_dir(&self, dir: impl Into<PathBuf>) {
+        let state = self.state.lock();
+        state.host.modules.borrow_mut().base_dir = Some(dir.into());
+    }
+
+    /// Registers an in-memory module importable as `name`, replacing any previous one
+    pub fn add_module(&self, name: &str, source: ModuleSource) {
+        let state = self.state.lock();
+        state.host.modules.borrow_mut().modules.insert(name.to_string(), source);
+    }
+
+    /// Asks `resolver` for modules that were not registered with `add_module`;
+    /// returning `None` falls back to the module directory
+    pub fn set_module_resolver<F>(&self, resolver: F)
+    where
+        F: Fn(&str) -> Option<ModuleSource> + Send + 'static,
+    {
+        let state = self.state.lock();
+        state.host.modules.borrow_mut().resolver = Some(Arc::new(resolver));
+    }
+
+    /// Imports and evaluates the module `name` through the module loader
+    pub fn load_module(&self, name: &str) -> Result<(), String> {
+        let specifier = serde_json::to_string(name).map_err(|e| e.to_string())?;
+        self.eval(&format!("import {};", specifier), "<load_module>", true)
+    }
+
     pub fn load_script_file(&self, path: &str, is_module: bool) -> Result<(), String> {
         let content = fs::read_to_string(Path::new(path))
             .map_err(|e| format!("Failed to read file: {}", e))?;
-        self.load_script_content(&content, is_module)
+        // Module name relative imports resolve against
+        let filename = {
+            let state = self.state.lock();
+            let modules = state.host.modules.borrow();
+            match modules.base_dir.as_deref().and_then(|dir| Path::new(path).strip_prefix(dir).ok()) {
+                Some(relative) => relative.to_string_lossy().replace('\\', "/"),
+                None => path.strip_prefix("./").unwrap_or(path).to_string(),
+            }
+        };
+        self.eval(&content, &filename, is_module)
     }
 
     pub fn load_script_content(&self, script: &str, is_module: bool) -> Result<(), String> {
+        self.eval(script, "script.js", is_module)
+    }
+
+    fn eval(&self, script: &str, filename: &str, is_module: bool) -> Result<(), String> {
         unsafe {
-            let ctx = self.ctx.lock().unwrap();
+            let state = lock_state(&self.state);
+            let ctx = state.ctx;
             let cscript = CString::new(script).unwrap();
-            let filename = CString::new("script.js").unwrap();
+            let filename = CString::new(filename).map_err(|e| e.to_string())?;
 
             let eval_flags = if is_module {
                 libquickjs_ng_sys::JS_EVAL_TYPE_MODULE as i32
@@ -46,14 +760,19 @@ impl JSBridge {
             };
 
             let val = JS_Eval(
-                *ctx,
+                ctx,
                 cscript.as_ptr(),
                 script.len(),
                 filename.as_ptr(),
                 eval_flags,
             );
 
-            self.eval_and_handle_errors(*ctx, val) // Changed from &mut *ctx to *ctx
+            let outcome = self.eval_and_handle_errors(ctx, libquickjs_ng_sys::JS_DupValue(ctx, val))
+                // Microtask checkpoint, also runs module top-level await up to its first pending promise
+                .and_then(|_| run_jobs(&state).map(|_| ()))
+                .and_then(|_| if is_module { module_error(&state, val) } else { Ok(()) });
+            JS_FreeValue(ctx, val);
+            outcome
         }
     }
 
@@ -65,159 +784,169 @@ impl JSBridge {
 
     pub fn load_bytecode_content(&self, bytecode: &[u8]) -> Result<(), String> {
         unsafe {
-            let ctx = self.ctx.lock().unwrap();
+            let state = lock_state(&self.state);
+            let ctx = state.ctx;
 
             let obj = libquickjs_ng_sys::JS_ReadObject(
-                *ctx,
+                ctx,
                 bytecode.as_ptr(),
                 bytecode.len(),
                 libquickjs_ng_sys::JS_READ_OBJ_BYTECODE as i32,
             );
 
-            if let Err(e) = self.eval_and_handle_errors(*ctx, obj) {
+            if let Err(e) = self.eval_and_handle_errors(ctx, obj) {
                 // Changed here
                 return Err(e.replace("Execution", "Bytecode read"));
             }
 
-            let val = libquickjs_ng_sys::JS_EvalFunction(*ctx, obj);
-            self.eval_and_handle_errors(*ctx, val) // Changed here
-                .map_err(|e| e.replace("Execution", "Bytecode evaluation"))
+            let is_module = libquickjs_ng_sys::JS_Ext_ValueGetTag(obj) == libquickjs_ng_sys::JS_TAG_MODULE;
+            let val = libquickjs_ng_sys::JS_EvalFunction(ctx, obj);
+            let outcome = self.eval_and_handle_errors(ctx, libquickjs_ng_sys::JS_DupValue(ctx, val))
+                .and_then(|_| run_jobs(&state).map(|_| ()))
+                .and_then(|_| if is_module { module_error(&state, val) } else { Ok(()) })
+                .map_err(|e| e.replace("Execution", "Bytecode evaluation"));
+            JS_FreeValue(ctx, val);
+            outcome
         }
     }
 
     pub fn call_function(&self, func_name: &str, arg: &str) -> Result<String, String> {
         unsafe {
-            let ctx = self.ctx.lock().unwrap();
-            let global = JS_GetGlobalObject(*ctx);
+            let state = lock_state(&self.state);
+            let ctx = state.ctx;
+            let global = JS_GetGlobalObject(ctx);
 
             let cname = CString::new(func_name).unwrap();
-            let func_val = JS_GetPropertyStr(*ctx, global, cname.as_ptr());
+            let func_val = JS_GetPropertyStr(ctx, global, cname.as_ptr());
 
-            if JS_HasException(*ctx) {
-                JS_FreeValue(*ctx, global);
+            if JS_HasException(ctx) {
+                JS_FreeValue(ctx, global);
                 return Err(format!("Function {} not found", func_name));
             }
 
             // Create JS string from input arg
             let arg_val =
-                JS_NewStringLen(*ctx, arg.as_ptr() as *const i8, arg.len() as libc::size_t);
+                JS_NewStringLen(ctx, arg.as_ptr() as *const i8, arg.len() as libc::size_t);
 
             let result = JS_Call(
-                *ctx,
+                ctx,
                 func_val,
                 global,
                 1, // Single argument
                 &arg_val as *const JSValue as *mut JSValue,
             );
 
-            JS_FreeValue(*ctx, func_val);
-            JS_FreeValue(*ctx, global);
+            JS_FreeValue(ctx, func_val);
+            JS_FreeValue(ctx, global);
 
-            if JS_HasException(*ctx) {
-                let exception = JS_GetException(*ctx);
+            if JS_HasException(ctx) {
+                let exception = JS_GetException(ctx);
                 let mut len = 0;
-                let ptr = libquickjs_ng_sys::JS_ToCStringLen2(*ctx, &mut len, exception, false);
+                let ptr = libquickjs_ng_sys::JS_ToCStringLen2(ctx, &mut len, exception, false);
                 let err_msg = cstr_to_rust(ptr).unwrap_or("Unknown error").to_string();
 
                 if !ptr.is_null() {
-                    libquickjs_ng_sys::JS_FreeCString(*ctx, ptr);
+                    libquickjs_ng_sys::JS_FreeCString(ctx, ptr);
                 }
-                JS_FreeValue(*ctx, exception);
+                JS_FreeValue(ctx, exception);
                 return Err(format!("Function call error: {}", err_msg));
             }
 
             // Convert result to string
             let mut len = 0;
-            let ptr = libquickjs_ng_sys::JS_ToCStringLen2(*ctx, &mut len, result, false);
+            let ptr = libquickjs_ng_sys::JS_ToCStringLen2(ctx, &mut len, result, false);
             let result_str = cstr_to_rust(ptr).unwrap_or("").to_string();
 
             if !ptr.is_null() {
-                libquickjs_ng_sys::JS_FreeCString(*ctx, ptr);
+                libquickjs_ng_sys::JS_FreeCString(ctx, ptr);
             }
-            JS_FreeValue(*ctx, result);
+            JS_FreeValue(ctx, result);
 
             Ok(result_str)
         }
     }
 
+    /// Calls the global function `func_name` with `args` and returns its result,
+    /// then runs the Promise jobs the call queued. A settled promise result is
+    /// unwrapped; one still pending is an error, as its value is not available yet.
+    pub fn call(&self, func_name: &str, args: &[JsValue]) -> Result<JsValue, String> {
+        let name = CString::new(func_name).map_err(|e| e.to_string())?;
+        let state = lock_state(&self.state);
+        let ctx = state.ctx;
+        unsafe {
+            let mut argv: Vec<JSValue> = args.iter().map(|arg| to_js(ctx, arg)).collect();
+            let result = call_global(ctx, &name, &mut argv);
+            for arg in argv {
+                JS_FreeValue(ctx, arg);
+            }
+            let result = result?;
+            let outcome = run_jobs(&state).and_then(|_| settled_value(ctx, result));
+            JS_FreeValue(ctx, result);
+            outcome
+        }
+    }
+
+    /// `call` with arguments and result as JSON
+    pub fn call_json(&self, func_name: &str, args: &[Value]) -> Result<Value, String> {
+        let args: Vec<JsValue> = args.iter().cloned().map(JsValue::from).collect();
+        self.call(func_name, &args).map(Value::from)
+    }
+
+    /// Exports `func` as the global function `name`, working on raw values: the
+    /// arguments are borrowed for the call and the returned value is handed to JS.
+    /// An `Err` is thrown as a string.
     pub fn export_function<F>(&self, name: &str, func: F) -> Result<(), String>
     where
-        F: Fn(Vec<JSValue>) -> Result<JSValue, String> + 'static,
+        F: Fn(Vec<JSValue>) -> Result<JSValue, String> + Send + 'static,
     {
-        unsafe {
-            let ctx = self.ctx.lock().unwrap();
-            let global = JS_GetGlobalObject(*ctx);
-            let cname = CString::new(name).unwrap();
-
-            // Define a trait object type for our callback
-            type Callback = dyn Fn(Vec<JSValue>) -> Result<JSValue, String> + 'static;
-
-            // Box the closure as a trait object
-            let func_box: Box<Callback> = Box::new(func);
-            let func_ptr = Box::into_raw(func_box);
-
-            // Store the pointer immediately after converting to raw
-            libquickjs_ng_sys::JS_SetContextOpaque(*ctx, func_ptr as *mut libc::c_void);
-
-            // Create a raw callback that uses the trait object
-            extern "C" fn raw_callback(
-                ctx: *mut JSContext,
-                _this_val: JSValue,
-                argc: i32,
-                argv: *mut JSValue,
-            ) -> JSValue {
-                unsafe {
-                    // Get the raw pointer and cast it to the concrete type
-                    let func_ptr = libquickjs_ng_sys::JS_GetContextOpaque(ctx) as *mut ();
-                    let func_ptr =
-                        func_ptr as *mut Box<dyn Fn(Vec<JSValue>) -> Result<JSValue, String>>;
-                    let func = &**func_ptr;
-
-                    let args = if argc > 0 {
-                        std::slice::from_raw_parts(argv, argc as usize).to_vec()
-                    } else {
-                        Vec::new()
-                    };
-
-                    match func(args) {
-                        Ok(result) => result,
-                        Err(err) => {
-                            let err_cstr = rust_to_cstr(err);
-                            let js_str = libquickjs_ng_sys::JS_NewStringLen(
-                                ctx,
-                                err_cstr,
-                                libc::strlen(err_cstr),
-                            );
-                            // Use utility function to free the C string
-                            ngenrs_free_cstr(err_cstr);
-                            libquickjs_ng_sys::JS_Throw(ctx, js_str);
-                            let global = libquickjs_ng_sys::JS_GetGlobalObject(ctx);
-                            let undefined = libquickjs_ng_sys::JS_GetPropertyStr(
-                                ctx,
-                                global,
-                                CString::new("undefined").unwrap().as_ptr(),
-                            );
-                            libquickjs_ng_sys::JS_FreeValue(ctx, global);
-                            undefined
-                        }
-                    }
-                }
+        self.define_host_function(name, Box::new(move |ctx, args| match func(args.to_vec()) {
+            Ok(result) => result,
+            Err(e) => unsafe { libquickjs_ng_sys::JS_Throw(ctx, new_string(ctx, &e)) },
+        }))
+    }
+
+    /// Exports `func` as the global function `name`, with arguments and result
+    /// converted as `JsValue`. An `Err` is thrown as an `Error` with that message.
+    pub fn register_function<F>(&self, name: &str, func: F) -> Result<(), String>
+    where
+        F: Fn(&[JsValue]) -> Result<JsValue, String> + Send + 'static,
+    {
+        self.define_host_function(name, Box::new(move |ctx, args| unsafe {
+            let args = match args.iter().map(|arg| from_js(ctx, *arg, 0)).collect::<Result<Vec<_>, _>>() {
+                Ok(args) => args,
+                Err(e) => return throw_error(ctx, &e),
+            };
+            match func(&args) {
+                Ok(result) => to_js(ctx, &result),
+                Err(e) => throw_error(ctx, &e),
             }
+        }))
+    }
 
-            // Create JS function with our raw callback
-            let js_func = libquickjs_ng_sys::JS_NewCFunction2(
-                *ctx,
-                Some(raw_callback),
-                cname.as_ptr(),
-                0,
-                0,
-                0,
-            );
+    /// Each function carries its closure in a `HostFunction` object bound as its
+    /// function data, so the closure is dropped when the function is collected
+    /// or the context is freed. Exporting a name again replaces the global only.
+    fn define_host_function(&self, name: &str, func: HostFn) -> Result<(), String> {
+        let cname = CString::new(name).map_err(|e| e.to_string())?;
+        let state = lock_state(&self.state);
+        let ctx = state.ctx;
+        unsafe {
+            let mut data = libquickjs_ng_sys::JS_NewObjectClass(ctx, state.host.host_fn_class as c_int);
+            if libquickjs_ng_sys::JS_Ext_IsException(data) {
+                return Err(take_exception(ctx));
+            }
+            libquickjs_ng_sys::JS_SetOpaque(data, Box::into_raw(Box::new(func)) as *mut libc::c_void);
 
-            JS_SetPropertyStr(*ctx, global, cname.as_ptr(), js_func);
-            JS_FreeValue(*ctx, global);
-            Ok(())
+            let js_func = libquickjs_ng_sys::JS_NewCFunctionData(ctx, Some(call_host_function), 0, 0, 1, &mut data);
+            JS_FreeValue(ctx, data);
+            if libquickjs_ng_sys::JS_Ext_IsException(js_func) {
+                return Err(take_exception(ctx));
+            }
+            let global = JS_GetGlobalObject(ctx);
+            JS_SetPropertyStr(ctx, global, cname.as_ptr(), js_func);
+            JS_FreeValue(ctx, global);
         }
+        Ok(())
     }
 
     unsafe fn eval_and_handle_errors(
@@ -249,13 +978,914 @@ impl JSBridge {
     }
 }
 
-impl Drop for JSBridge {
-    fn drop(&mut self) {
+/// HTTP interceptor calling global JS functions by name.
+///
+/// `on_request(req)` gets `{method, url, headers, body}` where `url` includes the
+/// query, `headers` maps name to value and `body` is the encoded body as a string
+/// (null for multipart or binary bodies). Edits to `url`, `headers` and `body` are
+/// sent; returning an object uses it instead, returning `false` rejects the request.
+/// Fields missing from the result are left unchanged.
+/// `on_response(req, rsp)` gets `rsp` as `{status, headers, body}`; returning
+/// `false` fails the request. Values are converted as with `JsValue`.
+struct JsInterceptor {
+    state: Arc<ReentrantMutex<JsState>>,
+    on_request: Option<CString>,
+    on_response: Option<CString>,
+}
+
+impl HttpInterceptor for JsInterceptor {
+    fn on_request(&self, request: &mut HttpRequest) -> Result<(), NGenError> {
+        let Some(name) = &self.on_request else {
+            return Ok(());
+        };
+        let (result, args) = {
+            let state = lock_state(&self.state);
+            unsafe { call_with_values(state.ctx, name, &[request_json(request).into()]) }
+                .map_err(|e| NGenError::new(ErrorCode::Js, e))?
+        };
+        let result = Value::from(result);
+        let edited = match result {
+            Value::Bool(false) => {
+                return Err(NGenError::new(ErrorCode::Cancelled, "Request rejected by JS interceptor"));
+            }
+            Value::Object(_) => result,
+            _ => args.into_iter().next().map(Value::from).unwrap_or_default(),
+        };
+        if !edited.is_object() {
+            return Ok(());
+        }
+
+        let url = edited["url"].as_str().map(str::to_string);
+        let headers = edited["headers"].as_object().map(|headers| {
+            headers.iter()
+                .filter_map(|(key, value)| Some((key.clone(), value.as_str()?.to_string())))
+                .collect()
+        });
+        let body = edited["body"].as_str().map(|body| body.as_bytes().to_vec());
+        request.apply_script_edits(url, headers, body);
+        Ok(())
+    }
+
+    fn on_response(&self, request: &HttpRequest, response: &HttpResponse) -> Result<(), NGenError> {
+        let Some(name) = &self.on_response else {
+            return Ok(());
+        };
+        let headers: serde_json::Map<String, Value> = response.headers.iter()
+            .map(|(key, value)| (key.to_string(), Value::from(String::from_utf8_lossy(value.as_bytes()))))
+            .collect();
+        let rsp = json!({
+            "status": response.status.as_u16(),
+            "headers": headers,
+            "body": response.text(),
+        });
+
+        let (result, _) = {
+            let state = lock_state(&self.state);
+            unsafe { call_with_values(state.ctx, name, &[request_json(request).into(), rsp.into()]) }
+                .map_err(|e| NGenError::new(ErrorCode::Js, e))?
+        };
+        match result {
+            JsValue::Bool(false) => Err(NGenError::new(ErrorCode::Cancelled, "Response rejected by JS interceptor")),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn request_json(request: &HttpRequest) -> Value {
+    let headers: serde_json::Map<String, Value> = request.headers.iter()
+        .map(|(key, value)| (key.clone(), Value::from(value.as_str())))
+        .collect();
+    json!({
+        "method": request.method.as_str(),
+        "url": request.full_url().unwrap_or_else(|| request.url.clone()),
+        "headers": headers,
+        "body": request.body_bytes().and_then(|body| String::from_utf8(body).ok()),
+    })
+}
+
+/// Calls the global function `name`; the caller frees `argv` and the result
+unsafe fn call_global(ctx: *mut JSContext, name: &CStr, argv: &mut [JSValue]) -> Result<JSValue, String> {
+    unsafe {
+        let global = JS_GetGlobalObject(ctx);
+        let func = JS_GetPropertyStr(ctx, global, name.as_ptr());
+        if !libquickjs_ng_sys::JS_IsFunction(ctx, func) {
+            JS_FreeValue(ctx, func);
+            JS_FreeValue(ctx, global);
+            return Err(format!("Function {} not found", name.to_string_lossy()));
+        }
+
+        let result = JS_Call(ctx, func, global, argv.len() as c_int, argv.as_mut_ptr());
+        JS_FreeValue(ctx, func);
+        JS_FreeValue(ctx, global);
+        if libquickjs_ng_sys::JS_Ext_IsException(result) {
+            return Err(format!("Function call error: {}", take_exception(ctx)));
+        }
+        Ok(result)
+    }
+}
+
+/// Calls the global function `name` and returns the converted result together
+/// with the arguments as they are after the call
+unsafe fn call_with_values(ctx: *mut JSContext, name: &CStr, args: &[JsValue]) -> Result<(JsValue, Vec<JsValue>), String> {
+    unsafe {
+        let mut argv: Vec<JSValue> = args.iter().map(|arg| to_js(ctx, arg)).collect();
+        let outcome = call_global(ctx, name, &mut argv).and_then(|result| {
+            let converted = from_js(ctx, result, 0);
+            JS_FreeValue(ctx, result);
+            let args = argv.iter().map(|arg| from_js(ctx, *arg, 0)).collect::<Result<Vec<_>, _>>()?;
+            Ok((converted?, args))
+        });
+        for arg in argv {
+            JS_FreeValue(ctx, arg);
+        }
+        outcome
+    }
+}
+
+// Guards against cyclic objects, which JS allows but `JsValue` cannot represent
+const MAX_VALUE_DEPTH: usize = 256;
+
+unsafe fn from_js(ctx: *mut JSContext, value: JSValue, depth: usize) -> Result<JsValue, String> {
+    use libquickjs_ng_sys as q;
+    unsafe {
+        if depth > MAX_VALUE_DEPTH {
+            return Err("Value is nested too deeply or cyclic".to_string());
+        }
+        if q::JS_Ext_IsUndefined(value) {
+            return Ok(JsValue::Undefined);
+        }
+        if q::JS_Ext_IsNull(value) {
+            return Ok(JsValue::Null);
+        }
+        if q::JS_Ext_IsBool(value) {
+            return Ok(JsValue::Bool(q::JS_Ext_GetBool(value) != 0));
+        }
+        if q::JS_Ext_IsNumber(value) {
+            let mut number = 0.0;
+            q::JS_ToFloat64(ctx, &mut number, value);
+            return Ok(JsValue::Number(number));
+        }
+        if q::JS_Ext_IsString(value) || q::JS_Ext_IsBigInt(ctx, value) {
+            return to_string(ctx, value).map(JsValue::String).ok_or_else(|| "Invalid string".to_string());
+        }
+        if !q::JS_Ext_IsObject(value) || q::JS_IsFunction(ctx, value) {
+            return Ok(JsValue::Undefined);
+        }
+
+        if q::JS_IsArrayBuffer(value) {
+            let mut len = 0;
+            let data = q::JS_GetArrayBuffer(ctx, &mut len, value);
+            return Ok(JsValue::Bytes(if data.is_null() { Vec::new() } else { std::slice::from_raw_parts(data, len).to_vec() }));
+        }
+        let typed = q::JS_GetTypedArrayType(value);
+        if typed == q::JSTypedArrayEnum_JS_TYPED_ARRAY_UINT8 as c_int || typed == q::JSTypedArrayEnum_JS_TYPED_ARRAY_UINT8C as c_int {
+            let mut len = 0;
+            let data = q::JS_GetUint8Array(ctx, &mut len, value);
+            if data.is_null() {
+                return Err(take_exception(ctx));
+            }
+            return Ok(JsValue::Bytes(std::slice::from_raw_parts(data, len).to_vec()));
+        }
+
+        if q::JS_IsArray(value) {
+            let mut len = 0;
+            if q::JS_GetLength(ctx, value, &mut len) < 0 {
+                return Err(take_exception(ctx));
+            }
+            let mut items = Vec::with_capacity(len as usize);
+            for index in 0..len as u32 {
+                let item = q::JS_GetPropertyUint32(ctx, value, index);
+                if q::JS_Ext_IsException(item) {
+                    return Err(take_exception(ctx));
+                }
+                let converted = from_js(ctx, item, depth + 1);
+                JS_FreeValue(ctx, item);
+                items.push(converted?);
+            }
+            return Ok(JsValue::Array(items));
+        }
+
+        let mut props = std::ptr::null_mut();
+        let mut len = 0;
+        let flags = (q::JS_GPN_STRING_MASK | q::JS_GPN_ENUM_ONLY) as c_int;
+        if q::JS_GetOwnPropertyNames(ctx, &mut props, &mut len, value, flags) < 0 {
+            return Err(take_exception(ctx));
+        }
+        let mut entries = Vec::with_capacity(len as usize);
+        let mut outcome = Ok(());
+        for prop in std::slice::from_raw_parts(props, len as usize) {
+            let key = q::JS_AtomToString(ctx, prop.atom);
+            let name = to_string(ctx, key).unwrap_or_default();
+            JS_FreeValue(ctx, key);
+            let item = q::JS_GetProperty(ctx, value, prop.atom);
+            if q::JS_Ext_IsException(item) {
+                outcome = Err(take_exception(ctx));
+                break;
+            }
+            let converted = from_js(ctx, item, depth + 1);
+            JS_FreeValue(ctx, item);
+            match converted {
+                Ok(converted) => entries.push((name, converted)),
+                Err(e) => {
+                    outcome = Err(e);
+                    break;
+                }
+            }
+        }
+        q::JS_FreePropertyEnum(ctx, props, len);
+        outcome.map(|_| JsValue::Object(entries))
+    }
+}
+
+unsafe fn to_js(ctx: *mut JSContext, value: &JsValue) -> JSValue {
+    use libquickjs_ng_sys as q;
+    unsafe {
+        match value {
+            JsValue::Undefined => q::JS_Ext_NewSpecialValue(q::JS_TAG_UNDEFINED, 0),
+            JsValue::Null => q::JS_Ext_NewSpecialValue(q::JS_TAG_NULL, 0),
+            JsValue::Bool(value) => q::JS_Ext_NewBool(ctx, *value as u8),
+            JsValue::Number(number) => {
+                let int = *number as i32;
+                if int as f64 == *number && (int != 0 || number.is_sign_positive()) {
+                    q::JS_Ext_NewInt32(ctx, int)
+                } else {
+                    q::JS_Ext_NewFloat64(ctx, *number)
+                }
+            }
+            JsValue::String(text) => new_string(ctx, text),
+            JsValue::Bytes(bytes) => q::JS_NewUint8ArrayCopy(ctx, bytes.as_ptr(), bytes.len()),
+            JsValue::Array(items) => {
+                let array = q::JS_NewArray(ctx);
+                for (index, item) in items.iter().enumerate() {
+                    q::JS_SetPropertyUint32(ctx, array, index as u32, to_js(ctx, item));
+                }
+                array
+            }
+            JsValue::Object(entries) => {
+                let object = q::JS_NewObject(ctx);
+                for (name, item) in entries {
+                    let atom = q::JS_NewAtomLen(ctx, name.as_ptr() as *const c_char, name.len());
+                    // Defined rather than set, so keys such as "__proto__" stay plain data
+                    q::JS_DefinePropertyValue(ctx, object, atom, to_js(ctx, item), q::JS_PROP_C_W_E as c_int);
+                    q::JS_FreeAtom(ctx, atom);
+                }
+                object
+            }
+        }
+    }
+}
+
+/// `value` converted, or the outcome of a settled promise
+unsafe fn settled_value(ctx: *mut JSContext, value: JSValue) -> Result<JsValue, String> {
+    use libquickjs_ng_sys as q;
+    unsafe {
+        if !q::JS_Ext_IsPromise(ctx, value) {
+            return from_js(ctx, value, 0);
+        }
+        let state = q::JS_PromiseState(ctx, value);
+        if state == q::JSPromiseStateEnum_JS_PROMISE_PENDING {
+            return Err("Function returned a pending promise".to_string());
+        }
+        let settled = q::JS_PromiseResult(ctx, value);
+        let outcome = if state == q::JSPromiseStateEnum_JS_PROMISE_FULFILLED {
+            from_js(ctx, settled, 0)
+        } else {
+            Err(format!("Function call error: {}", to_string(ctx, settled).unwrap_or_default()))
+        };
+        JS_FreeValue(ctx, settled);
+        outcome
+    }
+}
+
+/// Clears the pending exception and returns its string form
+unsafe fn take_exception(ctx: *mut JSContext) -> String {
+    unsafe {
+        let exception = JS_GetException(ctx);
+        let mut len = 0;
+        let ptr = libquickjs_ng_sys::JS_ToCStringLen2(ctx, &mut len, exception, false);
+        let message = cstr_to_rust(ptr).unwrap_or("Unknown error").to_string();
+        if !ptr.is_null() {
+            libquickjs_ng_sys::JS_FreeCString(ctx, ptr);
+        }
+        JS_FreeValue(ctx, exception);
+        message
+    }
+}
+
+/// Calls the `onload`/`onerror` callbacks of fetches finished since the last call
+unsafe fn settle_fetches(state: &JsState) -> Result<(), String> {
+    let ctx = state.ctx;
+    let completed = std::mem::take(&mut *state.host.completions.fetches.lock().unwrap());
+    let mut first_error = None;
+
+    for completion in completed {
+        let pending = state.host.fetch.borrow_mut().pending.remove(&completion.id);
+        let Some(pending) = pending else {
+            continue;
+        };
+        unsafe {
+            let mut argv = match &completion.result {
+                Ok(response) => response_args(ctx, response),
+                Err(message) => vec![new_string(ctx, message)],
+            };
+            let callback = if completion.result.is_ok() { pending.onload } else { pending.onerror };
+            let undefined = libquickjs_ng_sys::JS_Ext_NewSpecialValue(libquickjs_ng_sys::JS_TAG_UNDEFINED, 0);
+            let result = JS_Call(ctx, callback, undefined, argv.len() as c_int, argv.as_mut_ptr());
+            if libquickjs_ng_sys::JS_Ext_IsException(result) {
+                first_error.get_or_insert_with(|| take_exception(ctx));
+            }
+            JS_FreeValue(ctx, result);
+            for arg in argv {
+                JS_FreeValue(ctx, arg);
+            }
+            JS_FreeValue(ctx, pending.onload);
+            JS_FreeValue(ctx, pending.onerror);
+        }
+    }
+    first_error.map_or(Ok(()), Err)
+}
+
+unsafe fn run_jobs(state: &JsState) -> Result<usize, String> {
+    let mut ran = 0;
+    loop {
+        let mut job_ctx = std::ptr::null_mut();
+        match unsafe { libquickjs_ng_sys::JS_ExecutePendingJob(state.rt, &mut job_ctx) } {
+            0 => break,
+            n if n < 0 => return Err(format!("Job error: {}", unsafe { take_exception(job_ctx) })),
+            _ => ran += 1,
+        }
+    }
+
+    Ok(ran)
+}
+
+/// Fails if the evaluation promise of a module has already rejected, taking it
+/// out of the unhandled rejections as the load reports it
+unsafe fn module_error(state: &JsState, promise: JSValue) -> Result<(), String> {
+    use libquickjs_ng_sys as q;
+    unsafe {
+        let ctx = state.ctx;
+        if !q::JS_Ext_IsPromise(ctx, promise)
+            || q::JS_PromiseState(ctx, promise) != q::JSPromiseStateEnum_JS_PROMISE_REJECTED
+        {
+            return Ok(());
+        }
+        let target = q::JS_Ext_GetPtr(promise);
+        let tracked = {
+            let mut rejections = state.host.rejections.borrow_mut();
+            let index = rejections.iter().position(|(p, _)| q::JS_Ext_GetPtr(*p) == target);
+            index.map(|index| rejections.remove(index).0)
+        };
+        if let Some(tracked) = tracked {
+            JS_FreeValue(ctx, tracked);
+        }
+        let reason = q::JS_PromiseResult(ctx, promise);
+        let message = to_string(ctx, reason).unwrap_or_else(|| "Unknown error".to_string());
+        JS_FreeValue(ctx, reason);
+        Err(format!("Execution error: {}", message))
+    }
+}
+
+/// Fails with the first promise rejected without a handler since the last report
+unsafe fn report_rejections(state: &JsState) -> Result<(), String> {
+    let rejections = std::mem::take(&mut *state.host.rejections.borrow_mut());
+    let first = rejections.first().map(|(_, reason)| reason.clone());
+    for (promise, _) in rejections {
+        unsafe { JS_FreeValue(state.ctx, promise) };
+    }
+    match first {
+        Some(reason) => Err(format!("Unhandled promise rejection: {}", reason)),
+        None => Ok(()),
+    }
+}
+
+fn pending_operations(state: &JsState) -> usize {
+    state.host.fetch.borrow().pending.len() + state.host.timers.borrow().timers.len()
+}
+
+fn next_timer_deadline(state: &JsState) -> Option<Instant> {
+    state.host.timers.borrow().timers.values().map(|timer| timer.deadline).min()
+}
+
+/// Runs the callbacks of timers due now, each followed by a Promise job checkpoint
+unsafe fn fire_timers(state: &JsState) -> Result<(), String> {
+    let ctx = state.ctx;
+    let now = state.host.timers.borrow().clock.now();
+    let mut due: Vec<(Instant, u64, i32)> = state.host.timers.borrow().timers.iter()
+        .filter(|(_, timer)| timer.deadline <= now)
+        .map(|(id, timer)| (timer.deadline, timer.seq, *id))
+        .collect();
+    due.sort_unstable();
+
+    let mut first_error = None;
+    for (_, seq, id) in due {
+        // A callback earlier in this turn may have cleared or rescheduled it
+        let (callback, mut args) = {
+            let mut timers = state.host.timers.borrow_mut();
+            let next_seq = timers.next_seq;
+            let Some(timer) = timers.timers.get_mut(&id).filter(|timer| timer.seq == seq) else {
+                continue;
+            };
+            match timer.interval {
+                Some(interval) => unsafe {
+                    timer.deadline = now + interval;
+                    timer.seq = next_seq;
+                    let values = (
+                        libquickjs_ng_sys::JS_DupValue(ctx, timer.callback),
+                        timer.args.iter().map(|arg| libquickjs_ng_sys::JS_DupValue(ctx, *arg)).collect(),
+                    );
+                    timers.next_seq += 1;
+                    values
+                },
+                None => {
+                    let timer = timers.timers.remove(&id).unwrap();
+                    (timer.callback, timer.args)
+                }
+            }
+        };
+
+        unsafe {
+            let undefined = libquickjs_ng_sys::JS_Ext_NewSpecialValue(libquickjs_ng_sys::JS_TAG_UNDEFINED, 0);
+            let result = JS_Call(ctx, callback, undefined, args.len() as c_int, args.as_mut_ptr());
+            if libquickjs_ng_sys::JS_Ext_IsException(result) {
+                first_error.get_or_insert_with(|| format!("Timer error: {}", take_exception(ctx)));
+            }
+            JS_FreeValue(ctx, result);
+            JS_FreeValue(ctx, callback);
+            for arg in args {
+                JS_FreeValue(ctx, arg);
+            }
+            if let Err(e) = run_jobs(state) {
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    first_error.map_or(Ok(()), Err)
+}
+
+unsafe fn free_timer(ctx: *mut JSContext, timer: Timer) {
+    unsafe {
+        JS_FreeValue(ctx, timer.callback);
+        for arg in timer.args {
+            JS_FreeValue(ctx, arg);
+        }
+    }
+}
+
+/// Interrupts the running script once `JSBridge::interrupt` was called or the
+/// execution deadline has passed
+unsafe extern "C" fn interrupt_handler(_rt: *mut JSRuntime, opaque: *mut libc::c_void) -> c_int {
+    let host = unsafe { &*(opaque as *const HostState) };
+    let expired = host.deadline.get().is_some_and(|deadline| Instant::now() >= deadline);
+    (host.interrupt.swap(false, Ordering::SeqCst) || expired) as c_int
+}
+
+/// Records promises rejected without a handler, forgetting them once one is attached
+unsafe extern "C" fn track_rejection(
+    ctx: *mut JSContext,
+    promise: JSValue,
+    reason: JSValue,
+    is_handled: bool,
+    _opaque: *mut libc::c_void,
+) {
+    unsafe {
+        let host = host(ctx);
+        if is_handled {
+            let target = libquickjs_ng_sys::JS_Ext_GetPtr(promise);
+            let mut rejections = host.rejections.borrow_mut();
+            if let Some(index) = rejections.iter().position(|(p, _)| libquickjs_ng_sys::JS_Ext_GetPtr(*p) == target) {
+                let (promise, _) = rejections.remove(index);
+                drop(rejections);
+                JS_FreeValue(ctx, promise);
+            }
+        } else {
+            let reason = to_string(ctx, reason).unwrap_or_else(|| "Unknown error".to_string());
+            host.rejections.borrow_mut().push((libquickjs_ng_sys::JS_DupValue(ctx, promise), reason));
+        }
+    }
+}
+
+/// Resolves `name` imported from the module `base`: "./" and "../" specifiers are
+/// joined to the directory of `base`, anything else is kept as is
+fn resolve_module_name(base: &str, name: &str) -> String {
+    if !name.starts_with("./") && !name.starts_with("../") {
+        return name.to_string();
+    }
+    let mut parts: Vec<&str> = base.split('/').collect();
+    parts.pop();
+    for segment in name.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => match parts.last() {
+                None | Some(&"..") => parts.push(".."),
+                // Already at the filesystem root
+                Some(&"") if parts.len() == 1 => {}
+                Some(_) => {
+                    parts.pop();
+                }
+            },
+            _ => parts.push(segment),
+        }
+    }
+    parts.join("/")
+}
+
+unsafe extern "C" fn normalize_module(
+    ctx: *mut JSContext,
+    base: *const c_char,
+    name: *const c_char,
+    _opaque: *mut libc::c_void,
+) -> *mut c_char {
+    unsafe {
+        let base = CStr::from_ptr(base).to_string_lossy();
+        let name = CStr::from_ptr(name).to_string_lossy();
+        let resolved = resolve_module_name(&base, &name);
+        // Freed by QuickJS with js_free
+        libquickjs_ng_sys::js_strndup(ctx, resolved.as_ptr() as *const c_char, resolved.len())
+    }
+}
+
+unsafe extern "C" fn load_module(
+    ctx: *mut JSContext,
+    name: *const c_char,
+    _opaque: *mut libc::c_void,
+) -> *mut libquickjs_ng_sys::JSModuleDef {
+    unsafe {
+        let cname = CStr::from_ptr(name);
+        let name = cname.to_string_lossy();
+        let value = match find_module(&host(ctx).modules, &name) {
+            Ok(ModuleSource::Source(code)) => match CString::new(code) {
+                Ok(code) => JS_Eval(
+                    ctx,
+                    code.as_ptr(),
+                    code.as_bytes().len(),
+                    cname.as_ptr(),
+                    (libquickjs_ng_sys::JS_EVAL_TYPE_MODULE | libquickjs_ng_sys::JS_EVAL_FLAG_COMPILE_ONLY) as c_int,
+                ),
+                Err(_) => throw_module_error(ctx, &name, "source contains a NUL byte"),
+            },
+            Ok(ModuleSource::Bytecode(bytes)) => libquickjs_ng_sys::JS_ReadObject(
+                ctx,
+                bytes.as_ptr(),
+                bytes.len(),
+                libquickjs_ng_sys::JS_READ_OBJ_BYTECODE as c_int,
+            ),
+            Err(e) => throw_module_error(ctx, &name, &e),
+        };
+        if libquickjs_ng_sys::JS_Ext_IsException(value) {
+            return std::ptr::null_mut();
+        }
+        if libquickjs_ng_sys::JS_Ext_ValueGetTag(value) != libquickjs_ng_sys::JS_TAG_MODULE {
+            JS_FreeValue(ctx, value);
+            throw_module_error(ctx, &name, "bytecode is not a module");
+            return std::ptr::null_mut();
+        }
+        // The module is owned by the context; the compiled value only references it
+        let module = libquickjs_ng_sys::JS_Ext_GetPtr(value) as *mut libquickjs_ng_sys::JSModuleDef;
+        JS_FreeValue(ctx, value);
+        let meta = libquickjs_ng_sys::JS_GetImportMeta(ctx, module);
+        if !libquickjs_ng_sys::JS_Ext_IsException(meta) {
+            JS_SetPropertyStr(ctx, meta, c"url".as_ptr(), new_string(ctx, &name));
+        }
+        JS_FreeValue(ctx, meta);
+        module
+    }
+}
+
+unsafe fn throw_module_error(ctx: *mut JSContext, name: &str, reason: &str) -> JSValue {
+    unsafe {
+        let message = CString::new(format!("could not load module '{}': {}", name, reason)).unwrap_or_default();
+        libquickjs_ng_sys::JS_ThrowReferenceError(ctx, c"%s".as_ptr(), message.as_ptr())
+    }
+}
+
+type NativeFn = unsafe extern "C" fn(*mut JSContext, JSValue, c_int, *mut JSValue) -> JSValue;
+
+unsafe extern "C" fn call_host_function(
+    ctx: *mut JSContext,
+    _this: JSValue,
+    argc: c_int,
+    argv: *mut JSValue,
+    _magic: c_int,
+    data: *mut JSValue,
+) -> JSValue {
+    unsafe {
+        let func = libquickjs_ng_sys::JS_GetOpaque(*data, host(ctx).host_fn_class) as *const HostFn;
+        if func.is_null() {
+            return throw_error(ctx, "Host function is no longer available");
+        }
+        let args = if argc > 0 { std::slice::from_raw_parts(argv, argc as usize) } else { &[] };
+        (*func)(ctx, args)
+    }
+}
+
+unsafe extern "C" fn finalize_host_function(rt: *mut JSRuntime, value: JSValue) {
+    unsafe {
+        let host = &*(libquickjs_ng_sys::JS_GetRuntimeOpaque(rt) as *const HostState);
+        let func = libquickjs_ng_sys::JS_GetOpaque(value, host.host_fn_class) as *mut HostFn;
+        if !func.is_null() {
+            drop(Box::from_raw(func));
+        }
+    }
+}
+
+unsafe fn define_functions(ctx: *mut JSContext, target: JSValue, functions: &[(&CStr, NativeFn, c_int)]) {
+    for (name, func, length) in functions {
         unsafe {
-            let ctx = self.ctx.lock().unwrap();
-            let rt = self.rt.lock().unwrap();
-            libquickjs_ng_sys::JS_FreeContext(*ctx);
-            libquickjs_ng_sys::JS_FreeRuntime(*rt);
+            let value = libquickjs_ng_sys::JS_NewCFunction2(ctx, Some(*func), name.as_ptr(), *length, 0, 0);
+            JS_SetPropertyStr(ctx, target, name.as_ptr(), value);
+        }
+    }
+}
+
+unsafe extern "C" fn set_timeout(ctx: *mut JSContext, _this: JSValue, argc: c_int, argv: *mut JSValue) -> JSValue {
+    unsafe { schedule_timer(ctx, argc, argv, false) }
+}
+
+unsafe extern "C" fn set_interval(ctx: *mut JSContext, _this: JSValue, argc: c_int, argv: *mut JSValue) -> JSValue {
+    unsafe { schedule_timer(ctx, argc, argv, true) }
+}
+
+/// `(callback, delay, ...args)`; a missing, negative or NaN delay means zero.
+/// Intervals repeat no faster than `MIN_INTERVAL`.
+unsafe fn schedule_timer(ctx: *mut JSContext, argc: c_int, argv: *mut JSValue, repeat: bool) -> JSValue {
+    unsafe {
+        let args = if argc > 0 { std::slice::from_raw_parts(argv, argc as usize) } else { &[] };
+        if args.is_empty() || !libquickjs_ng_sys::JS_IsFunction(ctx, args[0]) {
+            return throw_type_error(ctx, "callback must be a function");
+        }
+        let mut delay = 0.0;
+        if args.len() > 1 && libquickjs_ng_sys::JS_ToFloat64(ctx, &mut delay, args[1]) < 0 {
+            return libquickjs_ng_sys::JS_Ext_NewSpecialValue(libquickjs_ng_sys::JS_TAG_EXCEPTION, 0);
+        }
+        let delay = if delay.is_finite() && delay > 0.0 {
+            Duration::from_secs_f64(delay.min(i32::MAX as f64) / 1000.0)
+        } else {
+            Duration::ZERO
+        };
+
+        let mut timers = host(ctx).timers.borrow_mut();
+        timers.next_id = timers.next_id.checked_add(1).unwrap_or(1);
+        let id = timers.next_id;
+        let seq = timers.next_seq;
+        timers.next_seq += 1;
+        let deadline = timers.clock.now() + delay;
+        timers.timers.insert(id, Timer {
+            deadline,
+            seq,
+            callback: libquickjs_ng_sys::JS_DupValue(ctx, args[0]),
+            args: args[2.min(args.len())..].iter().map(|arg| libquickjs_ng_sys::JS_DupValue(ctx, *arg)).collect(),
+            interval: repeat.then_some(delay.max(MIN_INTERVAL)),
+        });
+        libquickjs_ng_sys::JS_Ext_NewInt32(ctx, id)
+    }
+}
+
+/// `clearTimeout(id)` and `clearInterval(id)`, which share one id space
+unsafe extern "C" fn clear_timer(ctx: *mut JSContext, _this: JSValue, argc: c_int, argv: *mut JSValue) -> JSValue {
+    unsafe {
+        let mut id = 0;
+        if argc > 0 && libquickjs_ng_sys::JS_Ext_IsNumber(*argv) && libquickjs_ng_sys::JS_ToInt32(ctx, &mut id, *argv) == 0 {
+            let timer = host(ctx).timers.borrow_mut().timers.remove(&id);
+            if let Some(timer) = timer {
+                free_timer(ctx, timer);
+            }
+        }
+        libquickjs_ng_sys::JS_Ext_NewSpecialValue(libquickjs_ng_sys::JS_TAG_UNDEFINED, 0)
+    }
+}
+
+unsafe extern "C" fn queue_microtask(ctx: *mut JSContext, _this: JSValue, argc: c_int, argv: *mut JSValue) -> JSValue {
+    unsafe {
+        if argc < 1 || !libquickjs_ng_sys::JS_IsFunction(ctx, *argv) {
+            return throw_type_error(ctx, "callback must be a function");
+        }
+        if libquickjs_ng_sys::JS_EnqueueJob(ctx, Some(microtask_job), 1, argv) < 0 {
+            return libquickjs_ng_sys::JS_Ext_NewSpecialValue(libquickjs_ng_sys::JS_TAG_EXCEPTION, 0);
         }
+        libquickjs_ng_sys::JS_Ext_NewSpecialValue(libquickjs_ng_sys::JS_TAG_UNDEFINED, 0)
+    }
+}
+
+unsafe extern "C" fn microtask_job(ctx: *mut JSContext, _argc: c_int, argv: *mut JSValue) -> JSValue {
+    unsafe {
+        let undefined = libquickjs_ng_sys::JS_Ext_NewSpecialValue(libquickjs_ng_sys::JS_TAG_UNDEFINED, 0);
+        JS_Call(ctx, *argv, undefined, 0, std::ptr::null_mut())
+    }
+}
+
+/// `send(method, url, headers, body, onload, onerror)`: starts the request on the
+/// Tokio runtime; `onload(status, statusText, headers, body, url, redirected)` or
+/// `onerror(message)` is called from `JSBridge::poll`.
+unsafe extern "C" fn fetch_send(ctx: *mut JSContext, _this: JSValue, argc: c_int, argv: *mut JSValue) -> JSValue {
+    unsafe {
+        if argc < 6 {
+            return throw_type_error(ctx, "send expects 6 arguments");
+        }
+        let args = std::slice::from_raw_parts(argv, argc as usize);
+        let (Some(method), Some(url)) = (to_string(ctx, args[0]), to_string(ctx, args[1])) else {
+            return throw_type_error(ctx, "method and url must be strings");
+        };
+        let Some(method) = HttpMethod::from_name(&method) else {
+            return throw_type_error(ctx, &format!("Unsupported method {}", method));
+        };
+
+        let mut request = HttpRequest::new(method, &url);
+        let mut count = 0;
+        libquickjs_ng_sys::JS_GetLength(ctx, args[2], &mut count);
+        for index in 0..count as u32 {
+            let pair = libquickjs_ng_sys::JS_GetPropertyUint32(ctx, args[2], index);
+            let name = libquickjs_ng_sys::JS_GetPropertyUint32(ctx, pair, 0);
+            let value = libquickjs_ng_sys::JS_GetPropertyUint32(ctx, pair, 1);
+            if let (Some(name), Some(value)) = (to_string(ctx, name), to_string(ctx, value)) {
+                request = request.header(&name, &value);
+            }
+            JS_FreeValue(ctx, value);
+            JS_FreeValue(ctx, name);
+            JS_FreeValue(ctx, pair);
+        }
+        if libquickjs_ng_sys::JS_IsArrayBuffer(args[3]) {
+            let mut len = 0;
+            let data = libquickjs_ng_sys::JS_GetArrayBuffer(ctx, &mut len, args[3]);
+            if !data.is_null() {
+                request = request.body(std::slice::from_raw_parts(data, len));
+            }
+        }
+
+        let host = host(ctx);
+        let client = {
+            let mut fetch = host.fetch.borrow_mut();
+            match &fetch.client {
+                Some(client) => client.clone(),
+                None => match HttpClient::new(None) {
+                    Ok(client) => {
+                        fetch.client = Some(client.clone());
+                        client
+                    }
+                    Err(e) => return throw_type_error(ctx, &e.to_string()),
+                },
+            }
+        };
+        let id = {
+            let mut fetch = host.fetch.borrow_mut();
+            fetch.next_id += 1;
+            let id = fetch.next_id;
+            fetch.pending.insert(id, PendingFetch {
+                onload: libquickjs_ng_sys::JS_DupValue(ctx, args[4]),
+                onerror: libquickjs_ng_sys::JS_DupValue(ctx, args[5]),
+            });
+            id
+        };
+
+        let completions = host.completions.clone();
+        RUNTIME.spawn(async move {
+            let result = client.send(request).await.map_err(|e| e.to_string());
+            completions.push_fetch(FetchCompletion { id, result });
+        });
+        libquickjs_ng_sys::JS_Ext_NewSpecialValue(libquickjs_ng_sys::JS_TAG_UNDEFINED, 0)
+    }
+}
+
+/// `encode(string)`: UTF-8 bytes of `string` as an ArrayBuffer
+unsafe extern "C" fn fetch_encode(ctx: *mut JSContext, _this: JSValue, argc: c_int, argv: *mut JSValue) -> JSValue {
+    unsafe {
+        let text = if argc > 0 { to_string(ctx, *argv) } else { None };
+        let text = text.unwrap_or_default();
+        libquickjs_ng_sys::JS_NewArrayBufferCopy(ctx, text.as_ptr(), text.len())
+    }
+}
+
+/// `decode(buffer)`: an ArrayBuffer decoded as UTF-8, with invalid sequences replaced
+unsafe extern "C" fn fetch_decode(ctx: *mut JSContext, _this: JSValue, argc: c_int, argv: *mut JSValue) -> JSValue {
+    unsafe {
+        if argc < 1 || !libquickjs_ng_sys::JS_IsArrayBuffer(*argv) {
+            return throw_type_error(ctx, "decode expects an ArrayBuffer");
+        }
+        let mut len = 0;
+        let data = libquickjs_ng_sys::JS_GetArrayBuffer(ctx, &mut len, *argv);
+        let bytes = if data.is_null() { &[][..] } else { std::slice::from_raw_parts(data, len) };
+        new_string(ctx, &String::from_utf8_lossy(bytes))
+    }
+}
+
+/// `onload` arguments for `response`: status, status text, `[name, value]` pairs,
+/// body, final URL and whether it was redirected
+unsafe fn response_args(ctx: *mut JSContext, response: &HttpResponse) -> Vec<JSValue> {
+    unsafe {
+        let headers = libquickjs_ng_sys::JS_NewArray(ctx);
+        for (index, (name, value)) in response.headers.iter().enumerate() {
+            let pair = libquickjs_ng_sys::JS_NewArray(ctx);
+            libquickjs_ng_sys::JS_SetPropertyUint32(ctx, pair, 0, new_string(ctx, name.as_str()));
+            libquickjs_ng_sys::JS_SetPropertyUint32(ctx, pair, 1, new_string(ctx, &String::from_utf8_lossy(value.as_bytes())));
+            libquickjs_ng_sys::JS_SetPropertyUint32(ctx, headers, index as u32, pair);
+        }
+        let body = response.body.as_deref().unwrap_or_default();
+        vec![
+            libquickjs_ng_sys::JS_Ext_NewInt32(ctx, response.status.as_u16() as i32),
+            new_string(ctx, response.status.canonical_reason().unwrap_or_default()),
+            headers,
+            libquickjs_ng_sys::JS_NewArrayBufferCopy(ctx, body.as_ptr(), body.len()),
+            new_string(ctx, &response.url),
+            libquickjs_ng_sys::JS_Ext_NewBool(ctx, response.redirected as u8),
+        ]
+    }
+}
+
+unsafe fn new_string(ctx: *mut JSContext, text: &str) -> JSValue {
+    unsafe { JS_NewStringLen(ctx, text.as_ptr() as *const c_char, text.len()) }
+}
+
+/// String conversion of `value`, `None` if it throws
+unsafe fn to_string(ctx: *mut JSContext, value: JSValue) -> Option<String> {
+    unsafe {
+        let mut len = 0;
+        let ptr = libquickjs_ng_sys::JS_ToCStringLen2(ctx, &mut len, value, false);
+        if ptr.is_null() {
+            take_exception(ctx);
+            return None;
+        }
+        let text = String::from_utf8_lossy(std::slice::from_raw_parts(ptr as *const u8, len)).into_owned();
+        libquickjs_ng_sys::JS_FreeCString(ctx, ptr);
+        Some(text)
+    }
+}
+
+unsafe fn throw_error(ctx: *mut JSContext, message: &str) -> JSValue {
+    unsafe {
+        let message = CString::new(message).unwrap_or_default();
+        libquickjs_ng_sys::JS_ThrowPlainError(ctx, c"%s".as_ptr(), message.as_ptr())
+    }
+}
+
+unsafe fn throw_type_error(ctx: *mut JSContext, message: &str) -> JSValue {
+    unsafe {
+        let message = CString::new(message).unwrap_or_default();
+        libquickjs_ng_sys::JS_ThrowTypeError(ctx, c"%s".as_ptr(), message.as_ptr())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bridge whose timers run on a manual clock, advanced with `advance`
+    fn manual_bridge() -> JSBridge {
+        let bridge = JSBridge::new();
+        bridge.state.lock().host.timers.borrow_mut().clock = Clock::Manual(Instant::now());
+        bridge
+    }
+
+    fn advance(bridge: &JSBridge, ms: u64) {
+        let state = bridge.state.lock();
+        let mut timers = state.host.timers.borrow_mut();
+        if let Clock::Manual(now) = &mut timers.clock {
+            *now += Duration::from_millis(ms);
+        }
+    }
+
+    #[test]
+    fn timers_fire_in_deadline_then_scheduling_order() {
+        let js = manual_bridge();
+        js.load_script_content(r#"
+            var log = [];
+            function get() { return log.join(","); }
+            setTimeout(() => log.push("a20"), 20);
+            setTimeout(() => log.push("b10"), 10);
+            setTimeout(() => { log.push("c10"); Promise.resolve().then(() => log.push("c-micro")); }, 10);
+            setTimeout(() => log.push("d0"), 0);
+            var n = 0;
+            var iv = setInterval(() => { log.push("i" + n); if (++n == 3) clearInterval(iv); }, 0);
+        "#, false).unwrap();
+        let log = || js.call_function("get", "").unwrap();
+
+        assert_eq!(js.poll().unwrap(), 4);
+        assert_eq!(log(), "d0,i0");
+        // A zero interval waits for the minimum period instead of firing again
+        assert_eq!(js.poll().unwrap(), 4);
+        assert_eq!(log(), "d0,i0");
+        assert_eq!(js.next_timer_delay(), Some(MIN_INTERVAL));
+
+        advance(&js, 10);
+        assert_eq!(js.poll().unwrap(), 2);
+        assert_eq!(log(), "d0,i0,i1,b10,c10,c-micro");
+
+        advance(&js, 10);
+        assert_eq!(js.poll().unwrap(), 0);
+        assert_eq!(log(), "d0,i0,i1,b10,c10,c-micro,i2,a20");
+    }
+
+    #[test]
+    fn cleared_timer_does_not_fire() {
+        let js = manual_bridge();
+        js.load_script_content(r#"
+            var log = [];
+            function get() { return log.join(","); }
+            setTimeout(() => { log.push("first"); clearTimeout(late); }, 5);
+            var late = setTimeout(() => log.push("late"), 5);
+        "#, false).unwrap();
+        assert_eq!(js.poll().unwrap(), 2);
+        advance(&js, 5);
+        assert_eq!(js.poll().unwrap(), 0);
+        assert_eq!(js.call_function("get", "").unwrap(), "first");
     }
 }
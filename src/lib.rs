@@ -1,8 +1,11 @@
 pub mod core {
+    pub mod error;
     pub mod crypto;
     pub mod db;
     pub mod kv;
     pub mod net;
+    pub mod http_cache;
+    pub mod cookie_jar;
     pub mod zip;
     pub mod lua;
     pub mod qjs;
@@ -10,6 +13,7 @@ pub mod core {
 
 pub mod c {
     pub mod util;
+    pub mod error;
     pub mod crypto;
     pub mod db;
     pub mod kv;